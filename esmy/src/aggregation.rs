@@ -0,0 +1,593 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use doc_iter::DocIter;
+use error::Error;
+use numeric_doc_values::NumericDocValuesReader;
+use search::Collector;
+use seg::SegmentReader;
+use sorted_doc_values::SortedDocValuesReader;
+use DocId;
+
+/// Summarizes the docs that match a query, like the aggregations of Elasticsearch. Docs are
+/// given one segment at a time, so that aggregators can count by values which only compare
+/// within a segment, such as ordinals, and merge the counts when the segment ends.
+pub trait Aggregator: AggregatorClone + Sync + Send {
+    /// Called before any docs of the segment are collected.
+    fn begin_segment(&mut self, reader: &SegmentReader) -> Result<(), Error>;
+    /// Adds a matching doc of the current segment.
+    fn collect(&mut self, doc_id: DocId) -> Result<(), Error>;
+    /// Called after all matching docs of the current segment have been collected.
+    fn end_segment(&mut self) -> Result<(), Error>;
+    fn result(&self) -> AggregationResult;
+}
+
+pub trait AggregatorClone {
+    fn clone_box(&self) -> Box<dyn Aggregator>;
+}
+
+impl<T> AggregatorClone for T
+where
+    T: 'static + Aggregator + Clone,
+{
+    fn clone_box(&self) -> Box<dyn Aggregator> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Aggregator> {
+    fn clone(&self) -> Box<dyn Aggregator> {
+        (**self).clone_box()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum AggregationResult {
+    Terms(TermsResult),
+    Histogram(HistogramResult),
+    Range(RangeResult),
+    Stats(StatsResult),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TermsResult {
+    pub buckets: Vec<TermsBucket>,
+    /// The summed counts of the values which did not make it into the buckets.
+    pub other_count: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TermsBucket {
+    pub key: String,
+    pub count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<Box<AggregationResult>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HistogramResult {
+    pub buckets: Vec<HistogramBucket>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HistogramBucket {
+    /// The smallest value of the bucket.
+    pub key: f64,
+    pub count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<Box<AggregationResult>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RangeResult {
+    pub buckets: Vec<RangeBucket>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RangeBucket {
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+    pub count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<Box<AggregationResult>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StatsResult {
+    pub count: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub sum: f64,
+    pub avg: Option<f64>,
+}
+
+/// Runs named aggregations over the docs that match a query.
+#[derive(Default)]
+pub struct AggregationCollector {
+    aggregators: Vec<(String, Box<dyn Aggregator>)>,
+    total_hits: u64,
+}
+
+impl AggregationCollector {
+    pub fn new() -> AggregationCollector {
+        AggregationCollector {
+            aggregators: Vec::new(),
+            total_hits: 0,
+        }
+    }
+
+    pub fn add<N: Into<String>>(mut self, name: N, aggregator: Box<dyn Aggregator>) -> Self {
+        self.aggregators.push((name.into(), aggregator));
+        self
+    }
+
+    pub fn total_hits(&self) -> u64 {
+        self.total_hits
+    }
+
+    pub fn result(&self, name: &str) -> Option<AggregationResult> {
+        self.aggregators
+            .iter()
+            .find(|(aggregator_name, _)| aggregator_name == name)
+            .map(|(_, aggregator)| aggregator.result())
+    }
+
+    pub fn results(&self) -> HashMap<String, AggregationResult> {
+        self.aggregators
+            .iter()
+            .map(|(name, aggregator)| (name.clone(), aggregator.result()))
+            .collect()
+    }
+}
+
+impl Collector for AggregationCollector {
+    fn collect_for(&mut self, reader: &SegmentReader, docs: &mut dyn DocIter) -> Result<(), Error> {
+        for (_, aggregator) in self.aggregators.iter_mut() {
+            aggregator.begin_segment(reader)?;
+        }
+        while let Some(doc_id) = docs.next_doc()? {
+            if reader.deleted_docs().get(doc_id as usize).unwrap_or(false) {
+                continue;
+            }
+            self.total_hits += 1;
+            for (_, aggregator) in self.aggregators.iter_mut() {
+                aggregator.collect(doc_id)?;
+            }
+        }
+        for (_, aggregator) in self.aggregators.iter_mut() {
+            aggregator.end_segment()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct Bucket {
+    count: u64,
+    sub: Option<Box<dyn Aggregator>>,
+}
+
+/// The buckets of a bucket aggregator, each with its own copy of the sub aggregator. The sub
+/// aggregator given at creation is kept as a template which takes part in every segment without
+/// collecting any docs, so that buckets created within a segment can start from a copy of it.
+#[derive(Clone)]
+struct Buckets<K: Ord> {
+    template: Option<Box<dyn Aggregator>>,
+    keys: BTreeMap<K, usize>,
+    buckets: Vec<Bucket>,
+}
+
+impl<K: Ord> Buckets<K> {
+    fn new(template: Option<Box<dyn Aggregator>>) -> Buckets<K> {
+        Buckets {
+            template,
+            keys: BTreeMap::new(),
+            buckets: Vec::new(),
+        }
+    }
+
+    /// The index of the bucket with the key, which is created if it does not exist.
+    fn bucket_index(&mut self, key: K) -> usize {
+        let Buckets {
+            ref template,
+            ref mut keys,
+            ref mut buckets,
+        } = *self;
+        *keys.entry(key).or_insert_with(|| {
+            buckets.push(Bucket {
+                count: 0,
+                sub: template.clone(),
+            });
+            buckets.len() - 1
+        })
+    }
+
+    fn collect(&mut self, index: usize, doc_id: DocId) -> Result<(), Error> {
+        let bucket = &mut self.buckets[index];
+        bucket.count += 1;
+        match bucket.sub {
+            Some(ref mut sub) => sub.collect(doc_id),
+            None => Ok(()),
+        }
+    }
+
+    fn begin_segment(&mut self, reader: &SegmentReader) -> Result<(), Error> {
+        for sub in self.subs_mut() {
+            sub.begin_segment(reader)?;
+        }
+        Ok(())
+    }
+
+    fn end_segment(&mut self) -> Result<(), Error> {
+        for sub in self.subs_mut() {
+            sub.end_segment()?;
+        }
+        Ok(())
+    }
+
+    fn subs_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Aggregator>> {
+        self.template.iter_mut().chain(
+            self.buckets
+                .iter_mut()
+                .flat_map(|bucket| bucket.sub.iter_mut()),
+        )
+    }
+
+    /// The keys with the counts and sub aggregation results of their buckets, in key order.
+    fn results(&self) -> impl Iterator<Item = (&K, u64, Option<Box<AggregationResult>>)> {
+        self.keys.iter().map(move |(key, &index)| {
+            let bucket = &self.buckets[index];
+            (
+                key,
+                bucket.count,
+                bucket.sub.as_ref().map(|sub| Box::new(sub.result())),
+            )
+        })
+    }
+}
+
+/// Counts the docs that have each value of a field, using a sorted doc values feature of the
+/// field. Values are only looked up once per segment for each ordinal that matching docs have.
+/// The result has the `size` values with the highest counts, with ties broken by the values.
+#[derive(Clone)]
+pub struct TermsAggregator {
+    field: String,
+    size: usize,
+    buckets: Buckets<String>,
+    segment_values: Option<SortedDocValuesReader>,
+    segment_buckets: Vec<Option<usize>>,
+}
+
+impl TermsAggregator {
+    pub fn new<F: Into<String>>(field: F, size: usize) -> TermsAggregator {
+        TermsAggregator {
+            field: field.into(),
+            size,
+            buckets: Buckets::new(None),
+            segment_values: None,
+            segment_buckets: Vec::new(),
+        }
+    }
+
+    /// Runs the sub aggregator on the docs of each bucket.
+    pub fn with_sub(mut self, sub: Box<dyn Aggregator>) -> TermsAggregator {
+        self.buckets = Buckets::new(Some(sub));
+        self
+    }
+}
+
+impl Aggregator for TermsAggregator {
+    fn begin_segment(&mut self, reader: &SegmentReader) -> Result<(), Error> {
+        self.buckets.begin_segment(reader)?;
+        self.segment_values = reader.sorted_doc_values(&self.field).cloned();
+        let term_count = self
+            .segment_values
+            .as_ref()
+            .map(|values| values.term_count())
+            .unwrap_or(0);
+        self.segment_buckets = vec![None; term_count as usize];
+        Ok(())
+    }
+
+    fn collect(&mut self, doc_id: DocId) -> Result<(), Error> {
+        if let Some(ref values) = self.segment_values {
+            let mut ords = values.ords(doc_id);
+            //Docs count once for each distinct value
+            ords.dedup();
+            for ord in ords {
+                let index = match self.segment_buckets[ord as usize] {
+                    Some(index) => index,
+                    None => {
+                        let index = self.buckets.bucket_index(values.term(ord).into_owned());
+                        self.segment_buckets[ord as usize] = Some(index);
+                        index
+                    }
+                };
+                self.buckets.collect(index, doc_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn end_segment(&mut self) -> Result<(), Error> {
+        self.segment_values = None;
+        self.segment_buckets = Vec::new();
+        self.buckets.end_segment()
+    }
+
+    fn result(&self) -> AggregationResult {
+        let mut buckets: Vec<TermsBucket> = self
+            .buckets
+            .results()
+            .map(|(key, count, sub)| TermsBucket {
+                key: key.clone(),
+                count,
+                sub,
+            })
+            .collect();
+        buckets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+        let other_count = buckets.iter().skip(self.size).map(|b| b.count).sum();
+        buckets.truncate(self.size);
+        AggregationResult::Terms(TermsResult {
+            buckets,
+            other_count,
+        })
+    }
+}
+
+/// Counts the docs with values in each interval of a numeric field, using a numeric doc values
+/// feature of the field. Intervals start at multiples of the interval size, and only intervals
+/// with docs get a bucket.
+#[derive(Clone)]
+pub struct HistogramAggregator {
+    field: String,
+    interval: f64,
+    buckets: Buckets<i64>,
+    segment_values: Option<NumericDocValuesReader>,
+}
+
+impl HistogramAggregator {
+    /// The interval must be a finite number above zero, or the buckets make no sense.
+    pub fn new<F: Into<String>>(field: F, interval: f64) -> HistogramAggregator {
+        HistogramAggregator {
+            field: field.into(),
+            interval,
+            buckets: Buckets::new(None),
+            segment_values: None,
+        }
+    }
+
+    /// Runs the sub aggregator on the docs of each bucket.
+    pub fn with_sub(mut self, sub: Box<dyn Aggregator>) -> HistogramAggregator {
+        self.buckets = Buckets::new(Some(sub));
+        self
+    }
+}
+
+impl Aggregator for HistogramAggregator {
+    fn begin_segment(&mut self, reader: &SegmentReader) -> Result<(), Error> {
+        self.segment_values = reader.numeric_doc_values(&self.field).cloned();
+        self.buckets.begin_segment(reader)
+    }
+
+    fn collect(&mut self, doc_id: DocId) -> Result<(), Error> {
+        let mut keys: Vec<i64> = match self.segment_values {
+            Some(ref values) => values
+                .f64_values(doc_id)
+                .into_iter()
+                .map(|value| (value / self.interval).floor() as i64)
+                .collect(),
+            None => return Ok(()),
+        };
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let index = self.buckets.bucket_index(key);
+            self.buckets.collect(index, doc_id)?;
+        }
+        Ok(())
+    }
+
+    fn end_segment(&mut self) -> Result<(), Error> {
+        self.segment_values = None;
+        self.buckets.end_segment()
+    }
+
+    fn result(&self) -> AggregationResult {
+        AggregationResult::Histogram(HistogramResult {
+            buckets: self
+                .buckets
+                .results()
+                .map(|(&key, count, sub)| HistogramBucket {
+                    key: key as f64 * self.interval,
+                    count,
+                    sub,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Counts the docs with values in each of a number of ranges of a numeric field, using a numeric
+/// doc values feature of the field. Ranges include their `from` value but not their `to` value,
+/// and may overlap. All ranges get a bucket, in the order they were added.
+#[derive(Clone)]
+pub struct RangeAggregator {
+    field: String,
+    ranges: Vec<(Option<f64>, Option<f64>)>,
+    sub: Option<Box<dyn Aggregator>>,
+    buckets: Buckets<usize>,
+    segment_values: Option<NumericDocValuesReader>,
+}
+
+impl RangeAggregator {
+    pub fn new<F: Into<String>>(field: F) -> RangeAggregator {
+        RangeAggregator {
+            field: field.into(),
+            ranges: Vec::new(),
+            sub: None,
+            buckets: Buckets::new(None),
+            segment_values: None,
+        }
+    }
+
+    /// Adds a range, where a missing bound means that the range is unbounded in that direction.
+    pub fn add_range(mut self, from: Option<f64>, to: Option<f64>) -> RangeAggregator {
+        self.ranges.push((from, to));
+        self.reset_buckets();
+        self
+    }
+
+    /// Runs the sub aggregator on the docs of each bucket.
+    pub fn with_sub(mut self, sub: Box<dyn Aggregator>) -> RangeAggregator {
+        self.sub = Some(sub);
+        self.reset_buckets();
+        self
+    }
+
+    fn reset_buckets(&mut self) {
+        self.buckets = Buckets::new(self.sub.clone());
+        for index in 0..self.ranges.len() {
+            self.buckets.bucket_index(index);
+        }
+    }
+}
+
+impl Aggregator for RangeAggregator {
+    fn begin_segment(&mut self, reader: &SegmentReader) -> Result<(), Error> {
+        self.segment_values = reader.numeric_doc_values(&self.field).cloned();
+        self.buckets.begin_segment(reader)
+    }
+
+    fn collect(&mut self, doc_id: DocId) -> Result<(), Error> {
+        let values = match self.segment_values {
+            Some(ref values) => values.f64_values(doc_id),
+            None => return Ok(()),
+        };
+        for (index, &(from, to)) in self.ranges.iter().enumerate() {
+            let in_range = |value: &f64| {
+                from.map(|from| *value >= from).unwrap_or(true)
+                    && to.map(|to| *value < to).unwrap_or(true)
+            };
+            if values.iter().any(in_range) {
+                self.buckets.collect(index, doc_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn end_segment(&mut self) -> Result<(), Error> {
+        self.segment_values = None;
+        self.buckets.end_segment()
+    }
+
+    fn result(&self) -> AggregationResult {
+        AggregationResult::Range(RangeResult {
+            buckets: self
+                .buckets
+                .results()
+                .map(|(&index, count, sub)| RangeBucket {
+                    from: self.ranges[index].0,
+                    to: self.ranges[index].1,
+                    count,
+                    sub,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Computes the count, min, max, sum and average of the values that docs have in a numeric
+/// field, using a numeric doc values feature of the field.
+#[derive(Clone)]
+pub struct StatsAggregator {
+    field: String,
+    count: u64,
+    min: Option<f64>,
+    max: Option<f64>,
+    sum: f64,
+    segment_values: Option<NumericDocValuesReader>,
+}
+
+impl StatsAggregator {
+    pub fn new<F: Into<String>>(field: F) -> StatsAggregator {
+        StatsAggregator {
+            field: field.into(),
+            count: 0,
+            min: None,
+            max: None,
+            sum: 0.0,
+            segment_values: None,
+        }
+    }
+}
+
+impl Aggregator for StatsAggregator {
+    fn begin_segment(&mut self, reader: &SegmentReader) -> Result<(), Error> {
+        self.segment_values = reader.numeric_doc_values(&self.field).cloned();
+        Ok(())
+    }
+
+    fn collect(&mut self, doc_id: DocId) -> Result<(), Error> {
+        if let Some(ref values) = self.segment_values {
+            for value in values.f64_values(doc_id) {
+                self.count += 1;
+                self.sum += value;
+                self.min = Some(self.min.map_or(value, |min| min.min(value)));
+                self.max = Some(self.max.map_or(value, |max| max.max(value)));
+            }
+        }
+        Ok(())
+    }
+
+    fn end_segment(&mut self) -> Result<(), Error> {
+        self.segment_values = None;
+        Ok(())
+    }
+
+    fn result(&self) -> AggregationResult {
+        AggregationResult::Stats(StatsResult {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            sum: self.sum,
+            avg: if self.count > 0 {
+                Some(self.sum / self.count as f64)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::AggregationResult;
+    use super::HistogramBucket;
+    use super::HistogramResult;
+    use super::StatsResult;
+    use serde_json;
+
+    #[test]
+    fn serializes_nested_results_to_json() {
+        let result = AggregationResult::Histogram(HistogramResult {
+            buckets: vec![HistogramBucket {
+                key: 10.0,
+                count: 2,
+                sub: Some(Box::new(AggregationResult::Stats(StatsResult {
+                    count: 2,
+                    min: Some(10.0),
+                    max: Some(12.5),
+                    sum: 22.5,
+                    avg: Some(11.25),
+                }))),
+            }],
+        });
+        assert_eq!(
+            r#"{"buckets":[{"key":10.0,"count":2,"sub":{"count":2,"min":10.0,"max":12.5,"sum":22.5,"avg":11.25}}]}"#,
+            serde_json::to_string(&result).unwrap()
+        );
+    }
+}
@@ -1,20 +1,199 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fmt::Debug;
 use std::iter;
+use std::sync::Arc;
 use unicode_segmentation::UnicodeSegmentation;
 
+use doc::FieldValue;
+use error::Error;
+use seg::FeatureConfig;
+use stemming::Language;
+use stop_words;
+
+/// A token with the byte offsets of the text it was produced from. The position increment is
+/// the number of positions from the token before it, which is more than 1 where tokens were
+/// removed and 0 for a token at the same position as the one before.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token<'a> {
+    pub text: Cow<'a, str>,
+    pub start: usize,
+    pub end: usize,
+    pub position_increment: u32,
+}
+
+impl<'a> Token<'a> {
+    pub fn new<T>(text: T, start: usize, end: usize) -> Token<'a>
+    where
+        T: Into<Cow<'a, str>>,
+    {
+        Token {
+            text: text.into(),
+            start,
+            end,
+            position_increment: 1,
+        }
+    }
+
+    pub fn into_owned(self) -> Token<'static> {
+        Token {
+            text: Cow::Owned(self.text.into_owned()),
+            start: self.start,
+            end: self.end,
+            position_increment: self.position_increment,
+        }
+    }
+}
+
 pub trait Analyzer: AnalyzerClone + Send + Sync + Debug {
     fn analyzer_type(&self) -> &'static str;
+
     fn analyze<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a>;
+
+    /// Analyzes the value like `analyze`, but also tells where in the value each token is. By
+    /// default, the offsets are found by searching the value for each token, and a token which
+    /// is not in the value as it is, such as a lowercased one, gets an empty range, which is
+    /// not highlighted.
+    fn tokens<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Token<'a>> + 'a> {
+        Box::new(self.analyze(value).scan(0, move |next_start, text| {
+            let start = match value[*next_start..].find(text.as_ref()) {
+                Some(index) => *next_start + index,
+                None => {
+                    let start = *next_start;
+                    return Some(Token::new(text, start, start));
+                }
+            };
+            *next_start = start + text.len();
+            Some(Token::new(text, start, *next_start))
+        }))
+    }
+
+    /// Analyzes the text representation of a field value. The values of an array are analyzed
+    /// one by one, and the offsets of their tokens are within each value.
+    fn value_tokens<'a>(&self, value: &'a FieldValue) -> Box<dyn Iterator<Item = Token<'a>> + 'a> {
+        match *value {
+            FieldValue::String(ref value) => self.tokens(value),
+            FieldValue::Array(ref values) => {
+                let tokens: Vec<Token<'a>> =
+                    values.iter().flat_map(|v| self.value_tokens(v)).collect();
+                Box::new(tokens.into_iter())
+            }
+            _ => {
+                let tokens: Vec<Token<'a>> = self
+                    .tokens(&value.to_text())
+                    .map(|token| token.into_owned())
+                    .collect();
+                Box::new(tokens.into_iter())
+            }
+        }
+    }
+
+    /// The text of the tokens of a field value only.
+    fn analyze_value<'a>(
+        &self,
+        value: &'a FieldValue,
+    ) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        Box::new(self.value_tokens(value).map(|token| token.text))
+    }
+
+    /// How the analyzer is stored in the config of the features using it. Analyzers with the
+    /// same config produce the same tokens.
+    fn to_config(&self) -> FeatureConfig {
+        FeatureConfig::String(self.analyzer_type().to_string())
+    }
+
+    /// Whether the analyzers produce the same tokens, so that a feature written with one can be
+    /// searched with the other.
+    fn same_as(&self, other: &dyn Analyzer) -> bool {
+        self.to_config() == other.to_config()
+    }
 }
 
-impl dyn Analyzer {
-    pub fn for_name(name: &str) -> Box<dyn Analyzer> {
-        match name {
-            "uax29" => Box::new(UAX29Analyzer),
-            "whitespace" => Box::new(WhiteSpaceAnalyzer),
-            "noop" => Box::new(NoopAnalyzer),
-            _ => panic!("No such analyzer"),
+type AnalyzerFactory = dyn Fn(&FeatureConfig) -> Result<Box<dyn Analyzer>, Error> + Send + Sync;
+
+/// Creates analyzers by name, so that features get their analyzers back when a segment is
+/// opened or merged. An application can register its own analyzers, under the names that their
+/// `analyzer_type` returns.
+#[derive(Clone)]
+pub struct AnalyzerRegistry {
+    factories: HashMap<String, Arc<AnalyzerFactory>>,
+}
+
+impl Default for AnalyzerRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnalyzerRegistry {
+    /// A registry with the analyzers of this crate.
+    pub fn new() -> AnalyzerRegistry {
+        AnalyzerRegistry {
+            factories: HashMap::new(),
+        }
+        .register("uax29", |_| Ok(Box::new(UAX29Analyzer)))
+        .register("whitespace", |_| Ok(Box::new(WhiteSpaceAnalyzer)))
+        .register("noop", |_| Ok(Box::new(NoopAnalyzer)))
+        .register("custom", |config| {
+            Ok(Box::new(CustomAnalyzer::from_config(config)?))
+        })
+        .register("english", |_| {
+            Ok(CustomAnalyzer::stemming(Language::English).boxed())
+        })
+        .register("german", |_| {
+            Ok(CustomAnalyzer::stemming(Language::German).boxed())
+        })
+        .register("swedish", |_| {
+            Ok(CustomAnalyzer::stemming(Language::Swedish).boxed())
+        })
+        .register("stop_english", |_| {
+            Ok(CustomAnalyzer::without_stop_words(Language::English).boxed())
+        })
+        .register("stop_german", |_| {
+            Ok(CustomAnalyzer::without_stop_words(Language::German).boxed())
+        })
+        .register("stop_swedish", |_| {
+            Ok(CustomAnalyzer::without_stop_words(Language::Swedish).boxed())
+        })
+    }
+
+    /// Registers the factory of an analyzer, replacing any factory with the same name. The
+    /// factory gets the config that `Analyzer::to_config` returned, or `FeatureConfig::None` if
+    /// the analyzer is created from its name only.
+    pub fn register<N, F>(mut self, name: N, factory: F) -> AnalyzerRegistry
+    where
+        N: Into<String>,
+        F: Fn(&FeatureConfig) -> Result<Box<dyn Analyzer>, Error> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    pub fn create(&self, name: &str) -> Result<Box<dyn Analyzer>, Error> {
+        self.create_with_config(name, &FeatureConfig::None)
+    }
+
+    /// Creates an analyzer from a config that `Analyzer::to_config` returned. A config which is
+    /// not just a name needs the name at `type`.
+    pub fn from_config(&self, config: &FeatureConfig) -> Result<Box<dyn Analyzer>, Error> {
+        match *config {
+            FeatureConfig::String(ref name) => self.create(name),
+            _ => match config.str_at("type") {
+                Some(name) => self.create_with_config(name, config),
+                None => Err(invalid_config("Analyzer config without type", config)),
+            },
+        }
+    }
+
+    fn create_with_config(
+        &self,
+        name: &str,
+        config: &FeatureConfig,
+    ) -> Result<Box<dyn Analyzer>, Error> {
+        match self.factories.get(name) {
+            Some(factory) => factory(config),
+            None => Err(Error::InvalidConfig(format!("No such analyzer: {}", name))),
         }
     }
 }
@@ -56,19 +235,12 @@ impl Analyzer for UAX29Analyzer {
         "uax29"
     }
 
+    fn tokens<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Token<'a>> + 'a> {
+        TokenFilter::Lowercase.filter(Tokenizer::UAX29.tokens(value))
+    }
+
     fn analyze<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
-        Box::from(
-            value
-                .split_word_bounds()
-                .filter(|token| !is_only_whitespace_or_control_char(token))
-                .map(|token| {
-                    if token.find(char::is_uppercase).is_some() {
-                        Cow::Owned(token.to_lowercase())
-                    } else {
-                        Cow::Borrowed(token)
-                    }
-                }),
-        )
+        Box::new(self.tokens(value).map(|token| token.text))
     }
 }
 
@@ -99,8 +271,12 @@ impl Analyzer for WhiteSpaceAnalyzer {
         "whitespace"
     }
 
+    fn tokens<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Token<'a>> + 'a> {
+        Tokenizer::WhiteSpace.tokens(value)
+    }
+
     fn analyze<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
-        Box::from(value.split_whitespace().map(|s| Cow::Borrowed(s)))
+        Box::new(self.tokens(value).map(|token| token.text))
     }
 }
 
@@ -122,7 +298,597 @@ impl Analyzer for NoopAnalyzer {
         "noop"
     }
 
+    fn tokens<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Token<'a>> + 'a> {
+        Tokenizer::Keyword.tokens(value)
+    }
+
     fn analyze<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
-        Box::from(iter::once(Cow::Borrowed(value)))
+        Box::new(self.tokens(value).map(|token| token.text))
+    }
+}
+
+/// An analyzer put together from a tokenizer and a chain of token filters, which are applied in
+/// the order they are added. The whole chain is stored in the config of the features using it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomAnalyzer {
+    tokenizer: Tokenizer,
+    filters: Vec<TokenFilter>,
+}
+
+impl CustomAnalyzer {
+    pub fn new(tokenizer: Tokenizer) -> CustomAnalyzer {
+        CustomAnalyzer {
+            tokenizer,
+            filters: Vec::new(),
+        }
+    }
+
+    /// Splits like `UAX29Analyzer` and stems the lowercased tokens. The registry creates these
+    /// for the names of the languages.
+    pub fn stemming(language: Language) -> CustomAnalyzer {
+        CustomAnalyzer::new(Tokenizer::UAX29)
+            .add_filter(TokenFilter::Lowercase)
+            .add_filter(TokenFilter::Stemmer(language))
+    }
+
+    /// Splits like `UAX29Analyzer` and removes the stop words from the lowercased tokens. The
+    /// registry creates these for the names of the languages prefixed with "stop_".
+    pub fn without_stop_words(language: Language) -> CustomAnalyzer {
+        CustomAnalyzer::new(Tokenizer::UAX29)
+            .add_filter(TokenFilter::Lowercase)
+            .add_filter(TokenFilter::StopWords(language))
+    }
+
+    pub fn add_filter(mut self, filter: TokenFilter) -> CustomAnalyzer {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn boxed(self) -> Box<CustomAnalyzer> {
+        Box::new(self)
+    }
+
+    fn from_config(config: &FeatureConfig) -> Result<CustomAnalyzer, Error> {
+        let tokenizer = match config.str_at("tokenizer") {
+            Some(name) => Tokenizer::for_name(name)?,
+            None => return Err(invalid_config("Custom analyzer without tokenizer", config)),
+        };
+        let filters = match config.at("filters") {
+            Some(FeatureConfig::List(filters)) => filters
+                .iter()
+                .map(TokenFilter::from_config)
+                .collect::<Result<Vec<TokenFilter>, Error>>()?,
+            _ => Vec::new(),
+        };
+        Ok(CustomAnalyzer { tokenizer, filters })
+    }
+}
+
+impl Analyzer for CustomAnalyzer {
+    fn analyzer_type(&self) -> &'static str {
+        "custom"
+    }
+
+    fn tokens<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Token<'a>> + 'a> {
+        self.filters
+            .iter()
+            .fold(self.tokenizer.tokens(value), |tokens, filter| {
+                filter.filter(tokens)
+            })
+    }
+
+    fn analyze<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        Box::new(self.tokens(value).map(|token| token.text))
+    }
+
+    fn to_config(&self) -> FeatureConfig {
+        let mut map = HashMap::new();
+        map.insert(
+            "type".to_string(),
+            FeatureConfig::String(self.analyzer_type().to_string()),
+        );
+        map.insert(
+            "tokenizer".to_string(),
+            FeatureConfig::String(self.tokenizer.name().to_string()),
+        );
+        map.insert(
+            "filters".to_string(),
+            FeatureConfig::List(self.filters.iter().map(|f| f.to_config()).collect()),
+        );
+        FeatureConfig::Map(map)
+    }
+}
+
+/// Splits text into the tokens that the filters of a `CustomAnalyzer` work on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tokenizer {
+    /// Splits on the word boundaries of Unicode Standard Annex #29, leaving out whitespace.
+    UAX29,
+    WhiteSpace,
+    /// Keeps the whole text as one token.
+    Keyword,
+}
+
+impl Tokenizer {
+    fn name(&self) -> &'static str {
+        match *self {
+            Tokenizer::UAX29 => "uax29",
+            Tokenizer::WhiteSpace => "whitespace",
+            Tokenizer::Keyword => "keyword",
+        }
+    }
+
+    fn for_name(name: &str) -> Result<Tokenizer, Error> {
+        match name {
+            "uax29" => Ok(Tokenizer::UAX29),
+            "whitespace" => Ok(Tokenizer::WhiteSpace),
+            "keyword" => Ok(Tokenizer::Keyword),
+            _ => Err(Error::InvalidConfig(format!("No such tokenizer: {}", name))),
+        }
+    }
+
+    pub fn tokens<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Token<'a>> + 'a> {
+        match *self {
+            Tokenizer::UAX29 => Box::new(
+                value
+                    .split_word_bound_indices()
+                    .filter(|&(_, token)| !is_only_whitespace_or_control_char(token))
+                    .map(|(start, token)| Token::new(token, start, start + token.len())),
+            ),
+            Tokenizer::WhiteSpace => {
+                let value_start = value.as_ptr() as usize;
+                Box::new(value.split_whitespace().map(move |s| {
+                    let start = s.as_ptr() as usize - value_start;
+                    Token::new(s, start, start + s.len())
+                }))
+            }
+            Tokenizer::Keyword => Box::new(iter::once(Token::new(value, 0, value.len()))),
+        }
+    }
+}
+
+/// Changes or removes the tokens of a `CustomAnalyzer`. Filters that remove tokens leave a gap
+/// in the positions, so that phrases still only match the original text.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenFilter {
+    Lowercase,
+    /// Replaces letters with diacritics and ligatures of the Latin alphabet with the ASCII
+    /// letters they are based on.
+    AsciiFolding,
+    /// Removes the tokens with fewer than `min` or more than `max` characters.
+    Length {
+        min: usize,
+        max: usize,
+    },
+    /// Replaces the tokens with their stems, so that different forms of a word match each
+    /// other. Should come after `Lowercase`.
+    Stemmer(Language),
+    /// Removes the common words of a language, from the lists in `stop_words`. Should come
+    /// after `Lowercase`.
+    StopWords(Language),
+    CustomStopWords(Vec<String>),
+}
+
+impl TokenFilter {
+    pub fn filter<'a>(
+        &self,
+        tokens: Box<dyn Iterator<Item = Token<'a>> + 'a>,
+    ) -> Box<dyn Iterator<Item = Token<'a>> + 'a> {
+        match *self {
+            TokenFilter::Lowercase => map_text(tokens, |text| {
+                if text.find(char::is_uppercase).is_some() {
+                    Some(text.to_lowercase())
+                } else {
+                    None
+                }
+            }),
+            TokenFilter::AsciiFolding => map_text(tokens, fold_to_ascii),
+            TokenFilter::Length { min, max } => retain_tokens(tokens, move |token| {
+                let length = token.text.chars().count();
+                min <= length && length <= max
+            }),
+            TokenFilter::Stemmer(language) => {
+                map_text(tokens, move |text| match language.stem(text) {
+                    Cow::Owned(stem) => Some(stem),
+                    Cow::Borrowed(_) => None,
+                })
+            }
+            TokenFilter::StopWords(language) => {
+                let words = stop_words::for_language(language);
+                retain_tokens(tokens, move |token| {
+                    words.binary_search(&token.text.as_ref()).is_err()
+                })
+            }
+            TokenFilter::CustomStopWords(ref words) => {
+                let words: HashSet<String> = words.iter().cloned().collect();
+                retain_tokens(tokens, move |token| !words.contains(token.text.as_ref()))
+            }
+        }
+    }
+
+    fn to_config(&self) -> FeatureConfig {
+        let mut map = HashMap::new();
+        let filter_type = match *self {
+            TokenFilter::Lowercase => "lowercase",
+            TokenFilter::AsciiFolding => "ascii_folding",
+            TokenFilter::Length { min, max } => {
+                map.insert("min".to_string(), FeatureConfig::Int(min as i64));
+                map.insert("max".to_string(), FeatureConfig::Int(max as i64));
+                "length"
+            }
+            TokenFilter::Stemmer(language) => {
+                map.insert(
+                    "language".to_string(),
+                    FeatureConfig::String(language.name().to_string()),
+                );
+                "stemmer"
+            }
+            TokenFilter::StopWords(language) => {
+                map.insert(
+                    "language".to_string(),
+                    FeatureConfig::String(language.name().to_string()),
+                );
+                "stop"
+            }
+            TokenFilter::CustomStopWords(ref words) => {
+                map.insert(
+                    "words".to_string(),
+                    FeatureConfig::List(
+                        words
+                            .iter()
+                            .map(|word| FeatureConfig::String(word.clone()))
+                            .collect(),
+                    ),
+                );
+                "stop"
+            }
+        };
+        map.insert(
+            "type".to_string(),
+            FeatureConfig::String(filter_type.to_string()),
+        );
+        FeatureConfig::Map(map)
+    }
+
+    fn from_config(config: &FeatureConfig) -> Result<TokenFilter, Error> {
+        match config.str_at("type") {
+            Some("lowercase") => Ok(TokenFilter::Lowercase),
+            Some("ascii_folding") => Ok(TokenFilter::AsciiFolding),
+            Some("length") => match (config.int_at("min"), config.int_at("max")) {
+                (Some(min), Some(max)) => Ok(TokenFilter::Length {
+                    min: min as usize,
+                    max: max as usize,
+                }),
+                _ => Err(invalid_config("Length filter without min and max", config)),
+            },
+            Some("stemmer") => match config.str_at("language").and_then(Language::for_name) {
+                Some(language) => Ok(TokenFilter::Stemmer(language)),
+                None => Err(invalid_config("Stemmer without known language", config)),
+            },
+            Some("stop") => match (config.str_at("language"), config.at("words")) {
+                (Some(name), _) => match Language::for_name(name) {
+                    Some(language) => Ok(TokenFilter::StopWords(language)),
+                    None => Err(invalid_config("No stop words for language", config)),
+                },
+                (None, Some(FeatureConfig::List(words))) => words
+                    .iter()
+                    .map(|word| match *word {
+                        FeatureConfig::String(ref word) => Ok(word.clone()),
+                        _ => Err(invalid_config("Stop word which is not a string", config)),
+                    })
+                    .collect::<Result<Vec<String>, Error>>()
+                    .map(TokenFilter::CustomStopWords),
+                _ => Err(invalid_config(
+                    "Stop filter without language or words",
+                    config,
+                )),
+            },
+            _ => Err(invalid_config("No such token filter", config)),
+        }
+    }
+}
+
+fn invalid_config(message: &str, config: &FeatureConfig) -> Error {
+    Error::InvalidConfig(format!("{}: {:?}", message, config))
+}
+
+/// Replaces the text of the tokens for which `map` returns a new text.
+fn map_text<'a, F>(
+    tokens: Box<dyn Iterator<Item = Token<'a>> + 'a>,
+    map: F,
+) -> Box<dyn Iterator<Item = Token<'a>> + 'a>
+where
+    F: Fn(&str) -> Option<String> + 'a,
+{
+    Box::new(tokens.map(move |mut token| {
+        if let Some(text) = map(&token.text) {
+            token.text = Cow::Owned(text);
+        }
+        token
+    }))
+}
+
+/// Removes the tokens which are not kept, and adds their position increments to the next kept
+/// token.
+fn retain_tokens<'a, F>(
+    tokens: Box<dyn Iterator<Item = Token<'a>> + 'a>,
+    keep: F,
+) -> Box<dyn Iterator<Item = Token<'a>> + 'a>
+where
+    F: Fn(&Token) -> bool + 'a,
+{
+    let mut removed = 0;
+    Box::new(tokens.filter_map(move |mut token| {
+        if keep(&token) {
+            token.position_increment += removed;
+            removed = 0;
+            Some(token)
+        } else {
+            removed += token.position_increment;
+            None
+        }
+    }))
+}
+
+fn fold_to_ascii(text: &str) -> Option<String> {
+    if text.is_ascii() {
+        return None;
+    }
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        match ascii_for(c) {
+            Some(ascii) => folded.push_str(ascii),
+            None => folded.push(c),
+        }
+    }
+    Some(folded)
+}
+
+fn ascii_for(c: char) -> Option<&'static str> {
+    let ascii = match c {
+        'À'..='Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'Ð' | 'Ď' | 'Đ' => "D",
+        'ð' | 'ď' | 'đ' => "d",
+        'È'..='Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'Ĥ' | 'Ħ' => "H",
+        'ĥ' | 'ħ' => "h",
+        'Ì'..='Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I",
+        'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'Ĳ' => "IJ",
+        'ĳ' => "ij",
+        'Ĵ' => "J",
+        'ĵ' => "j",
+        'Ķ' => "K",
+        'ķ' | 'ĸ' => "k",
+        'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "L",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'Ñ' | 'Ń' | 'Ņ' | 'Ň' | 'Ŋ' => "N",
+        'ñ' | 'ń' | 'ņ' | 'ň' | 'ŉ' | 'ŋ' => "n",
+        'Ò'..='Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => "O",
+        'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ŕ' | 'Ŗ' | 'Ř' => "R",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'Ś' | 'Ŝ' | 'Ş' | 'Š' => "S",
+        'ś' | 'ŝ' | 'ş' | 'š' | 'ſ' => "s",
+        'ß' => "ss",
+        'Ţ' | 'Ť' | 'Ŧ' => "T",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'Þ' => "TH",
+        'þ' => "th",
+        'Ù'..='Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U",
+        'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'Ŵ' => "W",
+        'ŵ' => "w",
+        'Ý' | 'Ŷ' | 'Ÿ' => "Y",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    };
+    Some(ascii)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Analyzer;
+    use super::AnalyzerRegistry;
+    use super::CustomAnalyzer;
+    use super::Token;
+    use super::TokenFilter;
+    use super::Tokenizer;
+    use super::UAX29Analyzer;
+    use super::WhiteSpaceAnalyzer;
+    use rmps;
+    use seg::schema_from_metas;
+    use seg::schema_to_feature_metas;
+    use seg::FeatureMeta;
+    use seg::FeatureRegistry;
+    use seg::SegmentSchemaBuilder;
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+    use stemming::Language;
+    use string_index::StringIndex;
+
+    fn analyzer() -> CustomAnalyzer {
+        CustomAnalyzer::new(Tokenizer::UAX29)
+            .add_filter(TokenFilter::Lowercase)
+            .add_filter(TokenFilter::AsciiFolding)
+            .add_filter(TokenFilter::Length { min: 2, max: 10 })
+    }
+
+    #[test]
+    fn filters_tokens_in_order() {
+        let tokens: Vec<(String, usize, usize, u32)> = analyzer()
+            .tokens("Über die Brücke, a Straße")
+            .map(|t| (t.text.into_owned(), t.start, t.end, t.position_increment))
+            .collect();
+        assert_eq!(
+            vec![
+                ("uber".to_string(), 0, 5, 1),
+                ("die".to_string(), 6, 9, 1),
+                ("brucke".to_string(), 10, 17, 1),
+                ("strasse".to_string(), 21, 28, 3),
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn stores_analysis_in_feature_config() {
+        let schema = SegmentSchemaBuilder::new()
+            .add_string_index("text_index", "text", analyzer().boxed())
+            .build();
+        let data = rmps::to_vec(&schema_to_feature_metas(&schema)).unwrap();
+        let metas: HashMap<String, FeatureMeta> = rmps::from_slice(&data).unwrap();
+        let schema =
+            schema_from_metas(metas, &FeatureRegistry::new(), &AnalyzerRegistry::new()).unwrap();
+        let feature = schema.features["text_index"]
+            .as_any()
+            .downcast_ref::<StringIndex>()
+            .unwrap();
+        assert!(feature.analyzer.same_as(&analyzer()));
+        assert!(!feature
+            .analyzer
+            .same_as(&analyzer().add_filter(TokenFilter::AsciiFolding)));
+        let tokens: Vec<String> = feature
+            .analyzer
+            .analyze("Ærø Ålesund")
+            .map(|t| t.into_owned())
+            .collect();
+        assert_eq!(vec!["aero".to_string(), "alesund".to_string()], tokens);
+    }
+    #[derive(Clone, Debug)]
+    struct ShoutingAnalyzer;
+
+    impl Analyzer for ShoutingAnalyzer {
+        fn analyzer_type(&self) -> &'static str {
+            "shouting"
+        }
+
+        fn tokens<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Token<'a>> + 'a> {
+            Box::new(WhiteSpaceAnalyzer.tokens(value).map(|token| {
+                let text = token.text.to_uppercase();
+                Token {
+                    text: Cow::Owned(text),
+                    ..token
+                }
+            }))
+        }
+
+        fn analyze<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+            Box::new(self.tokens(value).map(|token| token.text))
+        }
+    }
+
+    #[derive(Clone, Debug)]
+    struct TextOnlyAnalyzer;
+
+    impl Analyzer for TextOnlyAnalyzer {
+        fn analyzer_type(&self) -> &'static str {
+            "text_only"
+        }
+
+        fn analyze<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+            Box::new(value.split_whitespace().map(|s| match s {
+                "Shout" => Cow::Owned(s.to_uppercase()),
+                _ => Cow::Borrowed(s),
+            }))
+        }
+    }
+
+    #[test]
+    fn finds_offsets_for_analyzers_without_tokens() {
+        let tokens: Vec<(String, usize, usize)> = TextOnlyAnalyzer
+            .tokens("a b  Shout a")
+            .map(|t| (t.text.into_owned(), t.start, t.end))
+            .collect();
+        assert_eq!(
+            vec![
+                ("a".to_string(), 0, 1),
+                ("b".to_string(), 2, 3),
+                ("SHOUT".to_string(), 3, 3),
+                ("a".to_string(), 11, 12),
+            ],
+            tokens
+        );
+    }
+
+    #[test]
+    fn creates_registered_analyzers() {
+        let schema = SegmentSchemaBuilder::new()
+            .add_string_index("text_index", "text", Box::new(ShoutingAnalyzer))
+            .build();
+        let data = rmps::to_vec(&schema_to_feature_metas(&schema)).unwrap();
+        let metas: HashMap<String, FeatureMeta> = rmps::from_slice(&data).unwrap();
+        assert!(
+            schema_from_metas(metas, &FeatureRegistry::new(), &AnalyzerRegistry::new()).is_err()
+        );
+
+        let registry =
+            AnalyzerRegistry::new().register("shouting", |_| Ok(Box::new(ShoutingAnalyzer)));
+        let metas: HashMap<String, FeatureMeta> = rmps::from_slice(&data).unwrap();
+        let schema = schema_from_metas(metas, &FeatureRegistry::new(), &registry).unwrap();
+        let feature = schema.features["text_index"]
+            .as_any()
+            .downcast_ref::<StringIndex>()
+            .unwrap();
+        assert!(feature.analyzer.same_as(&ShoutingAnalyzer));
+        assert!(registry.create("uax29").unwrap().same_as(&UAX29Analyzer));
+        assert!(registry.create("nope").is_err());
+    }
+    #[test]
+    fn stems_with_language_analyzers() {
+        let registry = AnalyzerRegistry::new();
+        let analyzer = registry.create("english").unwrap();
+        let tokens: Vec<String> = analyzer
+            .analyze("Running runs ran")
+            .map(|t| t.into_owned())
+            .collect();
+        assert_eq!(vec!["run", "run", "ran"], tokens);
+        assert!(analyzer.same_as(&CustomAnalyzer::stemming(Language::English)));
+        assert!(!analyzer.same_as(&CustomAnalyzer::stemming(Language::German)));
+        assert!(registry
+            .from_config(&analyzer.to_config())
+            .unwrap()
+            .same_as(&*analyzer));
+    }
+
+    #[test]
+    fn removes_stop_words_leaving_gaps() {
+        let registry = AnalyzerRegistry::new();
+        let analyzer = registry.create("stop_english").unwrap();
+        let tokens: Vec<(String, u32)> = analyzer
+            .tokens("The Tower of London")
+            .map(|t| (t.text.into_owned(), t.position_increment))
+            .collect();
+        assert_eq!(
+            vec![("tower".to_string(), 2), ("london".to_string(), 2)],
+            tokens
+        );
+        assert!(analyzer.same_as(&CustomAnalyzer::without_stop_words(Language::English)));
+
+        let custom = CustomAnalyzer::new(Tokenizer::WhiteSpace).add_filter(
+            TokenFilter::CustomStopWords(vec!["tower".to_string(), "of".to_string()]),
+        );
+        let tokens: Vec<String> = custom
+            .analyze("tower of london")
+            .map(|t| t.into_owned())
+            .collect();
+        assert_eq!(vec!["london"], tokens);
+        assert!(registry
+            .from_config(&custom.to_config())
+            .unwrap()
+            .same_as(&custom));
     }
 }
@@ -0,0 +1,124 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Cursor;
+use std::io::Write;
+use std::path::Path;
+
+use byteorder::BigEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use memmap::Mmap;
+
+use error::Error;
+use util::bits_required;
+use util::read_packed;
+use util::write_packed;
+use DocId;
+
+const HEADER_LENGTH: usize = 8 + 8 + 8 + 1 + 1 + 8;
+
+/// Writes the offsets of the values of each doc followed by the values themselves, both
+/// bit-packed. The values are stored as the difference from the smallest value. Nothing is
+/// written if no doc has a value.
+pub fn write_column<I>(path: &Path, doc_values: I) -> Result<(), Error>
+where
+    I: Iterator<Item = Vec<i64>>,
+{
+    let mut offsets = vec![0u64];
+    let mut values = Vec::new();
+    for doc in doc_values {
+        values.extend(doc);
+        offsets.push(values.len() as u64);
+    }
+    if values.is_empty() {
+        return Ok(());
+    }
+    let min = *values.iter().min().unwrap();
+    let max = *values.iter().max().unwrap();
+    let value_bits = bits_required(max.wrapping_sub(min) as u64);
+    let offset_bits = bits_required(values.len() as u64);
+    let deltas: Vec<u64> = values.iter().map(|v| v.wrapping_sub(min) as u64).collect();
+
+    let mut offsets_data = Vec::new();
+    let offsets_length = write_packed(&mut offsets_data, &offsets, offset_bits)?;
+    let mut target = BufWriter::new(File::create(path)?);
+    target.write_u64::<BigEndian>(offsets.len() as u64 - 1)?;
+    target.write_u64::<BigEndian>(values.len() as u64)?;
+    target.write_i64::<BigEndian>(min)?;
+    target.write_u8(offset_bits)?;
+    target.write_u8(value_bits)?;
+    target.write_u64::<BigEndian>(offsets_length)?;
+    target.write_all(&offsets_data)?;
+    write_packed(&mut target, &deltas, value_bits)?;
+    target.flush()?;
+    Ok(())
+}
+
+/// Random access to the values of each doc in a column written by `write_column`.
+pub struct Column {
+    data: Mmap,
+    doc_count: u64,
+    min: i64,
+    offset_bits: u8,
+    value_bits: u8,
+    values_start: usize,
+}
+
+impl Column {
+    pub fn open(path: &Path) -> Result<Option<Column>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = unsafe { Mmap::map(&File::open(path)?)? };
+        let mut header = Cursor::new(&data[..HEADER_LENGTH]);
+        let doc_count = header.read_u64::<BigEndian>()?;
+        let _value_count = header.read_u64::<BigEndian>()?;
+        let min = header.read_i64::<BigEndian>()?;
+        let offset_bits = header.read_u8()?;
+        let value_bits = header.read_u8()?;
+        let offsets_length = header.read_u64::<BigEndian>()?;
+        Ok(Some(Column {
+            data,
+            doc_count,
+            min,
+            offset_bits,
+            value_bits,
+            values_start: HEADER_LENGTH + offsets_length as usize,
+        }))
+    }
+
+    pub fn value_count(&self, doc_id: DocId) -> u64 {
+        let (start, end) = self.offsets(doc_id);
+        end - start
+    }
+
+    pub fn values(&self, doc_id: DocId) -> Vec<i64> {
+        let (start, end) = self.offsets(doc_id);
+        (start..end).map(|index| self.value(index)).collect()
+    }
+
+    pub fn first_value(&self, doc_id: DocId) -> Option<i64> {
+        let (start, end) = self.offsets(doc_id);
+        if start < end {
+            Some(self.value(start))
+        } else {
+            None
+        }
+    }
+
+    fn offsets(&self, doc_id: DocId) -> (u64, u64) {
+        if doc_id >= self.doc_count {
+            return (0, 0);
+        }
+        let offsets = &self.data[HEADER_LENGTH..self.values_start];
+        (
+            read_packed(offsets, self.offset_bits, doc_id),
+            read_packed(offsets, self.offset_bits, doc_id + 1),
+        )
+    }
+
+    fn value(&self, index: u64) -> i64 {
+        let delta = read_packed(&self.data[self.values_start..], self.value_bits, index);
+        self.min.wrapping_add(delta as i64)
+    }
+}
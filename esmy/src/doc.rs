@@ -1,23 +1,185 @@
-use serde::de::{self, Visitor};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::ser::SerializeSeq;
 use serde::Deserialize;
 use serde::Deserializer;
 use serde::Serialize;
 use serde::Serializer;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone)]
 pub enum FieldValue {
     String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Array(Vec<FieldValue>),
+    Object(HashMap<String, FieldValue>),
+}
+
+impl FieldValue {
+    /// The value as text, which is what analyzers and text based features see. The values of
+    /// an array or object are separated by spaces, with object values ordered by key.
+    pub fn to_text(&self) -> Cow<'_, str> {
+        match *self {
+            FieldValue::String(ref value) => Cow::Borrowed(value),
+            FieldValue::Int(value) => Cow::Owned(value.to_string()),
+            FieldValue::Float(value) => Cow::Owned(value.to_string()),
+            FieldValue::Bool(value) => Cow::Borrowed(if value { "true" } else { "false" }),
+            FieldValue::Array(ref values) => Cow::Owned(
+                values
+                    .iter()
+                    .map(|value| value.to_text())
+                    .collect::<Vec<Cow<str>>>()
+                    .join(" "),
+            ),
+            FieldValue::Object(ref fields) => Cow::Owned(
+                sorted_fields(fields)
+                    .iter()
+                    .map(|(_, value)| value.to_text())
+                    .collect::<Vec<Cow<str>>>()
+                    .join(" "),
+            ),
+        }
+    }
+
+    /// The single values of the field, with arrays flattened.
+    pub fn values(&self) -> Box<dyn Iterator<Item = &FieldValue> + '_> {
+        match *self {
+            FieldValue::Array(ref values) => Box::new(values.iter().flat_map(|v| v.values())),
+            _ => Box::new(::std::iter::once(self)),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            FieldValue::String(ref value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            FieldValue::Int(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The value as a float, for both integer and float values.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            FieldValue::Int(value) => Some(value as f64),
+            FieldValue::Float(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            FieldValue::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+//Floats are compared by their bits, so that values can be Eq and Hash
+impl PartialEq for FieldValue {
+    fn eq(&self, other: &FieldValue) -> bool {
+        match (self, other) {
+            (FieldValue::String(a), FieldValue::String(b)) => a == b,
+            (FieldValue::Int(a), FieldValue::Int(b)) => a == b,
+            (FieldValue::Float(a), FieldValue::Float(b)) => a.to_bits() == b.to_bits(),
+            (FieldValue::Bool(a), FieldValue::Bool(b)) => a == b,
+            (FieldValue::Array(a), FieldValue::Array(b)) => a == b,
+            (FieldValue::Object(a), FieldValue::Object(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for FieldValue {}
+
+impl Hash for FieldValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            FieldValue::String(ref value) => (0u8, value).hash(state),
+            FieldValue::Int(value) => (1u8, value).hash(state),
+            FieldValue::Float(value) => (2u8, value.to_bits()).hash(state),
+            FieldValue::Bool(value) => (3u8, value).hash(state),
+            FieldValue::Array(ref values) => (4u8, values).hash(state),
+            FieldValue::Object(ref fields) => (5u8, sorted_fields(fields)).hash(state),
+        }
+    }
+}
+
+fn sorted_fields(fields: &HashMap<String, FieldValue>) -> Vec<(&String, &FieldValue)> {
+    let mut sorted: Vec<(&String, &FieldValue)> = fields.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(b.0));
+    sorted
 }
 
 pub type Doc = HashMap<String, FieldValue>;
 
+/// The values of a field, where a dotted path such as `author.name` addresses a field of a
+/// nested object. A path through an array of objects gives the value of each object.
+pub fn field_values<'a>(doc: &'a Doc, path: &str) -> Vec<&'a FieldValue> {
+    let mut values = Vec::new();
+    collect_field_values(doc, path, &mut values);
+    values
+}
+
+fn collect_field_values<'a>(fields: &'a Doc, path: &str, values: &mut Vec<&'a FieldValue>) {
+    if let Some(value) = fields.get(path) {
+        values.push(value);
+    }
+    for (i, _) in path.match_indices('.') {
+        if let Some(value) = fields.get(&path[..i]) {
+            collect_nested_values(value, &path[i + 1..], values);
+        }
+    }
+}
+
+fn collect_nested_values<'a>(value: &'a FieldValue, path: &str, values: &mut Vec<&'a FieldValue>) {
+    match *value {
+        FieldValue::Object(ref fields) => collect_field_values(fields, path, values),
+        FieldValue::Array(ref elements) => {
+            for element in elements {
+                collect_nested_values(element, path, values);
+            }
+        }
+        _ => (),
+    }
+}
+
 pub trait DocDecorator {
     fn string_field<N, V>(self, name: N, value: V) -> Self
     where
         N: Into<String>,
         V: Into<String>;
+
+    fn int_field<N>(self, name: N, value: i64) -> Self
+    where
+        N: Into<String>;
+
+    fn float_field<N>(self, name: N, value: f64) -> Self
+    where
+        N: Into<String>;
+
+    fn bool_field<N>(self, name: N, value: bool) -> Self
+    where
+        N: Into<String>;
+
+    fn array_field<N>(self, name: N, values: Vec<FieldValue>) -> Self
+    where
+        N: Into<String>;
+
+    fn object_field<N>(self, name: N, fields: Doc) -> Self
+    where
+        N: Into<String>;
 }
 
 impl DocDecorator for Doc {
@@ -29,6 +191,46 @@ impl DocDecorator for Doc {
         self.insert(name.into(), FieldValue::String(value.into()));
         self
     }
+
+    fn int_field<N>(mut self, name: N, value: i64) -> Self
+    where
+        N: Into<String>,
+    {
+        self.insert(name.into(), FieldValue::Int(value));
+        self
+    }
+
+    fn float_field<N>(mut self, name: N, value: f64) -> Self
+    where
+        N: Into<String>,
+    {
+        self.insert(name.into(), FieldValue::Float(value));
+        self
+    }
+
+    fn bool_field<N>(mut self, name: N, value: bool) -> Self
+    where
+        N: Into<String>,
+    {
+        self.insert(name.into(), FieldValue::Bool(value));
+        self
+    }
+
+    fn array_field<N>(mut self, name: N, values: Vec<FieldValue>) -> Self
+    where
+        N: Into<String>,
+    {
+        self.insert(name.into(), FieldValue::Array(values));
+        self
+    }
+
+    fn object_field<N>(mut self, name: N, fields: Doc) -> Self
+    where
+        N: Into<String>,
+    {
+        self.insert(name.into(), FieldValue::Object(fields));
+        self
+    }
 }
 
 impl<'a> Serialize for FieldValue {
@@ -38,6 +240,23 @@ impl<'a> Serialize for FieldValue {
     {
         match *self {
             FieldValue::String(ref value) => serializer.serialize_str(&value),
+            FieldValue::Int(value) => serializer.serialize_i64(value),
+            FieldValue::Float(value) => serializer.serialize_f64(value),
+            FieldValue::Bool(value) => serializer.serialize_bool(value),
+            FieldValue::Array(ref values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            FieldValue::Object(ref fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (name, value) in fields {
+                    map.serialize_entry(name, value)?;
+                }
+                map.end()
+            }
         }
     }
 }
@@ -47,7 +266,7 @@ impl<'de> Deserialize<'de> for FieldValue {
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_str(FieldValueVisitor)
+        deserializer.deserialize_any(FieldValueVisitor)
     }
 }
 
@@ -57,7 +276,7 @@ impl<'de> Visitor<'de> for FieldValueVisitor {
     type Value = FieldValue;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("A string value")
+        formatter.write_str("A string, number, boolean, array or object value")
     }
 
     fn visit_str<E>(self, value: &str) -> Result<FieldValue, E>
@@ -66,20 +285,113 @@ impl<'de> Visitor<'de> for FieldValueVisitor {
     {
         Ok(FieldValue::String(String::from(value)))
     }
+
+    fn visit_string<E>(self, value: String) -> Result<FieldValue, E>
+    where
+        E: de::Error,
+    {
+        Ok(FieldValue::String(value))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<FieldValue, E>
+    where
+        E: de::Error,
+    {
+        Ok(FieldValue::Int(value))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<FieldValue, E>
+    where
+        E: de::Error,
+    {
+        if value <= i64::MAX as u64 {
+            Ok(FieldValue::Int(value as i64))
+        } else {
+            Ok(FieldValue::Float(value as f64))
+        }
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<FieldValue, E>
+    where
+        E: de::Error,
+    {
+        Ok(FieldValue::Float(value))
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<FieldValue, E>
+    where
+        E: de::Error,
+    {
+        Ok(FieldValue::Bool(value))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<FieldValue, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+        Ok(FieldValue::Array(values))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<FieldValue, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut fields = HashMap::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some((name, value)) = map.next_entry()? {
+            fields.insert(name, value);
+        }
+        Ok(FieldValue::Object(fields))
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use super::field_values;
     use super::Doc;
     use super::FieldValue;
     use proptest::collection::hash_map;
+    use proptest::collection::vec;
     use proptest::prelude::*;
     use rmps::{Deserializer, Serializer};
     use serde::{Deserialize, Serialize};
+    use serde_json;
 
     fn arb_fieldvalue() -> BoxedStrategy<FieldValue> {
-        prop_oneof![".*".prop_map(FieldValue::String),].boxed()
+        prop_oneof![
+            ".*".prop_map(FieldValue::String),
+            any::<i64>().prop_map(FieldValue::Int),
+            any::<f64>().prop_map(FieldValue::Float),
+            any::<bool>().prop_map(FieldValue::Bool),
+        ]
+        .prop_recursive(2, 20, 5, |inner| {
+            prop_oneof![
+                vec(inner.clone(), 0..5).prop_map(FieldValue::Array),
+                hash_map(arb_fieldname(), inner, 0..5).prop_map(FieldValue::Object),
+            ]
+        })
+        .boxed()
+    }
+
+    fn arb_json_fieldvalue() -> BoxedStrategy<FieldValue> {
+        //JSON has no NaN or infinity, and only short decimals are sure to round trip exactly
+        prop_oneof![
+            ".*".prop_map(FieldValue::String),
+            any::<i64>().prop_map(FieldValue::Int),
+            any::<i32>().prop_map(|i| FieldValue::Float(f64::from(i) / 8.0)),
+            any::<bool>().prop_map(FieldValue::Bool),
+        ]
+        .prop_recursive(2, 20, 5, |inner| {
+            prop_oneof![
+                vec(inner.clone(), 0..5).prop_map(FieldValue::Array),
+                hash_map(arb_fieldname(), inner, 0..5).prop_map(FieldValue::Object),
+            ]
+        })
+        .boxed()
     }
 
     fn arb_fieldname() -> BoxedStrategy<String> {
@@ -90,6 +402,26 @@ mod tests {
         hash_map(arb_fieldname(), arb_fieldvalue(), 0..100).boxed()
     }
 
+    #[test]
+    fn finds_nested_values_by_path() {
+        let doc: Doc = serde_json::from_str(
+            r#"{"title": "t", "author": {"name": "a"}, "editors": [{"name": "b"}, {"name": "c"}, {}], "x.y": "d"}"#,
+        )
+        .unwrap();
+        let texts = |path| {
+            field_values(&doc, path)
+                .iter()
+                .map(|v| v.to_text().into_owned())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(vec!["t"], texts("title"));
+        assert_eq!(vec!["a"], texts("author.name"));
+        assert_eq!(vec!["b", "c"], texts("editors.name"));
+        assert_eq!(vec!["d"], texts("x.y"));
+        assert!(texts("author.age").is_empty());
+        assert!(texts("title.name").is_empty());
+    }
+
     proptest! {
         #[test]
         fn serializes_doc_correct(ref doc in arb_doc()) {
@@ -98,5 +430,11 @@ mod tests {
             let mut de = Deserializer::new(&buf[..]);
             assert!(doc == &Deserialize::deserialize(&mut de).unwrap());
         }
+
+        #[test]
+        fn serializes_doc_as_json(ref doc in hash_map(arb_fieldname(), arb_json_fieldvalue(), 0..100)) {
+            let json = serde_json::to_string(doc).unwrap();
+            assert!(doc == &serde_json::from_str::<Doc>(&json).unwrap());
+        }
     }
 }
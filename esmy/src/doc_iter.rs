@@ -1,5 +1,8 @@
+use std::cmp::Reverse;
 use std::collections::BTreeSet;
+use std::collections::BinaryHeap;
 use std::collections::VecDeque;
+use std::mem;
 use DocId;
 use Error;
 
@@ -23,9 +26,21 @@ pub trait DocIter {
         }
     }
     fn current_doc(&self) -> Option<DocId>;
+    /// The relevance score of the current doc. Iterators that do not score return a constant
+    /// score of 1.
+    fn score(&mut self) -> Result<f32, Error> {
+        Ok(1.0)
+    }
+}
+
+/// A doc iterator that knows how many times its term (or phrase) occurs in the current doc.
+pub trait TermFreqDocIter: DocIter {
+    fn freq(&mut self) -> Result<u64, Error>;
 }
 
 pub type Position = u64;
+/// The start and (exclusive) end position of a span.
+pub type Span = (Position, Position);
 pub trait DocSpansIter: DocIter {
     fn next_start_pos(&mut self) -> Result<Option<Position>, Error>;
     fn start_pos(&self) -> Option<Position>;
@@ -55,16 +70,303 @@ impl DocIter for AllDocIter {
         self.current_doc = conjunction_advance(&mut self.sub)?;
         Ok(self.current_doc)
     }
+
+    fn score(&mut self) -> Result<f32, Error> {
+        let mut score = 0f32;
+        for sub in self.sub.iter_mut() {
+            score += sub.score()?;
+        }
+        Ok(score)
+    }
+}
+
+/// Matches docs which at least `min_match` of the sub iterators match, keeping the sub iterators
+/// in a heap ordered by their current doc.
+pub struct AnyDocIter {
+    sub: Vec<Box<dyn DocIter>>,
+    heap: BinaryHeap<Reverse<(DocId, usize)>>,
+    current_sub: Vec<usize>,
+    current_doc: Option<DocId>,
+    min_match: usize,
+    started: bool,
+}
+
+impl AnyDocIter {
+    pub fn new(sub: Vec<Box<dyn DocIter>>, min_match: usize) -> AnyDocIter {
+        let num_sub = sub.len();
+        AnyDocIter {
+            sub,
+            heap: BinaryHeap::with_capacity(num_sub),
+            current_sub: Vec::with_capacity(num_sub),
+            current_doc: None,
+            min_match: min_match.max(1),
+            started: false,
+        }
+    }
+
+    fn push(&mut self, i: usize, doc: Option<DocId>) {
+        if let Some(doc_id) = doc {
+            self.heap.push(Reverse((doc_id, i)));
+        }
+    }
+
+    fn move_current(&mut self, target: Option<DocId>) -> Result<(), Error> {
+        let current_sub = mem::take(&mut self.current_sub);
+        for &i in &current_sub {
+            let doc = match target {
+                Some(target) => self.sub[i].advance(target)?,
+                None => self.sub[i].next_doc()?,
+            };
+            self.push(i, doc);
+        }
+        self.current_sub = current_sub;
+        self.current_sub.clear();
+        Ok(())
+    }
+
+    fn start(&mut self, target: Option<DocId>) -> Result<(), Error> {
+        self.started = true;
+        self.current_sub = (0..self.sub.len()).collect();
+        self.move_current(target)
+    }
+
+    fn collect_current(&mut self) -> Result<Option<DocId>, Error> {
+        loop {
+            match self.heap.pop() {
+                None => {
+                    self.current_doc = None;
+                    return Ok(None);
+                }
+                Some(Reverse((doc_id, i))) => {
+                    self.current_sub.push(i);
+                    while let Some(&Reverse((next_doc_id, j))) = self.heap.peek() {
+                        if next_doc_id != doc_id {
+                            break;
+                        }
+                        self.heap.pop();
+                        self.current_sub.push(j);
+                    }
+                    if self.current_sub.len() >= self.min_match {
+                        self.current_doc = Some(doc_id);
+                        return Ok(self.current_doc);
+                    }
+                    self.move_current(None)?;
+                }
+            }
+        }
+    }
+}
+
+impl DocIter for AnyDocIter {
+    fn next_doc(&mut self) -> Result<Option<DocId>, Error> {
+        if self.started {
+            self.move_current(None)?;
+        } else {
+            self.start(None)?;
+        }
+        self.collect_current()
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<Option<DocId>, Error> {
+        if self.started {
+            self.move_current(Some(target))?;
+            while let Some(&Reverse((doc_id, i))) = self.heap.peek() {
+                if doc_id >= target {
+                    break;
+                }
+                self.heap.pop();
+                let doc = self.sub[i].advance(target)?;
+                self.push(i, doc);
+            }
+        } else {
+            self.start(Some(target))?;
+        }
+        self.collect_current()
+    }
+
+    fn current_doc(&self) -> Option<DocId> {
+        self.current_doc
+    }
+
+    fn score(&mut self) -> Result<f32, Error> {
+        let mut score = 0f32;
+        for &i in &self.current_sub {
+            score += self.sub[i].score()?;
+        }
+        Ok(score)
+    }
+}
+
+/// Follows along with another iterator, to check whether a doc is matched by this one as well.
+struct FollowingDocIter {
+    iter: Box<dyn DocIter>,
+    doc: Option<DocId>,
+    started: bool,
+}
+
+impl FollowingDocIter {
+    fn new(iter: Box<dyn DocIter>) -> FollowingDocIter {
+        FollowingDocIter {
+            iter,
+            doc: None,
+            started: false,
+        }
+    }
+
+    fn is_on(&mut self, target: DocId) -> Result<bool, Error> {
+        if !self.started {
+            self.started = true;
+            self.doc = self.iter.advance(target)?;
+        } else if let Some(doc_id) = self.doc {
+            if doc_id < target {
+                self.doc = self.iter.advance(target)?;
+            }
+        }
+        Ok(self.doc == Some(target))
+    }
+}
+
+/// Matches the docs of one iterator, except those matched by another.
+pub struct ExcludeDocIter {
+    include: Box<dyn DocIter>,
+    exclude: FollowingDocIter,
+}
+
+impl ExcludeDocIter {
+    pub fn new(include: Box<dyn DocIter>, exclude: Box<dyn DocIter>) -> ExcludeDocIter {
+        ExcludeDocIter {
+            include,
+            exclude: FollowingDocIter::new(exclude),
+        }
+    }
+
+    fn skip_excluded(&mut self, mut doc: Option<DocId>) -> Result<Option<DocId>, Error> {
+        while let Some(doc_id) = doc {
+            if !self.exclude.is_on(doc_id)? {
+                break;
+            }
+            doc = self.include.next_doc()?;
+        }
+        Ok(doc)
+    }
+}
+
+impl DocIter for ExcludeDocIter {
+    fn next_doc(&mut self) -> Result<Option<DocId>, Error> {
+        let doc = self.include.next_doc()?;
+        self.skip_excluded(doc)
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<Option<DocId>, Error> {
+        let doc = self.include.advance(target)?;
+        self.skip_excluded(doc)
+    }
+
+    fn current_doc(&self) -> Option<DocId> {
+        self.include.current_doc()
+    }
+
+    fn score(&mut self) -> Result<f32, Error> {
+        self.include.score()
+    }
+}
+
+/// Matches the docs of a required iterator, adding the score of an optional iterator for the
+/// docs which it matches as well.
+pub struct ReqOptDocIter {
+    required: Box<dyn DocIter>,
+    optional: FollowingDocIter,
+    //Some iterators give the next doc as their current one, so the doc is kept here
+    doc: Option<DocId>,
+}
+
+impl ReqOptDocIter {
+    pub fn new(required: Box<dyn DocIter>, optional: Box<dyn DocIter>) -> ReqOptDocIter {
+        ReqOptDocIter {
+            required,
+            optional: FollowingDocIter::new(optional),
+            doc: None,
+        }
+    }
+}
+
+impl DocIter for ReqOptDocIter {
+    fn next_doc(&mut self) -> Result<Option<DocId>, Error> {
+        self.doc = self.required.next_doc()?;
+        Ok(self.doc)
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<Option<DocId>, Error> {
+        self.doc = self.required.advance(target)?;
+        Ok(self.doc)
+    }
+
+    fn current_doc(&self) -> Option<DocId> {
+        self.doc
+    }
+
+    fn score(&mut self) -> Result<f32, Error> {
+        let mut score = self.required.score()?;
+        if let Some(doc_id) = self.doc {
+            if self.optional.is_on(doc_id)? {
+                score += self.optional.iter.score()?;
+            }
+        }
+        Ok(score)
+    }
+}
+
+/// Gives all docs of an iterator the same score.
+pub struct ConstantScoreDocIter {
+    iter: Box<dyn DocIter>,
+    score: f32,
+}
+
+impl ConstantScoreDocIter {
+    pub fn new(iter: Box<dyn DocIter>, score: f32) -> ConstantScoreDocIter {
+        ConstantScoreDocIter { iter, score }
+    }
+}
+
+impl DocIter for ConstantScoreDocIter {
+    fn next_doc(&mut self) -> Result<Option<DocId>, Error> {
+        self.iter.next_doc()
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<Option<DocId>, Error> {
+        self.iter.advance(target)
+    }
+
+    fn current_doc(&self) -> Option<DocId> {
+        self.iter.current_doc()
+    }
+
+    fn score(&mut self) -> Result<f32, Error> {
+        Ok(self.score)
+    }
 }
 
 pub struct VecDocIter {
     doc_ids: Vec<DocId>,
+    scores: Option<Vec<f32>>,
     pos: usize,
 }
 
 impl VecDocIter {
     pub fn new(doc_ids: Vec<DocId>) -> VecDocIter {
-        VecDocIter { doc_ids, pos: 0 }
+        VecDocIter {
+            doc_ids,
+            scores: None,
+            pos: 0,
+        }
+    }
+
+    pub fn with_scores(doc_ids: Vec<DocId>, scores: Vec<f32>) -> VecDocIter {
+        VecDocIter {
+            doc_ids,
+            scores: Some(scores),
+            pos: 0,
+        }
     }
 }
 
@@ -77,6 +379,14 @@ impl DocIter for VecDocIter {
         }
     }
 
+    fn score(&mut self) -> Result<f32, Error> {
+        //pos has already moved past the current doc
+        match self.scores {
+            Some(ref scores) if self.pos > 0 => Ok(scores[self.pos - 1]),
+            _ => Ok(1.0),
+        }
+    }
+
     fn next_doc(&mut self) -> Result<Option<DocId>, Error> {
         if self.pos >= self.doc_ids.len() {
             Ok(None)
@@ -122,22 +432,42 @@ impl DocIter for AllDocsDocIter {
     }
 }
 
+/// Matches docs where the sub spans occur in order, with at most `slop` positions in between
+/// them in total. With a slop of 0, this is an exact phrase.
 pub struct OrderedNearDocSpansIter {
     sub_spans: Vec<Box<dyn DocSpansIter>>,
+    offsets: Vec<Position>,
+    slop: u64,
     current_doc: Option<DocId>,
-    position_queue: VecDeque<Position>,
-    current_position: Option<Position>,
+    span_queue: VecDeque<Span>,
+    current_span: Option<Span>,
+    match_count: u64,
 }
 
 impl OrderedNearDocSpansIter {
     pub fn new(sub_spans: Vec<Box<dyn DocSpansIter>>) -> OrderedNearDocSpansIter {
+        OrderedNearDocSpansIter::with_slop(sub_spans, 0)
+    }
+
+    pub fn with_slop(sub_spans: Vec<Box<dyn DocSpansIter>>, slop: u64) -> OrderedNearDocSpansIter {
         OrderedNearDocSpansIter {
+            offsets: (0..sub_spans.len() as Position).collect(),
             sub_spans,
+            slop,
             current_doc: None,
-            position_queue: VecDeque::new(),
-            current_position: None,
+            span_queue: VecDeque::new(),
+            current_span: None,
+            match_count: 0,
         }
     }
+
+    /// The positions of the sub spans relative to each other, where they are not next to each
+    /// other, such as in a phrase that had words removed by the analyzer. The sub spans must be
+    /// at least that far apart, and the slop is on top of that.
+    pub fn with_offsets(mut self, offsets: Vec<Position>) -> OrderedNearDocSpansIter {
+        self.offsets = offsets;
+        self
+    }
 }
 
 impl DocIter for OrderedNearDocSpansIter {
@@ -146,38 +476,16 @@ impl DocIter for OrderedNearDocSpansIter {
     }
 
     fn next_doc(&mut self) -> Result<Option<DocId>, Error> {
-        loop {
-            match conjunction_advance_span(&mut self.sub_spans)? {
-                Some(doc) => {
-                    //TODO this can be done so much better
-                    let mut sub_pos = Vec::new();
-                    for sub in self.sub_spans.iter_mut() {
-                        let mut set = BTreeSet::new();
-                        while let Some(pos) = sub.next_start_pos()? {
-                            set.insert(pos);
-                        }
-                        sub_pos.push(set);
-                    }
-                    let mut valid_first_pos = VecDeque::new();
-                    'outer: for first_pos in sub_pos.first().unwrap().iter() {
-                        for (off, other_positions) in sub_pos.iter().enumerate().skip(1) {
-                            if !other_positions.contains(&(*first_pos + off as u64)) {
-                                continue 'outer;
-                            }
-                        }
-                        valid_first_pos.push_back(*first_pos);
-                    }
-                    if !valid_first_pos.is_empty() {
-                        self.position_queue = valid_first_pos;
-                        self.current_doc = Some(doc);
-                        break;
-                    }
-                }
-                None => {
-                    self.current_doc = None;
-                    break;
-                }
+        let (offsets, slop) = (&self.offsets, self.slop);
+        match next_near_doc(&mut self.sub_spans, |positions| {
+            ordered_near_spans(positions, offsets, slop)
+        })? {
+            Some((doc, spans)) => {
+                self.match_count = spans.len() as u64;
+                self.span_queue = spans;
+                self.current_doc = Some(doc);
             }
+            None => self.current_doc = None,
         }
         Ok(self.current_doc)
     }
@@ -185,20 +493,216 @@ impl DocIter for OrderedNearDocSpansIter {
 
 impl DocSpansIter for OrderedNearDocSpansIter {
     fn next_start_pos(&mut self) -> Result<Option<Position>, Error> {
-        self.current_position = self.position_queue.pop_front();
-        Ok(self.current_position)
+        self.current_span = self.span_queue.pop_front();
+        Ok(self.start_pos())
     }
 
     fn start_pos(&self) -> Option<Position> {
-        self.current_position
+        self.current_span.map(|(start, _)| start)
     }
 
     fn end_pos(&self) -> Option<Position> {
-        self.current_position
-            .map(|p| p + self.sub_spans.len() as u64)
+        self.current_span.map(|(_, end)| end)
+    }
+}
+
+impl TermFreqDocIter for OrderedNearDocSpansIter {
+    fn freq(&mut self) -> Result<u64, Error> {
+        Ok(self.match_count)
     }
 }
 
+/// Matches docs where the sub spans occur in any order, at distinct positions within a window
+/// that has at most `slop` positions more than the number of sub spans.
+pub struct UnorderedNearDocSpansIter {
+    sub_spans: Vec<Box<dyn DocSpansIter>>,
+    slop: u64,
+    current_doc: Option<DocId>,
+    span_queue: VecDeque<Span>,
+    current_span: Option<Span>,
+    match_count: u64,
+}
+
+impl UnorderedNearDocSpansIter {
+    pub fn new(sub_spans: Vec<Box<dyn DocSpansIter>>, slop: u64) -> UnorderedNearDocSpansIter {
+        UnorderedNearDocSpansIter {
+            sub_spans,
+            slop,
+            current_doc: None,
+            span_queue: VecDeque::new(),
+            current_span: None,
+            match_count: 0,
+        }
+    }
+}
+
+impl DocIter for UnorderedNearDocSpansIter {
+    fn current_doc(&self) -> Option<DocId> {
+        self.current_doc
+    }
+
+    fn next_doc(&mut self) -> Result<Option<DocId>, Error> {
+        let slop = self.slop;
+        match next_near_doc(&mut self.sub_spans, |positions| {
+            unordered_near_spans(positions, slop)
+        })? {
+            Some((doc, spans)) => {
+                self.match_count = spans.len() as u64;
+                self.span_queue = spans;
+                self.current_doc = Some(doc);
+            }
+            None => self.current_doc = None,
+        }
+        Ok(self.current_doc)
+    }
+}
+
+impl DocSpansIter for UnorderedNearDocSpansIter {
+    fn next_start_pos(&mut self) -> Result<Option<Position>, Error> {
+        self.current_span = self.span_queue.pop_front();
+        Ok(self.start_pos())
+    }
+
+    fn start_pos(&self) -> Option<Position> {
+        self.current_span.map(|(start, _)| start)
+    }
+
+    fn end_pos(&self) -> Option<Position> {
+        self.current_span.map(|(_, end)| end)
+    }
+}
+
+impl TermFreqDocIter for UnorderedNearDocSpansIter {
+    fn freq(&mut self) -> Result<u64, Error> {
+        Ok(self.match_count)
+    }
+}
+
+/// Moves to the next doc which all sub spans match, and for which `find_spans` finds at least
+/// one span given the sorted start positions of each sub span.
+fn next_near_doc<F>(
+    sub_spans: &mut [Box<dyn DocSpansIter>],
+    find_spans: F,
+) -> Result<Option<(DocId, VecDeque<Span>)>, Error>
+where
+    F: Fn(&[Vec<Position>]) -> VecDeque<Span>,
+{
+    while let Some(doc) = conjunction_advance_span(sub_spans)? {
+        let mut sub_positions = Vec::with_capacity(sub_spans.len());
+        for sub in sub_spans.iter_mut() {
+            let mut positions = BTreeSet::new();
+            while let Some(pos) = sub.next_start_pos()? {
+                positions.insert(pos);
+            }
+            sub_positions.push(positions.into_iter().collect());
+        }
+        let spans = find_spans(&sub_positions);
+        if !spans.is_empty() {
+            return Ok(Some((doc, spans)));
+        }
+    }
+    Ok(None)
+}
+
+fn ordered_near_spans(
+    sub_positions: &[Vec<Position>],
+    offsets: &[Position],
+    slop: u64,
+) -> VecDeque<Span> {
+    let mut spans = VecDeque::new();
+    let length = offsets[offsets.len() - 1] - offsets[0] + 1;
+    'outer: for &start in &sub_positions[0] {
+        //Picking the first position after the previous one gives the shortest span for a start
+        let mut end = start;
+        for (i, positions) in sub_positions.iter().enumerate().skip(1) {
+            let next = match positions.binary_search(&(end + offsets[i] - offsets[i - 1])) {
+                Ok(i) | Err(i) => i,
+            };
+            match positions.get(next) {
+                Some(&pos) => end = pos,
+                None => break 'outer,
+            }
+        }
+        if end - start + 1 - length <= slop {
+            spans.push_back((start, end + 1));
+        }
+    }
+    spans
+}
+
+fn unordered_near_spans(sub_positions: &[Vec<Position>], slop: u64) -> VecDeque<Span> {
+    let mut spans = VecDeque::new();
+    let num_subs = sub_positions.len() as u64;
+    let all_positions: BTreeSet<Position> = sub_positions.iter().flatten().cloned().collect();
+    for &start in &all_positions {
+        let max_end = start + num_subs - 1 + slop;
+        for &end in all_positions.range(start + num_subs - 1..=max_end) {
+            if distinct_positions_within(sub_positions, start, end) {
+                spans.push_back((start, end + 1));
+                break;
+            }
+        }
+    }
+    spans
+}
+
+/// Whether each sub can be given its own position in `[start, end]`, found as a bipartite
+/// matching between subs and positions.
+fn distinct_positions_within(
+    sub_positions: &[Vec<Position>],
+    start: Position,
+    end: Position,
+) -> bool {
+    let candidates: Vec<&[Position]> = sub_positions
+        .iter()
+        .map(|positions| {
+            let from = match positions.binary_search(&start) {
+                Ok(i) | Err(i) => i,
+            };
+            let to = match positions.binary_search(&(end + 1)) {
+                Ok(i) | Err(i) => i,
+            };
+            &positions[from..to]
+        })
+        .collect();
+    if candidates.iter().any(|c| c.is_empty()) {
+        return false;
+    }
+    let mut owner = vec![None; (end - start + 1) as usize];
+    for sub in 0..candidates.len() {
+        let mut visited = vec![false; owner.len()];
+        if !assign_position(sub, &candidates, start, &mut owner, &mut visited) {
+            return false;
+        }
+    }
+    true
+}
+
+fn assign_position(
+    sub: usize,
+    candidates: &[&[Position]],
+    start: Position,
+    owner: &mut Vec<Option<usize>>,
+    visited: &mut Vec<bool>,
+) -> bool {
+    for &pos in candidates[sub] {
+        let slot = (pos - start) as usize;
+        if visited[slot] {
+            continue;
+        }
+        visited[slot] = true;
+        let free = match owner[slot] {
+            None => true,
+            Some(other) => assign_position(other, candidates, start, owner, visited),
+        };
+        if free {
+            owner[slot] = Some(sub);
+            return true;
+        }
+    }
+    false
+}
+
 fn conjunction_advance(iters: &mut [Box<dyn DocIter>]) -> Result<Option<DocId>, Error> {
     let size = iters.len();
     let mut target = {
@@ -275,3 +779,26 @@ fn conjunction_advance_span(iters: &mut [Box<dyn DocSpansIter>]) -> Result<Optio
     }
     Ok(Some(target))
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::DocIter;
+    use super::ReqOptDocIter;
+    use super::VecDocIter;
+
+    #[test]
+    fn adds_optional_scores_to_the_docs_they_match() {
+        let required = VecDocIter::new(vec![0, 1, 2, 3, 4]);
+        let optional = VecDocIter::with_scores(vec![2, 4], vec![1.5, 0.5]);
+        let mut iter = ReqOptDocIter::new(Box::new(required), Box::new(optional));
+        let mut scores = Vec::new();
+        while let Some(doc_id) = iter.next_doc().unwrap() {
+            scores.push((doc_id, iter.score().unwrap()));
+        }
+        assert_eq!(
+            vec![(0, 1.0), (1, 1.0), (2, 2.5), (3, 1.0), (4, 1.5)],
+            scores
+        );
+    }
+}
@@ -1,4 +1,6 @@
+use fst::automaton::LevenshteinError;
 use rayon::ThreadPoolBuildError;
+use regex_automata;
 use rmps;
 use std;
 use std::convert::From;
@@ -6,6 +8,8 @@ use std::convert::From;
 #[derive(Debug)]
 pub enum Error {
     IOError(std::io::Error),
+    /// A stored config, or one given by a user, which can not be used.
+    InvalidConfig(String),
     Other(Box<dyn std::error::Error + Send>),
 }
 
@@ -13,6 +17,7 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
             Error::IOError(ref io) => io.fmt(f),
+            Error::InvalidConfig(ref message) => message.fmt(f),
             Error::Other(ref err) => err.fmt(f),
         }
     }
@@ -29,6 +34,7 @@ impl std::error::Error for Error {
     fn cause(&self) -> Option<&dyn std::error::Error> {
         match *self {
             Error::IOError(ref io) => Some(io),
+            Error::InvalidConfig(_) => None,
             Error::Other(ref other) => Some(&**other),
         }
     }
@@ -57,3 +63,15 @@ impl From<ThreadPoolBuildError> for Error {
         Error::Other(Box::new(e))
     }
 }
+
+impl From<regex_automata::Error> for Error {
+    fn from(e: regex_automata::Error) -> Self {
+        Error::Other(Box::new(e))
+    }
+}
+
+impl From<LevenshteinError> for Error {
+    fn from(e: LevenshteinError) -> Self {
+        Error::Other(Box::new(e))
+    }
+}
@@ -0,0 +1,283 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::sync::Arc;
+
+use bit_vec::BitVec;
+use memmap::Mmap;
+
+use analyzis::Analyzer;
+use analyzis::AnalyzerRegistry;
+use doc::field_values;
+use error::Error;
+use seg::Feature;
+use seg::FeatureAddress;
+use seg::FeatureConfig;
+use seg::FeatureReader;
+use seg::SegmentInfo;
+use Doc;
+use DocId;
+
+const FIELD_NORMS: &str = "fln";
+
+/// Stores the number of tokens that each doc has in a field, encoded in a single byte per doc.
+#[derive(Clone)]
+pub struct FieldNorms {
+    pub field_name: String,
+    pub analyzer: Box<dyn Analyzer>,
+}
+
+impl FieldNorms {
+    /// The name this feature is stored and registered under.
+    pub const FEATURE_TYPE: &'static str = "field_norms";
+
+    pub fn new(field_name: String, analyzer: Box<dyn Analyzer>) -> FieldNorms {
+        FieldNorms {
+            field_name,
+            analyzer,
+        }
+    }
+
+    fn field_length(&self, doc: &Doc) -> u64 {
+        let mut length = 0u64;
+        for val in field_values(doc, &self.field_name) {
+            length += self.analyzer.analyze_value(val).count() as u64;
+        }
+        length
+    }
+}
+
+impl Feature for FieldNorms {
+    fn feature_type(&self) -> &'static str {
+        Self::FEATURE_TYPE
+    }
+
+    fn from_config(config: FeatureConfig, analyzers: &AnalyzerRegistry) -> Result<Self, Error> {
+        let field_name = config.required_str_at("field")?.to_string();
+        let analyzer =
+            analyzers.from_config(config.at("analyzer").unwrap_or(&FeatureConfig::None))?;
+        Ok(FieldNorms {
+            field_name,
+            analyzer,
+        })
+    }
+
+    fn to_config(&self) -> FeatureConfig {
+        let mut map = HashMap::new();
+        map.insert(
+            "field".to_string(),
+            FeatureConfig::String(self.field_name.to_string()),
+        );
+        map.insert(
+            "analyzer".to_string(),
+            self.analyzer.to_config(),
+        );
+        FeatureConfig::Map(map)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn write_segment(&self, address: &FeatureAddress, docs: &[Doc]) -> Result<(), Error> {
+        let mut norms = BufWriter::new(File::create(address.with_ending(FIELD_NORMS))?);
+        for doc in docs {
+            norms.write_all(&[encode_length(self.field_length(doc))])?;
+        }
+        norms.flush()?;
+        Ok(())
+    }
+
+    fn reader(&self, address: &FeatureAddress) -> Result<Box<dyn FeatureReader>, Error> {
+        let path = address.with_ending(FIELD_NORMS);
+        let norms = if path.exists() {
+            Some(Arc::new(unsafe { Mmap::map(&File::open(path)?)? }))
+        } else {
+            None
+        };
+        let total_length = match norms {
+            Some(ref norms) => norms.iter().map(|b| u64::from(decode_length(*b))).sum(),
+            None => 0,
+        };
+        Ok(Box::new(FieldNormsReader {
+            feature: self.clone(),
+            norms,
+            total_length,
+        }))
+    }
+
+    fn merge_segments(
+        &self,
+        old_segments: &[(FeatureAddress, SegmentInfo, BitVec)],
+        new_segment: &FeatureAddress,
+    ) -> Result<(), Error> {
+        let target_path = new_segment.with_ending(FIELD_NORMS);
+        let mut target = BufWriter::new(File::create(&target_path)?);
+        let mut has_written = false;
+        for (old_address, old_info, deleted_docs) in old_segments {
+            let source_path = old_address.with_ending(FIELD_NORMS);
+            let source = if source_path.exists() {
+                Some(unsafe { Mmap::map(&File::open(source_path)?)? })
+            } else {
+                None
+            };
+            for doc_id in 0..old_info.doc_count as usize {
+                if !deleted_docs.get(doc_id).unwrap_or(false) {
+                    let norm = match source {
+                        Some(ref norms) => norms[doc_id],
+                        None => 0,
+                    };
+                    target.write_all(&[norm])?;
+                    has_written = true;
+                }
+            }
+        }
+        target.flush()?;
+        if !has_written {
+            ::std::fs::remove_file(target_path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads field lengths of a segment. Cloning is cheap, so scorers may keep their own copy.
+#[derive(Clone)]
+pub struct FieldNormsReader {
+    pub feature: FieldNorms,
+    norms: Option<Arc<Mmap>>,
+    total_length: u64,
+}
+
+impl FeatureReader for FieldNormsReader {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl FieldNormsReader {
+    /// The (approximate) number of tokens that the doc has in the field.
+    pub fn field_length(&self, doc_id: DocId) -> u32 {
+        match self.norms {
+            Some(ref norms) => decode_length(norms[doc_id as usize]),
+            None => 0,
+        }
+    }
+
+    /// The sum of the field lengths of all docs in the segment.
+    pub fn total_length(&self) -> u64 {
+        self.total_length
+    }
+
+    pub fn doc_count(&self) -> u64 {
+        match self.norms {
+            Some(ref norms) => norms.len() as u64,
+            None => 0,
+        }
+    }
+}
+
+//Lengths below this are stored exactly, larger ones with a 4 bit mantissa, like in Lucene.
+const EXACT_LENGTHS: u64 = 24;
+
+fn encode_length(length: u64) -> u8 {
+    if length < EXACT_LENGTHS {
+        length as u8
+    } else {
+        let encoded = EXACT_LENGTHS + encode_int4(length - EXACT_LENGTHS);
+        encoded.min(255) as u8
+    }
+}
+
+fn decode_length(b: u8) -> u32 {
+    let i = u64::from(b);
+    if i < EXACT_LENGTHS {
+        i as u32
+    } else {
+        (EXACT_LENGTHS + decode_int4(i - EXACT_LENGTHS)) as u32
+    }
+}
+
+fn encode_int4(i: u64) -> u64 {
+    let num_bits = 64 - i.leading_zeros() as u64;
+    if num_bits < 4 {
+        i
+    } else {
+        let shift = num_bits - 4;
+        ((i >> shift) & 0x07) | ((shift + 1) << 3)
+    }
+}
+
+fn decode_int4(i: u64) -> u64 {
+    let bits = i & 0x07;
+    let shift = i >> 3;
+    if shift == 0 {
+        bits
+    } else {
+        (bits | 0x08) << (shift - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::decode_length;
+    use super::encode_length;
+    use super::FieldNorms;
+    use super::FieldNormsReader;
+    use analyzis::WhiteSpaceAnalyzer;
+    use doc::DocDecorator;
+    use proptest::prelude::*;
+    use seg::test_fixtures::feature_address;
+    use seg::test_fixtures::old_segment;
+    use seg::Feature;
+    use seg::FeatureAddress;
+    use tempfile::TempDir;
+    use Doc;
+
+    fn lengths(norms: &FieldNorms, address: &FeatureAddress) -> Vec<u32> {
+        let reader = norms.reader(address).unwrap();
+        let reader = reader.as_any().downcast_ref::<FieldNormsReader>().unwrap();
+        (0..reader.doc_count())
+            .map(|doc_id| reader.field_length(doc_id))
+            .collect()
+    }
+
+    #[test]
+    fn remaps_lengths_on_merge() {
+        let dir = TempDir::new().unwrap();
+        let norms = FieldNorms::new("text".to_string(), Box::new(WhiteSpaceAnalyzer));
+        let docs = vec![
+            Doc::new().string_field("text", "a b c"),
+            Doc::new().string_field("other", "a b c"),
+            Doc::new().string_field("text", "a b"),
+        ];
+        let first = feature_address(&dir, "first");
+        norms.write_segment(&first, &docs).unwrap();
+        assert_eq!(vec![3, 0, 2], lengths(&norms, &first));
+
+        let merged = feature_address(&dir, "merged");
+        norms
+            .merge_segments(&[old_segment(&first, docs.len(), &[1])], &merged)
+            .unwrap();
+        assert_eq!(vec![3, 2], lengths(&norms, &merged));
+    }
+
+    #[test]
+    fn short_lengths_are_exact() {
+        for length in 0..32u64 {
+            assert_eq!(length as u32, decode_length(encode_length(length)));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn encoding_is_monotonic_and_close(length in 0u64..i32::max_value() as u64) {
+            let decoded = u64::from(decode_length(encode_length(length)));
+            assert!(decoded <= length);
+            assert!(length - decoded <= length / 8);
+            assert!(encode_length(length) <= encode_length(length + 1));
+        }
+    }
+}
@@ -17,6 +17,7 @@ use lz4::EncoderBuilder;
 use rmps;
 use serde::Serialize;
 
+use analyzis::AnalyzerRegistry;
 use doc::Doc;
 use error::Error;
 use seg::Feature;
@@ -51,6 +52,9 @@ pub struct FullDoc {
 }
 
 impl FullDoc {
+    /// The name this feature is stored and registered under.
+    pub const FEATURE_TYPE: &'static str = "full_doc";
+
     pub fn new() -> FullDoc {
         FullDoc {
             level: None
@@ -73,14 +77,14 @@ impl FullDoc {
 
 impl Feature for FullDoc {
     fn feature_type(&self) -> &'static str {
-        "full_doc"
+        Self::FEATURE_TYPE
     }
 
-    fn from_config(config: FeatureConfig) -> Self {
+    fn from_config(config: FeatureConfig, _analyzers: &AnalyzerRegistry) -> Result<Self, Error> {
         let level = config
             .int_at("compression_level")
             .map(|n| n as u32);
-        FullDoc { level }
+        Ok(FullDoc { level })
     }
 
     fn to_config(&self) -> FeatureConfig {
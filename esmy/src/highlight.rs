@@ -0,0 +1,292 @@
+use std::cmp::Reverse;
+
+use analyzis::Analyzer;
+use analyzis::Token;
+use doc::field_values;
+use doc_iter::Position;
+use search::Query;
+use string_pos_index::positioned_tokens;
+use Doc;
+
+/// Marks the tokens that make a query match in the text of a stored doc, and picks the fragments
+/// of the text with the most matches.
+///
+/// The field is analyzed again with the given analyzer, which should be the analyzer of the
+/// feature the field is indexed with, so that the tokens are the same as the query matched.
+#[derive(Clone, Debug)]
+pub struct Highlighter {
+    field: String,
+    analyzer: Box<dyn Analyzer>,
+    pre_tag: String,
+    post_tag: String,
+    fragment_size: usize,
+    fragment_count: usize,
+}
+
+impl Highlighter {
+    pub fn new<F>(field: F, analyzer: Box<dyn Analyzer>) -> Highlighter
+    where
+        F: Into<String>,
+    {
+        Highlighter {
+            field: field.into(),
+            analyzer,
+            pre_tag: "<em>".to_string(),
+            post_tag: "</em>".to_string(),
+            fragment_size: 100,
+            fragment_count: 5,
+        }
+    }
+
+    pub fn with_tags<P, S>(mut self, pre_tag: P, post_tag: S) -> Highlighter
+    where
+        P: Into<String>,
+        S: Into<String>,
+    {
+        self.pre_tag = pre_tag.into();
+        self.post_tag = post_tag.into();
+        self
+    }
+
+    /// The number of bytes after which a fragment ends, not counting the tags. Fragments only
+    /// end before a token that is not part of a match, so they may be longer. With 0, each
+    /// value of the field is one fragment.
+    pub fn with_fragment_size(mut self, fragment_size: usize) -> Highlighter {
+        self.fragment_size = fragment_size;
+        self
+    }
+
+    pub fn with_fragment_count(mut self, fragment_count: usize) -> Highlighter {
+        self.fragment_count = fragment_count;
+        self
+    }
+
+    /// The fragments of the field with the most matches, best first, where the matching tokens
+    /// are put between the tags. Fragments without matches are left out.
+    pub fn highlight(&self, query: &impl Query, doc: &Doc) -> Vec<String> {
+        let mut fragments = Vec::new();
+        for value in field_values(doc, &self.field)
+            .iter()
+            .flat_map(|value| value.values())
+        {
+            let text = value.to_text();
+            let tokens = positioned_tokens(&*self.analyzer, &[value]);
+            let spans = self.match_spans(query, &tokens);
+            for (start, end) in fragment_bounds(&text, &tokens, &spans, self.fragment_size) {
+                let fragment_spans: Vec<(usize, usize)> = spans
+                    .iter()
+                    .filter(|&&(span_start, _)| start <= span_start && span_start < end)
+                    .cloned()
+                    .collect();
+                if !fragment_spans.is_empty() {
+                    let fragment = self.mark(&text, start, end, &fragment_spans);
+                    fragments.push((fragment_spans.len(), fragment));
+                }
+            }
+        }
+        // The sort is stable, so fragments with as many matches stay in text order.
+        fragments.sort_by_key(|fragment| Reverse(fragment.0));
+        fragments
+            .into_iter()
+            .take(self.fragment_count)
+            .map(|(_, fragment)| fragment)
+            .collect()
+    }
+
+    /// The byte ranges of the matches in a value, sorted and with overlapping matches joined.
+    fn match_spans(&self, query: &impl Query, tokens: &[(Position, Token)]) -> Vec<(usize, usize)> {
+        let mut positions: Vec<Option<String>> = match tokens.last() {
+            Some(&(pos, _)) => vec![None; pos as usize + 1],
+            None => Vec::new(),
+        };
+        let mut offsets = vec![None; positions.len()];
+        for &(pos, ref token) in tokens {
+            positions[pos as usize] = Some(token.text.to_string());
+            //Tokens without a range in the text can not be marked
+            if token.start < token.end {
+                offsets[pos as usize] = Some((token.start, token.end));
+            }
+        }
+        let mut spans: Vec<(usize, usize)> = query
+            .highlight_positions(&self.field, &positions)
+            .into_iter()
+            .filter(|range| range.end <= offsets.len())
+            .filter_map(|range| {
+                let mut range_offsets = offsets[range].iter().filter_map(|offsets| *offsets);
+                let first = range_offsets.next()?;
+                let last = range_offsets.next_back().unwrap_or(first);
+                Some((first.0, last.1))
+            })
+            .collect();
+        spans.sort();
+        let mut joined: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+        for (start, end) in spans {
+            match joined.last_mut() {
+                Some(last) if start < last.1 => last.1 = last.1.max(end),
+                _ => joined.push((start, end)),
+            }
+        }
+        joined
+    }
+
+    fn mark(&self, text: &str, start: usize, end: usize, spans: &[(usize, usize)]) -> String {
+        let mut fragment = String::new();
+        let mut pos = start;
+        for &(span_start, span_end) in spans {
+            fragment.push_str(&text[pos..span_start]);
+            fragment.push_str(&self.pre_tag);
+            fragment.push_str(&text[span_start..span_end]);
+            fragment.push_str(&self.post_tag);
+            pos = span_end;
+        }
+        fragment.push_str(&text[pos..end]);
+        fragment.trim().to_string()
+    }
+}
+
+/// Splits a value into fragments of at least `fragment_size` bytes, at the start of tokens
+/// which are not inside a match.
+fn fragment_bounds(
+    text: &str,
+    tokens: &[(Position, Token)],
+    spans: &[(usize, usize)],
+    fragment_size: usize,
+) -> Vec<(usize, usize)> {
+    let mut bounds = Vec::new();
+    let mut start = 0;
+    if fragment_size > 0 {
+        for (_, token) in tokens {
+            let in_span = spans
+                .iter()
+                .any(|&(span_start, span_end)| span_start < token.start && token.start < span_end);
+            if token.start - start >= fragment_size && !in_span {
+                bounds.push((start, token.start));
+                start = token.start;
+            }
+        }
+    }
+    bounds.push((start, text.len()));
+    bounds
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::borrow::Cow;
+
+    use super::Highlighter;
+    use analyzis::Analyzer;
+    use analyzis::CustomAnalyzer;
+    use analyzis::UAX29Analyzer;
+    use doc::DocDecorator;
+    use doc::FieldValue;
+    use search::BooleanQuery;
+    use search::PrefixQuery;
+    use search::TextQuery;
+    use stemming::Language;
+    use Doc;
+
+    fn highlighter() -> Highlighter {
+        Highlighter::new("text", Box::new(UAX29Analyzer))
+    }
+
+    #[test]
+    fn marks_terms_and_phrases() {
+        let doc = Doc::new().string_field("text", "The quick brown fox jumps over the lazy Dog.");
+        let query = BooleanQuery::new()
+            .should(Box::new(TextQuery::new(
+                "text",
+                "brown fox",
+                Box::new(UAX29Analyzer),
+            )))
+            .should(Box::new(TextQuery::new(
+                "text",
+                "dog",
+                Box::new(UAX29Analyzer),
+            )));
+        assert_eq!(
+            vec!["The quick <em>brown fox</em> jumps over the lazy <em>Dog</em>."],
+            highlighter().highlight(&query, &doc)
+        );
+
+        let query = PrefixQuery::new("text", "qu", Box::new(UAX29Analyzer));
+        assert_eq!(
+            vec!["The [quick] brown fox jumps over the lazy Dog."],
+            highlighter().with_tags("[", "]").highlight(&query, &doc)
+        );
+
+        let query = TextQuery::new("other", "dog", Box::new(UAX29Analyzer));
+        assert!(highlighter().highlight(&query, &doc).is_empty());
+    }
+
+    #[test]
+    fn picks_fragments_with_most_matches() {
+        let doc = Doc::new().array_field(
+            "text",
+            vec![
+                FieldValue::String("one fox here, and then some more words".to_string()),
+                FieldValue::String("no match at all in this value".to_string()),
+                FieldValue::String("a fox and another fox".to_string()),
+            ],
+        );
+        let query = TextQuery::new("text", "fox", Box::new(UAX29Analyzer));
+        assert_eq!(
+            vec![
+                "a <em>fox</em> and another <em>fox</em>",
+                "one <em>fox</em> here, and then"
+            ],
+            highlighter()
+                .with_fragment_size(20)
+                .with_fragment_count(2)
+                .highlight(&query, &doc)
+        );
+        assert_eq!(
+            vec![
+                "a <em>fox</em> and another <em>fox</em>",
+                "one <em>fox</em> here, and then some more words",
+            ],
+            highlighter().with_fragment_size(0).highlight(&query, &doc)
+        );
+    }
+
+    #[test]
+    fn marks_phrases_with_stop_words() {
+        let analyzer = CustomAnalyzer::without_stop_words(Language::English).boxed();
+        let doc = Doc::new().array_field(
+            "text",
+            vec![
+                FieldValue::String("The Tower of London".to_string()),
+                FieldValue::String("London Tower Bridge".to_string()),
+            ],
+        );
+        let query = TextQuery::new("text", "tower of london", analyzer.clone());
+        assert_eq!(
+            vec!["The <em>Tower of London</em>"],
+            Highlighter::new("text", analyzer).highlight(&query, &doc)
+        );
+    }
+
+    /// Lowercases whitespace separated words, without telling where they are.
+    #[derive(Clone, Debug)]
+    struct LowercaseAnalyzer;
+
+    impl Analyzer for LowercaseAnalyzer {
+        fn analyzer_type(&self) -> &'static str {
+            "lowercase"
+        }
+
+        fn analyze<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+            Box::new(value.split_whitespace().map(|s| Cow::Owned(s.to_lowercase())))
+        }
+    }
+
+    #[test]
+    fn marks_only_tokens_found_in_the_text() {
+        let doc = Doc::new().string_field("text", "fox and Fox");
+        let query = TextQuery::new("text", "fox", Box::new(LowercaseAnalyzer));
+        assert_eq!(
+            vec!["<em>fox</em> and Fox"],
+            Highlighter::new("text", Box::new(LowercaseAnalyzer)).highlight(&query, &doc)
+        );
+    }
+}
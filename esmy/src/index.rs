@@ -22,6 +22,7 @@ use rayon::prelude::*;
 use rmps;
 use walkdir::WalkDir;
 
+use analyzis::AnalyzerRegistry;
 use doc::Doc;
 use doc_iter::DocIter;
 use search;
@@ -30,6 +31,7 @@ use search::Query;
 use seg;
 use seg::write_seg;
 use seg::FeatureMeta;
+use seg::FeatureRegistry;
 use seg::SegmentSchema;
 use seg::{SegmentAddress, SegmentInfo, SegmentReader};
 
@@ -82,6 +84,8 @@ impl Drop for SegRef {
 struct IndexOptions {
     auto_commit: bool,
     auto_merge: bool,
+    features: FeatureRegistry,
+    analyzers: AnalyzerRegistry,
 }
 
 pub struct IndexBuilder {
@@ -100,6 +104,8 @@ impl IndexBuilder {
             options: IndexOptions {
                 auto_commit: true,
                 auto_merge: true,
+                features: FeatureRegistry::new(),
+                analyzers: AnalyzerRegistry::new(),
             },
         }
     }
@@ -114,6 +120,18 @@ impl IndexBuilder {
         self
     }
 
+    /// The features that feature configs stored in the index are looked up in.
+    pub fn features(mut self, features: FeatureRegistry) -> IndexBuilder {
+        self.options.features = features;
+        self
+    }
+
+    /// The analyzers that analyzer configs stored in the index are looked up in.
+    pub fn analyzers(mut self, analyzers: AnalyzerRegistry) -> IndexBuilder {
+        self.options.analyzers = analyzers;
+        self
+    }
+
     pub fn open<P>(self, path: P) -> Result<Index, Error>
     where
         P: Into<PathBuf>,
@@ -159,7 +177,11 @@ impl Index {
         P: Into<PathBuf>,
     {
         let path = path.into();
-        let schema = seg::schema_from_metas(read_index_meta(&path).unwrap().feature_template_metas);
+        let schema = seg::schema_from_metas(
+            read_index_meta(&path).unwrap().feature_template_metas,
+            &options.features,
+            &options.analyzers,
+        )?;
         Ok(Index {
             indexer: Indexer::start(path, schema, options)?,
         })
@@ -228,7 +250,7 @@ impl Indexer {
         schema_template: SegmentSchema,
         options: IndexOptions,
     ) -> Result<Arc<Self>, Error> {
-        let state = Indexer::init_state(&path)?;
+        let state = Indexer::init_state(&path, &options)?;
         let indexer = Arc::new(Indexer {
             path,
             options,
@@ -238,12 +260,14 @@ impl Indexer {
         Ok(indexer.clone())
     }
 
-    fn init_state(path: &Path) -> Result<Arc<RwLock<IndexState>>, Error> {
+    fn init_state(path: &Path, options: &IndexOptions) -> Result<Arc<RwLock<IndexState>>, Error> {
         let mut segments = HashMap::new();
         for segment_address in Self::segments_on_disk(&path)? {
             segments.insert(
                 segment_address.clone(),
-                Arc::from(SegRef::new(segment_address.read_info()?)),
+                Arc::from(SegRef::new(
+                    segment_address.read_info(&options.features, &options.analyzers)?,
+                )),
             );
         }
         Ok(Arc::new(RwLock::new(IndexState {
@@ -330,7 +354,11 @@ impl Indexer {
         write_seg(&self.schema_template, &address, &chunk)?;
         self.state.write().unwrap().active_segments.insert(
             address.clone(),
-            Arc::new(SegRef::new(address.read_info().unwrap())),
+            Arc::new(SegRef::new(
+                address
+                    .read_info(&self.options.features, &self.options.analyzers)
+                    .unwrap(),
+            )),
         );
         Ok(())
     }
@@ -367,7 +395,13 @@ impl Indexer {
         let new_address = new_segment_address(&self.path);
         let seg_cloned: Vec<SegmentAddress> =
             segments.iter().map(|info| info.address.clone()).collect();
-        seg::merge(&self.schema_template, &new_address, &seg_cloned)?;
+        seg::merge(
+            &self.schema_template,
+            &new_address,
+            &seg_cloned,
+            &self.options.features,
+            &self.options.analyzers,
+        )?;
         let mut local_state = self.state.write().unwrap();
         for old_segment in seg_cloned.iter() {
             //TODO inefficient iteration
@@ -376,7 +410,11 @@ impl Indexer {
             }
             local_state.waiting_merge.remove(old_segment);
         }
-        let new_info = Arc::new(SegRef::new(new_address.read_info().unwrap()));
+        let new_info = Arc::new(SegRef::new(
+            new_address
+                .read_info(&self.options.features, &self.options.analyzers)
+                .unwrap(),
+        ));
         local_state.active_segments.insert(new_address, new_info);
         Ok(())
     }
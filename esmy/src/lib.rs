@@ -78,6 +78,7 @@ extern crate fasthash;
 extern crate fst;
 extern crate indexmap;
 extern crate rand;
+extern crate regex_automata;
 extern crate smallvec;
 extern crate unicode_segmentation;
 extern crate walkdir;
@@ -95,21 +96,35 @@ extern crate lz4;
 
 #[cfg(test)]
 extern crate proptest;
+#[cfg(test)]
+extern crate serde_json;
+#[cfg(test)]
+extern crate tempfile;
 
 #[macro_use]
 extern crate lazy_static;
 extern crate memmap;
 
+pub mod aggregation;
 pub mod analyzis;
 pub mod doc;
 pub mod doc_iter;
 pub mod error;
+pub mod field_norms;
 pub mod full_doc;
+pub mod highlight;
 pub mod index;
+pub mod numeric_doc_values;
+pub mod numeric_points;
+pub mod score;
 pub mod search;
 pub mod seg;
+pub mod sorted_doc_values;
+pub mod stemming;
+pub mod stop_words;
 pub mod string_index;
 pub mod string_pos_index;
+mod column;
 mod util;
 pub use error::Error;
 
@@ -0,0 +1,315 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bit_vec::BitVec;
+
+use analyzis::AnalyzerRegistry;
+use column::write_column;
+use column::Column;
+use doc::field_values;
+use doc::FieldValue;
+use error::Error;
+use seg::Feature;
+use seg::FeatureAddress;
+use seg::FeatureConfig;
+use seg::FeatureReader;
+use seg::SegmentInfo;
+use Doc;
+use DocId;
+
+const NUMERIC_DOC_VALUES: &str = "ndv";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumericType {
+    Int,
+    Float,
+}
+
+impl NumericType {
+    pub fn from_name(name: &str) -> Result<NumericType, Error> {
+        match name {
+            "int" => Ok(NumericType::Int),
+            "float" => Ok(NumericType::Float),
+            _ => Err(Error::InvalidConfig(format!("No such numeric type: {}", name))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NumericType::Int => "int",
+            NumericType::Float => "float",
+        }
+    }
+
+    /// The stored form of a value. Floats are stored as integers which sort in the same order,
+    /// with -0.0 stored as 0.0 so that the two are equal.
+    pub fn encode(self, value: &FieldValue) -> Option<i64> {
+        match self {
+            NumericType::Int => value.as_i64(),
+            NumericType::Float => value
+                .as_f64()
+                .map(|value| sortable_f64_bits(if value == 0.0 { 0.0 } else { value })),
+        }
+    }
+
+    /// The value of a stored integer as a float.
+    pub fn to_f64(self, stored: i64) -> f64 {
+        match self {
+            NumericType::Int => stored as f64,
+            NumericType::Float => f64::from_bits(sortable_f64_bits_inv(stored) as u64),
+        }
+    }
+}
+
+fn sortable_f64_bits(value: f64) -> i64 {
+    let bits = value.to_bits() as i64;
+    bits ^ ((bits >> 63) & i64::MAX)
+}
+
+fn sortable_f64_bits_inv(bits: i64) -> i64 {
+    bits ^ ((bits >> 63) & i64::MAX)
+}
+
+/// Stores the numeric values that each doc has in a field, in a bit-packed column which allows
+/// fast access by doc id.
+#[derive(Clone)]
+pub struct NumericDocValues {
+    pub field_name: String,
+    pub numeric_type: NumericType,
+}
+
+impl NumericDocValues {
+    /// The name this feature is stored and registered under.
+    pub const FEATURE_TYPE: &'static str = "numeric_doc_values";
+
+    pub fn new(field_name: String, numeric_type: NumericType) -> NumericDocValues {
+        NumericDocValues {
+            field_name,
+            numeric_type,
+        }
+    }
+
+    fn doc_values(&self, doc: &Doc) -> Vec<i64> {
+        field_values(doc, &self.field_name)
+            .iter()
+            .flat_map(|value| value.values())
+            .filter_map(|value| self.numeric_type.encode(value))
+            .collect()
+    }
+}
+
+impl Feature for NumericDocValues {
+    fn feature_type(&self) -> &'static str {
+        Self::FEATURE_TYPE
+    }
+
+    fn from_config(config: FeatureConfig, _analyzers: &AnalyzerRegistry) -> Result<Self, Error> {
+        let field_name = config.required_str_at("field")?.to_string();
+        let numeric_type = NumericType::from_name(config.required_str_at("type")?)?;
+        Ok(NumericDocValues {
+            field_name,
+            numeric_type,
+        })
+    }
+
+    fn to_config(&self) -> FeatureConfig {
+        let mut map = HashMap::new();
+        map.insert(
+            "field".to_string(),
+            FeatureConfig::String(self.field_name.to_string()),
+        );
+        map.insert(
+            "type".to_string(),
+            FeatureConfig::String(self.numeric_type.name().to_string()),
+        );
+        FeatureConfig::Map(map)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn write_segment(&self, address: &FeatureAddress, docs: &[Doc]) -> Result<(), Error> {
+        write_column(
+            &address.with_ending(NUMERIC_DOC_VALUES),
+            docs.iter().map(|doc| self.doc_values(doc)),
+        )
+    }
+
+    fn reader(&self, address: &FeatureAddress) -> Result<Box<dyn FeatureReader>, Error> {
+        Ok(Box::new(NumericDocValuesReader::open(
+            self.clone(),
+            address,
+        )?))
+    }
+
+    fn merge_segments(
+        &self,
+        old_segments: &[(FeatureAddress, SegmentInfo, BitVec)],
+        new_segment: &FeatureAddress,
+    ) -> Result<(), Error> {
+        let mut doc_values = Vec::new();
+        for (old_address, old_info, deleted_docs) in old_segments {
+            let reader = NumericDocValuesReader::open(self.clone(), old_address)?;
+            for doc_id in 0..old_info.doc_count {
+                if !deleted_docs.get(doc_id as usize).unwrap_or(false) {
+                    doc_values.push(reader.values(doc_id));
+                }
+            }
+        }
+        write_column(
+            &new_segment.with_ending(NUMERIC_DOC_VALUES),
+            doc_values.into_iter(),
+        )
+    }
+}
+
+/// Reads the numeric values of docs in a segment. Cloning is cheap.
+#[derive(Clone)]
+pub struct NumericDocValuesReader {
+    pub feature: NumericDocValues,
+    column: Option<Arc<Column>>,
+}
+
+impl NumericDocValuesReader {
+    fn open(
+        feature: NumericDocValues,
+        address: &FeatureAddress,
+    ) -> Result<NumericDocValuesReader, Error> {
+        let column = Column::open(&address.with_ending(NUMERIC_DOC_VALUES))?.map(Arc::new);
+        Ok(NumericDocValuesReader { feature, column })
+    }
+
+    /// The number of values that the doc has in the field.
+    pub fn value_count(&self, doc_id: DocId) -> u64 {
+        match self.column {
+            Some(ref column) => column.value_count(doc_id),
+            None => 0,
+        }
+    }
+
+    /// The stored values of the doc, in the order they had in the doc.
+    pub fn values(&self, doc_id: DocId) -> Vec<i64> {
+        match self.column {
+            Some(ref column) => column.values(doc_id),
+            None => Vec::new(),
+        }
+    }
+
+    /// The first stored value of the doc, if it has any.
+    pub fn first_value(&self, doc_id: DocId) -> Option<i64> {
+        match self.column {
+            Some(ref column) => column.first_value(doc_id),
+            None => None,
+        }
+    }
+
+    /// The values of the doc as floats, for both integer and float fields.
+    pub fn f64_values(&self, doc_id: DocId) -> Vec<f64> {
+        self.values(doc_id)
+            .into_iter()
+            .map(|v| self.feature.numeric_type.to_f64(v))
+            .collect()
+    }
+}
+
+impl FeatureReader for NumericDocValuesReader {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::NumericDocValues;
+    use super::NumericDocValuesReader;
+    use super::NumericType;
+    use doc::FieldValue;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use seg::test_fixtures::feature_address;
+    use seg::test_fixtures::int_doc;
+    use seg::test_fixtures::int_docs;
+    use seg::test_fixtures::old_segment;
+    use seg::Feature;
+    use seg::FeatureAddress;
+    use tempfile::TempDir;
+    use Doc;
+
+    fn read_values(
+        feature: &NumericDocValues,
+        address: &FeatureAddress,
+        doc_count: u64,
+    ) -> Vec<Vec<i64>> {
+        let reader = feature.reader(address).unwrap();
+        let reader = reader
+            .as_any()
+            .downcast_ref::<NumericDocValuesReader>()
+            .unwrap();
+        (0..doc_count).map(|doc_id| reader.values(doc_id)).collect()
+    }
+
+    #[test]
+    fn remaps_values_on_merge() {
+        let dir = TempDir::new().unwrap();
+        let feature = NumericDocValues::new("n".to_string(), NumericType::Int);
+        let docs = int_docs();
+        let first = feature_address(&dir, "first");
+        feature.write_segment(&first, &docs).unwrap();
+        assert_eq!(
+            vec![vec![3], vec![], vec![-7, 12], vec![5]],
+            read_values(&feature, &first, 4)
+        );
+
+        let merged = feature_address(&dir, "merged");
+        feature
+            .merge_segments(&[old_segment(&first, docs.len(), &[0])], &merged)
+            .unwrap();
+        assert_eq!(
+            vec![vec![], vec![-7, 12], vec![5]],
+            read_values(&feature, &merged, 3)
+        );
+    }
+
+    #[test]
+    fn floats_keep_their_order() {
+        let values = [f64::NEG_INFINITY, -2.5, 0.0, 1e-300, 3.25, f64::INFINITY];
+        for pair in values.windows(2) {
+            let a = NumericType::Float
+                .encode(&FieldValue::Float(pair[0]))
+                .unwrap();
+            let b = NumericType::Float
+                .encode(&FieldValue::Float(pair[1]))
+                .unwrap();
+            assert!(a < b);
+            assert_eq!(pair[0].to_bits(), NumericType::Float.to_f64(a).to_bits());
+        }
+    }
+
+    #[test]
+    fn stores_negative_zero_as_zero() {
+        let encoded = NumericType::Float.encode(&FieldValue::Float(-0.0)).unwrap();
+        assert_eq!(
+            NumericType::Float.encode(&FieldValue::Float(0.0)),
+            Some(encoded)
+        );
+        assert_eq!(
+            0.0f64.to_bits(),
+            NumericType::Float.to_f64(encoded).to_bits()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn reads_written_values(ref doc_values in vec(vec(any::<i64>(), 0..3), 0..50)) {
+            let dir = TempDir::new().unwrap();
+            let feature = NumericDocValues::new("n".to_string(), NumericType::Int);
+            let docs: Vec<Doc> = doc_values.iter().map(|v| int_doc(v)).collect();
+            let address = feature_address(&dir, "seg");
+            feature.write_segment(&address, &docs).unwrap();
+            assert_eq!(doc_values, &read_values(&feature, &address, docs.len() as u64));
+        }
+    }
+}
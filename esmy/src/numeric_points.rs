@@ -0,0 +1,339 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Cursor;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use bit_vec::BitVec;
+use byteorder::BigEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use memmap::Mmap;
+
+use analyzis::AnalyzerRegistry;
+use doc::field_values;
+use error::Error;
+use numeric_doc_values::NumericType;
+use seg::Feature;
+use seg::FeatureAddress;
+use seg::FeatureConfig;
+use seg::FeatureReader;
+use seg::SegmentInfo;
+use util::bits_required;
+use util::read_packed;
+use util::write_packed;
+use Doc;
+use DocId;
+
+const NUMERIC_POINTS: &str = "pts";
+const HEADER_LENGTH: usize = 8 + 8 + 1 + 1 + 8;
+
+/// Indexes the numeric values of a field sorted by value, each with the doc that has it, so that
+/// the docs with a value in a range can be found without looking at any other docs.
+#[derive(Clone)]
+pub struct NumericPoints {
+    pub field_name: String,
+    pub numeric_type: NumericType,
+}
+
+impl NumericPoints {
+    /// The name this feature is stored and registered under.
+    pub const FEATURE_TYPE: &'static str = "numeric_points";
+
+    pub fn new(field_name: String, numeric_type: NumericType) -> NumericPoints {
+        NumericPoints {
+            field_name,
+            numeric_type,
+        }
+    }
+
+    fn doc_points(&self, doc_id: DocId, doc: &Doc) -> Vec<(i64, DocId)> {
+        field_values(doc, &self.field_name)
+            .iter()
+            .flat_map(|value| value.values())
+            .filter_map(|value| self.numeric_type.encode(value))
+            .map(|value| (value, doc_id))
+            .collect()
+    }
+}
+
+impl Feature for NumericPoints {
+    fn feature_type(&self) -> &'static str {
+        Self::FEATURE_TYPE
+    }
+
+    fn from_config(config: FeatureConfig, _analyzers: &AnalyzerRegistry) -> Result<Self, Error> {
+        let field_name = config.required_str_at("field")?.to_string();
+        let numeric_type = NumericType::from_name(config.required_str_at("type")?)?;
+        Ok(NumericPoints {
+            field_name,
+            numeric_type,
+        })
+    }
+
+    fn to_config(&self) -> FeatureConfig {
+        let mut map = HashMap::new();
+        map.insert(
+            "field".to_string(),
+            FeatureConfig::String(self.field_name.to_string()),
+        );
+        map.insert(
+            "type".to_string(),
+            FeatureConfig::String(self.numeric_type.name().to_string()),
+        );
+        FeatureConfig::Map(map)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn write_segment(&self, address: &FeatureAddress, docs: &[Doc]) -> Result<(), Error> {
+        let points = docs
+            .iter()
+            .enumerate()
+            .flat_map(|(doc_id, doc)| self.doc_points(doc_id as DocId, doc))
+            .collect();
+        write_points(&address.with_ending(NUMERIC_POINTS), points)
+    }
+
+    fn reader(&self, address: &FeatureAddress) -> Result<Box<dyn FeatureReader>, Error> {
+        Ok(Box::new(NumericPointsReader::open(self.clone(), address)?))
+    }
+
+    fn merge_segments(
+        &self,
+        old_segments: &[(FeatureAddress, SegmentInfo, BitVec)],
+        new_segment: &FeatureAddress,
+    ) -> Result<(), Error> {
+        let mut points = Vec::new();
+        let mut doc_offset = 0u64;
+        for (old_address, old_info, deleted_docs) in old_segments {
+            let reader = NumericPointsReader::open(self.clone(), old_address)?;
+            let mut new_doc_ids = Vec::with_capacity(old_info.doc_count as usize);
+            for doc_id in 0..old_info.doc_count {
+                if deleted_docs.get(doc_id as usize).unwrap_or(false) {
+                    new_doc_ids.push(None);
+                } else {
+                    new_doc_ids.push(Some(doc_offset));
+                    doc_offset += 1;
+                }
+            }
+            for (value, doc_id) in reader.points() {
+                if let Some(new_doc_id) = new_doc_ids[doc_id as usize] {
+                    points.push((value, new_doc_id));
+                }
+            }
+        }
+        write_points(&new_segment.with_ending(NUMERIC_POINTS), points)
+    }
+}
+
+/// Writes the points sorted by value and then by doc id, as the bit-packed values followed by
+/// the bit-packed doc ids. The values are stored as the difference from the smallest value.
+/// Nothing is written if there are no points.
+fn write_points(path: &Path, mut points: Vec<(i64, DocId)>) -> Result<(), Error> {
+    if points.is_empty() {
+        return Ok(());
+    }
+    points.sort();
+    let min = points[0].0;
+    let max = points[points.len() - 1].0;
+    let value_bits = bits_required(max.wrapping_sub(min) as u64);
+    let doc_bits = bits_required(points.iter().map(|&(_, doc_id)| doc_id).max().unwrap());
+    let deltas: Vec<u64> = points
+        .iter()
+        .map(|&(value, _)| value.wrapping_sub(min) as u64)
+        .collect();
+    let doc_ids: Vec<DocId> = points.iter().map(|&(_, doc_id)| doc_id).collect();
+
+    let mut values_data = Vec::new();
+    let values_length = write_packed(&mut values_data, &deltas, value_bits)?;
+    let mut target = BufWriter::new(File::create(path)?);
+    target.write_u64::<BigEndian>(points.len() as u64)?;
+    target.write_i64::<BigEndian>(min)?;
+    target.write_u8(value_bits)?;
+    target.write_u8(doc_bits)?;
+    target.write_u64::<BigEndian>(values_length)?;
+    target.write_all(&values_data)?;
+    write_packed(&mut target, &doc_ids, doc_bits)?;
+    target.flush()?;
+    Ok(())
+}
+
+struct Points {
+    data: Mmap,
+    point_count: u64,
+    min: i64,
+    value_bits: u8,
+    doc_bits: u8,
+    doc_ids_start: usize,
+}
+
+impl Points {
+    fn open(path: &Path) -> Result<Option<Points>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = unsafe { Mmap::map(&File::open(path)?)? };
+        let mut header = Cursor::new(&data[..HEADER_LENGTH]);
+        let point_count = header.read_u64::<BigEndian>()?;
+        let min = header.read_i64::<BigEndian>()?;
+        let value_bits = header.read_u8()?;
+        let doc_bits = header.read_u8()?;
+        let values_length = header.read_u64::<BigEndian>()?;
+        Ok(Some(Points {
+            data,
+            point_count,
+            min,
+            value_bits,
+            doc_bits,
+            doc_ids_start: HEADER_LENGTH + values_length as usize,
+        }))
+    }
+
+    fn value(&self, index: u64) -> i64 {
+        let values = &self.data[HEADER_LENGTH..self.doc_ids_start];
+        let delta = read_packed(values, self.value_bits, index);
+        self.min.wrapping_add(delta as i64)
+    }
+
+    fn doc_id(&self, index: u64) -> DocId {
+        read_packed(&self.data[self.doc_ids_start..], self.doc_bits, index)
+    }
+
+    /// The index of the first point for which `before` is false. Points must be partitioned so
+    /// that `before` is true for all points preceding that index.
+    fn partition_point<F: Fn(i64) -> bool>(&self, before: F) -> u64 {
+        let mut low = 0;
+        let mut high = self.point_count;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if before(self.value(mid)) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
+
+/// Finds the docs of a segment which have values in a range. Cloning is cheap.
+#[derive(Clone)]
+pub struct NumericPointsReader {
+    pub feature: NumericPoints,
+    points: Option<Arc<Points>>,
+}
+
+impl NumericPointsReader {
+    fn open(
+        feature: NumericPoints,
+        address: &FeatureAddress,
+    ) -> Result<NumericPointsReader, Error> {
+        let points = Points::open(&address.with_ending(NUMERIC_POINTS))?.map(Arc::new);
+        Ok(NumericPointsReader { feature, points })
+    }
+
+    /// The ids of the docs with a stored value from `min` to `max`, both inclusive, in doc id
+    /// order.
+    pub fn doc_ids(&self, min: i64, max: i64) -> Vec<DocId> {
+        let points = match self.points {
+            Some(ref points) if min <= max => points,
+            _ => return Vec::new(),
+        };
+        let start = points.partition_point(|value| value < min);
+        let end = points.partition_point(|value| value <= max);
+        let mut doc_ids: Vec<DocId> = (start..end).map(|index| points.doc_id(index)).collect();
+        doc_ids.sort();
+        doc_ids.dedup();
+        doc_ids
+    }
+
+    /// All stored values with their docs, sorted by value.
+    fn points(&self) -> Vec<(i64, DocId)> {
+        match self.points {
+            Some(ref points) => (0..points.point_count)
+                .map(|index| (points.value(index), points.doc_id(index)))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl FeatureReader for NumericPointsReader {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::NumericPoints;
+    use super::NumericPointsReader;
+    use numeric_doc_values::NumericType;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use seg::test_fixtures::feature_address;
+    use seg::test_fixtures::int_doc;
+    use seg::test_fixtures::int_docs;
+    use seg::test_fixtures::old_segment;
+    use seg::Feature;
+    use seg::FeatureAddress;
+    use tempfile::TempDir;
+    use Doc;
+    use DocId;
+
+    fn doc_ids(feature: &NumericPoints, address: &FeatureAddress, min: i64, max: i64) -> Vec<u64> {
+        let reader = feature.reader(address).unwrap();
+        let reader = reader
+            .as_any()
+            .downcast_ref::<NumericPointsReader>()
+            .unwrap();
+        reader.doc_ids(min, max)
+    }
+
+    #[test]
+    fn remaps_doc_ids_on_merge() {
+        let dir = TempDir::new().unwrap();
+        let feature = NumericPoints::new("n".to_string(), NumericType::Int);
+        let docs = int_docs();
+        let first = feature_address(&dir, "first");
+        feature.write_segment(&first, &docs).unwrap();
+        assert_eq!(vec![0, 3], doc_ids(&feature, &first, 3, 5));
+        assert_eq!(vec![2], doc_ids(&feature, &first, -10, 0));
+
+        let merged = feature_address(&dir, "merged");
+        feature
+            .merge_segments(&[old_segment(&first, docs.len(), &[0])], &merged)
+            .unwrap();
+        assert_eq!(vec![2], doc_ids(&feature, &merged, 3, 5));
+        assert_eq!(vec![1, 2], doc_ids(&feature, &merged, -7, 12));
+    }
+
+    proptest! {
+        #[test]
+        fn finds_docs_in_range(
+            ref doc_values in vec(vec(-20i64..20, 0..3), 0..50),
+            min in -25i64..25,
+            max in -25i64..25
+        ) {
+            let dir = TempDir::new().unwrap();
+            let feature = NumericPoints::new("n".to_string(), NumericType::Int);
+            let docs: Vec<Doc> = doc_values.iter().map(|v| int_doc(v)).collect();
+            let address = feature_address(&dir, "seg");
+            feature.write_segment(&address, &docs).unwrap();
+            let expected: Vec<DocId> = doc_values
+                .iter()
+                .enumerate()
+                .filter(|(_, values)| values.iter().any(|v| min <= *v && *v <= max))
+                .map(|(doc_id, _)| doc_id as DocId)
+                .collect();
+            assert_eq!(expected, doc_ids(&feature, &address, min, max));
+        }
+    }
+}
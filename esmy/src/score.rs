@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use doc_iter::DocIter;
+use doc_iter::TermFreqDocIter;
+use error::Error;
+use field_norms::FieldNormsReader;
+use index::ManagedIndexReader;
+use search::Query;
+use seg::SegmentReader;
+use DocId;
+
+/// Parameters of the Okapi BM25 ranking function.
+#[derive(Clone, Copy, Debug)]
+pub struct Bm25 {
+    /// Controls how quickly the contribution of repeated terms saturates.
+    pub k1: f32,
+    /// Controls how much the score is normalized by field length.
+    pub b: f32,
+}
+
+impl Default for Bm25 {
+    fn default() -> Self {
+        Bm25 { k1: 1.2, b: 0.75 }
+    }
+}
+
+impl Bm25 {
+    pub fn idf(&self, doc_count: u64, doc_freq: u64) -> f32 {
+        let n = doc_count as f32;
+        let df = doc_freq as f32;
+        (1.0 + (n - df + 0.5) / (df + 0.5)).ln()
+    }
+
+    pub fn tf_norm(&self, freq: u64, field_length: f32, avg_field_length: f32) -> f32 {
+        let tf = freq as f32;
+        let length_ratio = if avg_field_length > 0.0 {
+            field_length / avg_field_length
+        } else {
+            1.0
+        };
+        tf * (self.k1 + 1.0) / (tf + self.k1 * (1.0 - self.b + self.b * length_ratio))
+    }
+}
+
+/// Index-wide statistics needed to score a query, aggregated over all segments.
+#[derive(Clone, Debug, Default)]
+pub struct IndexStats {
+    doc_count: u64,
+    doc_freqs: HashMap<(String, String), u64>,
+    field_lengths: HashMap<String, (u64, u64)>,
+}
+
+impl IndexStats {
+    pub fn new() -> IndexStats {
+        IndexStats {
+            doc_count: 0,
+            doc_freqs: HashMap::new(),
+            field_lengths: HashMap::new(),
+        }
+    }
+
+    pub fn collect(
+        index_reader: &ManagedIndexReader,
+        query: &impl Query,
+    ) -> Result<IndexStats, Error> {
+        let mut stats = IndexStats::new();
+        for segment_reader in index_reader.segment_readers() {
+            stats.add_segment(segment_reader, query)?;
+        }
+        Ok(stats)
+    }
+
+    fn add_segment(&mut self, reader: &SegmentReader, query: &impl Query) -> Result<(), Error> {
+        //Collect into separate stats so that a term occurring twice in a query is counted once
+        let mut segment_stats = IndexStats::new();
+        query.collect_stats(reader, &mut segment_stats)?;
+        self.doc_count += reader.info().doc_count;
+        for (key, doc_freq) in segment_stats.doc_freqs {
+            *self.doc_freqs.entry(key).or_insert(0) += doc_freq;
+        }
+        for (field, (total_length, doc_count)) in segment_stats.field_lengths {
+            let lengths = self.field_lengths.entry(field).or_insert((0, 0));
+            lengths.0 += total_length;
+            lengths.1 += doc_count;
+        }
+        Ok(())
+    }
+
+    /// Records the number of docs in the current segment which contain the term.
+    pub fn set_doc_freq(&mut self, field: &str, term: &str, doc_freq: u64) {
+        self.doc_freqs
+            .insert((field.to_string(), term.to_string()), doc_freq);
+    }
+
+    /// Records the field lengths of the current segment, as read from its field norms.
+    pub fn set_field_lengths(&mut self, field: &str, norms: &FieldNormsReader) {
+        self.field_lengths.insert(
+            field.to_string(),
+            (norms.total_length(), norms.doc_count()),
+        );
+    }
+
+    pub fn avg_field_length(&self, field: &str) -> f32 {
+        match self.field_lengths.get(field) {
+            Some(&(total_length, doc_count)) if doc_count > 0 => {
+                total_length as f32 / doc_count as f32
+            }
+            _ => 0.0,
+        }
+    }
+
+    pub fn doc_count(&self) -> u64 {
+        self.doc_count
+    }
+
+    pub fn doc_freq(&self, field: &str, term: &str) -> u64 {
+        self.doc_freqs
+            .get(&(field.to_string(), term.to_string()))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// The BM25 weight of a term, or the sum of the term weights for a phrase.
+    pub fn weight<T: AsRef<str>>(&self, field: &str, terms: &[T]) -> TermWeight {
+        let bm25 = Bm25::default();
+        let idf = terms
+            .iter()
+            .map(|term| bm25.idf(self.doc_count, self.doc_freq(field, term.as_ref())))
+            .sum();
+        TermWeight {
+            bm25,
+            idf,
+            avg_field_length: self.avg_field_length(field),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TermWeight {
+    bm25: Bm25,
+    idf: f32,
+    avg_field_length: f32,
+}
+
+impl TermWeight {
+    /// Scores a doc. Without a known field length, the doc is assumed to be of average length.
+    pub fn score(&self, freq: u64, field_length: Option<u32>) -> f32 {
+        let tf_norm = match field_length {
+            Some(length) => self
+                .bm25
+                .tf_norm(freq, length as f32, self.avg_field_length),
+            None => self.bm25.tf_norm(freq, 1.0, 1.0),
+        };
+        self.idf * tf_norm
+    }
+}
+
+/// Scores the docs of a term (or phrase) iterator with BM25.
+pub struct TermScorer<I: TermFreqDocIter> {
+    iter: I,
+    weight: TermWeight,
+    norms: Option<FieldNormsReader>,
+}
+
+impl<I: TermFreqDocIter> TermScorer<I> {
+    pub fn new(iter: I, weight: TermWeight) -> TermScorer<I> {
+        TermScorer {
+            iter,
+            weight,
+            norms: None,
+        }
+    }
+
+    /// Normalizes scores by the field lengths in the norms.
+    pub fn with_norms(mut self, norms: Option<FieldNormsReader>) -> TermScorer<I> {
+        self.norms = norms;
+        self
+    }
+}
+
+impl<I: TermFreqDocIter> DocIter for TermScorer<I> {
+    fn next_doc(&mut self) -> Result<Option<DocId>, Error> {
+        self.iter.next_doc()
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<Option<DocId>, Error> {
+        self.iter.advance(target)
+    }
+
+    fn current_doc(&self) -> Option<DocId> {
+        self.iter.current_doc()
+    }
+
+    fn score(&mut self) -> Result<f32, Error> {
+        let freq = self.iter.freq()?;
+        let field_length = match (self.norms.as_ref(), self.iter.current_doc()) {
+            (Some(norms), Some(doc_id)) => Some(norms.field_length(doc_id)),
+            _ => None,
+        };
+        Ok(self.weight.score(freq, field_length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Bm25;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn rare_terms_weigh_more(doc_count in 1u64..1_000_000, df in 1u64..1_000_000) {
+            prop_assume!(df < doc_count);
+            let bm25 = Bm25::default();
+            assert!(bm25.idf(doc_count, df) > bm25.idf(doc_count, df + 1));
+        }
+
+        #[test]
+        fn term_freq_saturates(freq in 1u64..1_000) {
+            let bm25 = Bm25::default();
+            let norm = bm25.tf_norm(freq, 1.0, 1.0);
+            assert!(norm < bm25.tf_norm(freq + 1, 1.0, 1.0));
+            assert!(norm < bm25.k1 + 1.0);
+        }
+
+        #[test]
+        fn long_fields_score_lower(freq in 1u64..100, length in 1u32..1_000) {
+            let bm25 = Bm25::default();
+            let norm = bm25.tf_norm(freq, length as f32, 10.0);
+            assert!(norm > bm25.tf_norm(freq, (length + 1) as f32, 10.0));
+        }
+    }
+}
@@ -1,17 +1,46 @@
 use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::ops::Bound;
+use std::ops::Range;
+
+use bit_vec::BitVec;
+use fst::automaton::Levenshtein;
+use fst::automaton::Str;
+use fst::Automaton;
+use regex_automata::dense;
+use regex_automata::DenseDFA;
 
 use analyzis::Analyzer;
 use analyzis::NoopAnalyzer;
+use doc::field_values;
 use doc::FieldValue;
 use doc_iter::AllDocIter;
 use doc_iter::AllDocsDocIter;
+use doc_iter::AnyDocIter;
+use doc_iter::ConstantScoreDocIter;
 use doc_iter::DocIter;
 use doc_iter::DocSpansIter;
+use doc_iter::ExcludeDocIter;
 use doc_iter::OrderedNearDocSpansIter;
+use doc_iter::Position;
+use doc_iter::ReqOptDocIter;
+use doc_iter::TermFreqDocIter;
+use doc_iter::UnorderedNearDocSpansIter;
 use doc_iter::VecDocIter;
 use index::ManagedIndexReader;
+use numeric_doc_values::NumericDocValuesReader;
+use numeric_doc_values::NumericType;
+use score::IndexStats;
+use score::TermScorer;
+use seg::SegmentAddress;
 use seg::SegmentReader;
+use sorted_doc_values::SortedDocValuesReader;
+use string_index::StringIndexReader;
+use string_pos_index::StringPosIndexReader;
+use string_pos_index::positioned_tokens;
 use Doc;
 use DocId;
 
@@ -22,8 +51,9 @@ pub fn search(
     query: &impl Query,
     collector: &mut dyn Collector,
 ) -> Result<(), Error> {
+    let stats = IndexStats::collect(index_reader, query)?;
     for segment_reader in index_reader.segment_readers() {
-        if let Some(mut disi) = query.segment_matches(&segment_reader)? {
+        if let Some(mut disi) = query.segment_scorer(&segment_reader, &stats)? {
             collector.collect_for(segment_reader, &mut *disi)?;
         };
     }
@@ -32,7 +62,28 @@ pub fn search(
 
 pub trait Query: QueryClone + Debug + Sync {
     fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error>;
+    /// Like `segment_matches`, but the returned iterator also scores the matching docs. Queries
+    /// which do not score give all matches the same score.
+    fn segment_scorer(
+        &self,
+        reader: &SegmentReader,
+        _stats: &IndexStats,
+    ) -> Result<Option<Box<dyn DocIter>>, Error> {
+        self.segment_matches(reader)
+    }
+    /// Adds the segment statistics that the query needs for scoring.
+    fn collect_stats(&self, _reader: &SegmentReader, _stats: &mut IndexStats) -> Result<(), Error> {
+        Ok(())
+    }
     fn matches(&self, doc: &Doc) -> bool;
+
+    /// Finds the tokens of a field value which make the query match, as ranges of token
+    /// positions, so that they can be highlighted. Queries which do not match on the terms of
+    /// the field find nothing.
+    fn highlight_positions(&self, _field: &str, _tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        Vec::new()
+    }
+
     fn as_any(&self) -> &dyn Any;
 }
 
@@ -41,10 +92,26 @@ impl Query for Box<dyn Query> {
         self.as_ref().segment_matches(reader)
     }
 
+    fn segment_scorer(
+        &self,
+        reader: &SegmentReader,
+        stats: &IndexStats,
+    ) -> Result<Option<Box<dyn DocIter>>, Error> {
+        self.as_ref().segment_scorer(reader, stats)
+    }
+
+    fn collect_stats(&self, reader: &SegmentReader, stats: &mut IndexStats) -> Result<(), Error> {
+        self.as_ref().collect_stats(reader, stats)
+    }
+
     fn matches(&self, doc: &Doc) -> bool {
         self.as_ref().matches(doc)
     }
 
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        self.as_ref().highlight_positions(field, tokens)
+    }
+
     fn as_any(&self) -> &dyn Any {
         &*self
     }
@@ -98,20 +165,42 @@ impl<'a> ValueQuery {
 
 impl<'a> Query for ValueQuery {
     fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        self.segment_scorer(reader, &IndexStats::new())
+    }
+
+    fn segment_scorer(
+        &self,
+        reader: &SegmentReader,
+        stats: &IndexStats,
+    ) -> Result<Option<Box<dyn DocIter>>, Error> {
         match reader.string_index(&self.field, &NoopAnalyzer) {
             Some(index) => match index.doc_iter(&self.value)? {
-                Some(iter) => Ok(Some(Box::from(iter))),
+                Some(iter) => Ok(Some(term_scorer(
+                    iter,
+                    reader,
+                    &self.field,
+                    &NoopAnalyzer,
+                    &[&self.value],
+                    stats,
+                ))),
                 None => Ok(None),
             },
             None => Ok(None),
         }
     }
 
-    fn matches(&self, doc: &Doc) -> bool {
-        match doc.get(&self.field) {
-            Some(&FieldValue::String(ref val)) => &self.value == val,
-            None => false,
+    fn collect_stats(&self, reader: &SegmentReader, stats: &mut IndexStats) -> Result<(), Error> {
+        if let Some(index) = reader.string_index(&self.field, &NoopAnalyzer) {
+            stats.set_doc_freq(&self.field, &self.value, index.doc_freq(&self.value)?);
         }
+        collect_field_stats(reader, &self.field, &NoopAnalyzer, stats);
+        Ok(())
+    }
+
+    fn matches(&self, doc: &Doc) -> bool {
+        field_values(doc, &self.field)
+            .iter()
+            .any(|val| val.values().any(|v| v.to_text() == self.value))
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -138,22 +227,49 @@ impl TermQuery {
 
 impl Query for TermQuery {
     fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        self.segment_scorer(reader, &IndexStats::new())
+    }
+
+    fn segment_scorer(
+        &self,
+        reader: &SegmentReader,
+        stats: &IndexStats,
+    ) -> Result<Option<Box<dyn DocIter>>, Error> {
         match reader.string_index(&self.field, &*self.analyzer) {
             Some(index) => match index.doc_iter(&self.value)? {
-                Some(iter) => Ok(Some(Box::from(iter))),
+                Some(iter) => Ok(Some(term_scorer(
+                    iter,
+                    reader,
+                    &self.field,
+                    &*self.analyzer,
+                    &[&self.value],
+                    stats,
+                ))),
                 None => Ok(None),
             },
             None => Ok(None),
         }
     }
 
+    fn collect_stats(&self, reader: &SegmentReader, stats: &mut IndexStats) -> Result<(), Error> {
+        if let Some(index) = reader.string_index(&self.field, &*self.analyzer) {
+            stats.set_doc_freq(&self.field, &self.value, index.doc_freq(&self.value)?);
+        }
+        collect_field_stats(reader, &self.field, &*self.analyzer, stats);
+        Ok(())
+    }
+
     fn matches(&self, doc: &Doc) -> bool {
-        match doc.get(&self.field) {
-            Some(&FieldValue::String(ref val)) => {
-                self.analyzer.analyze(val).any(|t| t == self.value)
-            }
-            None => false,
+        field_values(doc, &self.field)
+            .iter()
+            .any(|val| self.analyzer.analyze_value(val).any(|t| t == self.value))
+    }
+
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        if field != self.field {
+            return Vec::new();
         }
+        accepted_positions(tokens, |token| token == self.value)
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -165,8 +281,12 @@ impl Query for TermQuery {
 pub struct TextQuery {
     field: String,
     values: Vec<String>,
+    /// The positions of the values relative to the first one, with gaps where the analyzer
+    /// removed tokens.
+    positions: Vec<Position>,
     //TODO: Cow<str> instead?
     analyzer: Box<dyn Analyzer>,
+    slop: u64,
 }
 
 impl TextQuery {
@@ -176,31 +296,103 @@ impl TextQuery {
         V: Into<String>,
     {
         let v = value.into();
-        let values = analyzer
-            .analyze(&v)
-            .map(|c| c.to_string())
-            .collect::<Vec<String>>();
+        let mut values = Vec::new();
+        let mut positions: Vec<Position> = Vec::new();
+        for token in analyzer.tokens(&v) {
+            let position = match positions.last() {
+                Some(&last) => last + Position::from(token.position_increment),
+                None => 0,
+            };
+            positions.push(position);
+            values.push(token.text.into_owned());
+        }
         TextQuery {
             field: field.into(),
             values,
+            positions,
             analyzer,
+            slop: 0,
+        }
+    }
+
+    /// Allows up to `slop` other positions in between the terms of a phrase, which must still
+    /// occur in order.
+    pub fn with_slop(mut self, slop: u64) -> TextQuery {
+        self.slop = slop;
+        self
+    }
+}
+
+impl TextQuery {
+    fn scorer<I, T>(
+        &self,
+        iter: I,
+        reader: &SegmentReader,
+        terms: &[T],
+        stats: &IndexStats,
+    ) -> Box<dyn DocIter>
+    where
+        I: TermFreqDocIter + 'static,
+        T: AsRef<str>,
+    {
+        term_scorer(iter, reader, &self.field, &*self.analyzer, terms, stats)
+    }
+
+    /// The positions of the tokens where the values occur as a phrase, by start position. Each
+    /// value must be at least as far after the one before as in the query, and the phrase may
+    /// be at most `slop` positions longer than in the query. Like `OrderedNearDocSpansIter`,
+    /// each value is taken at the first position it can be at.
+    fn phrase_matches(&self, tokens: &[Option<String>]) -> Vec<Vec<usize>> {
+        let mut matches = Vec::new();
+        if self.values.is_empty() {
+            return matches;
+        }
+        let length = self.positions[self.positions.len() - 1] as usize + 1;
+        let is_value = |pos: usize, i: usize| tokens[pos].as_ref() == Some(&self.values[i]);
+        for start in (0..tokens.len()).filter(|&start| is_value(start, 0)) {
+            let mut positions = vec![start];
+            for i in 1..self.values.len() {
+                let distance = (self.positions[i] - self.positions[i - 1]) as usize;
+                let from = positions[i - 1] + distance;
+                match (from..tokens.len()).find(|&pos| is_value(pos, i)) {
+                    Some(pos) => positions.push(pos),
+                    //Later starts can not find the value either
+                    None => return matches,
+                }
+            }
+            if positions[positions.len() - 1] + 1 - start - length <= self.slop as usize {
+                matches.push(positions);
+            }
         }
+        matches
     }
 }
 
 impl Query for TextQuery {
     fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        self.segment_scorer(reader, &IndexStats::new())
+    }
+
+    fn segment_scorer(
+        &self,
+        reader: &SegmentReader,
+        stats: &IndexStats,
+    ) -> Result<Option<Box<dyn DocIter>>, Error> {
+        //A text of stop words only has no values, and matches nothing
+        if self.values.is_empty() {
+            return Ok(None);
+        }
         if self.values.len() == 1 {
             if let Some(string_index_reader) = reader.string_index(&self.field, &*self.analyzer) {
                 match string_index_reader.doc_iter(&self.values[0])? {
-                    Some(iter) => Ok(Some(Box::from(iter))),
+                    Some(iter) => Ok(Some(self.scorer(iter, reader, &self.values, stats))),
                     None => Ok(None),
                 }
             } else if let Some(string_pos_index_reader) =
                 reader.string_pos_index(&self.field, &*self.analyzer)
             {
                 return match string_pos_index_reader.doc_spans_iter(&self.values[0])? {
-                    Some(iter) => Ok(Some(Box::from(iter))),
+                    Some(iter) => Ok(Some(self.scorer(iter, reader, &self.values, stats))),
                     None => Ok(None),
                 };
             } else if let Some(full_doc_reader) = reader.full_doc() {
@@ -228,48 +420,163 @@ impl Query for TextQuery {
                     return Ok(None);
                 }
             }
-            return Ok(Some(
-                Box::new(OrderedNearDocSpansIter::new(sub_spans)) as Box<dyn DocIter>
-            ));
+            let spans = OrderedNearDocSpansIter::with_slop(sub_spans, self.slop)
+                .with_offsets(self.positions.clone());
+            return Ok(Some(self.scorer(spans, reader, &self.values, stats)));
         } else if let Some(string_reader) = reader.string_index(&self.field, &*self.analyzer) {
             let mut sub: Vec<Box<dyn DocIter>> = Vec::with_capacity(self.values.len());
             for v in &self.values {
                 match string_reader.doc_iter(&v)? {
-                    Some(iter) => sub.push(Box::new(iter)),
+                    Some(iter) => sub.push(self.scorer(iter, reader, &[v], stats)),
                     None => return Ok(None),
                 };
             }
 
             let mut ids: Vec<DocId> = Vec::new();
+            let mut scores: Vec<f32> = Vec::new();
             if let Some(mut full_doc) = reader.full_doc().unwrap().cursor()? {
                 let mut all_iter = AllDocIter::new(sub);
                 while let Some(doc_id) = all_iter.next_doc()? {
                     if self.matches(&full_doc.read_doc(doc_id)?) {
                         ids.push(doc_id);
+                        scores.push(all_iter.score()?);
                     }
                 }
             }
-            return Ok(Some(Box::new(VecDocIter::new(ids))));
+            return Ok(Some(Box::new(VecDocIter::with_scores(ids, scores))));
         } else {
             //TODO fix
             panic!();
         }
     }
 
+    fn collect_stats(&self, reader: &SegmentReader, stats: &mut IndexStats) -> Result<(), Error> {
+        for v in &self.values {
+            if let Some(index) = reader.string_index(&self.field, &*self.analyzer) {
+                stats.set_doc_freq(&self.field, v, index.doc_freq(v)?);
+            } else if let Some(index) = reader.string_pos_index(&self.field, &*self.analyzer) {
+                stats.set_doc_freq(&self.field, v, index.doc_freq(v)?);
+            }
+        }
+        collect_field_stats(reader, &self.field, &*self.analyzer, stats);
+        Ok(())
+    }
+
     fn matches(&self, doc: &Doc) -> bool {
-        match doc.get(&self.field) {
-            Some(&FieldValue::String(ref val)) => {
-                let doc_vals = self
-                    .analyzer
-                    .analyze(val)
-                    .map(|c| c.to_string())
-                    .collect::<Vec<String>>();
-                doc_vals
-                    .windows(self.values.len())
-                    .any(|t| t == self.values.as_slice())
+        let doc_vals = token_positions(&*self.analyzer, &field_values(doc, &self.field));
+        !self.phrase_matches(&doc_vals).is_empty()
+    }
+
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        if field != self.field || self.values.is_empty() {
+            return Vec::new();
+        }
+        let matches = self.phrase_matches(tokens).into_iter();
+        if self.slop == 0 {
+            matches
+                .map(|positions| positions[0]..positions[positions.len() - 1] + 1)
+                .collect()
+        } else {
+            matches.flatten().map(|pos| pos..pos + 1).collect()
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Matches docs where the terms occur near each other, with at most `slop` other positions in
+/// between them. Unless `in_order` is false, the terms must occur in the given order. Needs a
+/// `StringPosIndex` for the field.
+#[derive(Debug, Clone)]
+pub struct SpanNearQuery {
+    field: String,
+    terms: Vec<String>,
+    slop: u64,
+    in_order: bool,
+    analyzer: Box<dyn Analyzer>,
+}
+
+impl SpanNearQuery {
+    pub fn new<N>(
+        field: N,
+        terms: Vec<String>,
+        slop: u64,
+        analyzer: Box<dyn Analyzer>,
+    ) -> SpanNearQuery
+    where
+        N: Into<String>,
+    {
+        SpanNearQuery {
+            field: field.into(),
+            terms,
+            slop,
+            in_order: true,
+            analyzer,
+        }
+    }
+
+    pub fn in_order(mut self, in_order: bool) -> SpanNearQuery {
+        self.in_order = in_order;
+        self
+    }
+}
+
+impl Query for SpanNearQuery {
+    fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        self.segment_scorer(reader, &IndexStats::new())
+    }
+
+    fn segment_scorer(
+        &self,
+        reader: &SegmentReader,
+        stats: &IndexStats,
+    ) -> Result<Option<Box<dyn DocIter>>, Error> {
+        let index = match reader.string_pos_index(&self.field, &*self.analyzer) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        if self.terms.is_empty() {
+            return Ok(None);
+        }
+        let mut sub_spans = Vec::with_capacity(self.terms.len());
+        for term in &self.terms {
+            match index.doc_spans_iter(term)? {
+                Some(sub_span) => sub_spans.push(Box::new(sub_span) as Box<dyn DocSpansIter>),
+                None => return Ok(None),
+            }
+        }
+        let (field, analyzer) = (&self.field, &*self.analyzer);
+        Ok(Some(if self.in_order {
+            let iter = OrderedNearDocSpansIter::with_slop(sub_spans, self.slop);
+            term_scorer(iter, reader, field, analyzer, &self.terms, stats)
+        } else {
+            let iter = UnorderedNearDocSpansIter::new(sub_spans, self.slop);
+            term_scorer(iter, reader, field, analyzer, &self.terms, stats)
+        }))
+    }
+
+    fn collect_stats(&self, reader: &SegmentReader, stats: &mut IndexStats) -> Result<(), Error> {
+        if let Some(index) = reader.string_pos_index(&self.field, &*self.analyzer) {
+            for term in &self.terms {
+                stats.set_doc_freq(&self.field, term, index.doc_freq(term)?);
             }
-            None => false,
         }
+        collect_field_stats(reader, &self.field, &*self.analyzer, stats);
+        Ok(())
+    }
+
+    fn matches(&self, doc: &Doc) -> bool {
+        let tokens = token_positions(&*self.analyzer, &field_values(doc, &self.field));
+        near_match(&tokens, &self.terms, self.slop, self.in_order)
+    }
+
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        if field != self.field {
+            return Vec::new();
+        }
+        near_positions(tokens, &self.terms, self.slop, self.in_order)
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -277,6 +584,73 @@ impl Query for TextQuery {
     }
 }
 
+/// The tokens of field values by position, where the gaps between values are empty.
+fn token_positions(analyzer: &dyn Analyzer, values: &[&FieldValue]) -> Vec<Option<String>> {
+    let tokens = positioned_tokens(analyzer, values);
+    let mut positions = match tokens.last() {
+        Some(&(pos, _)) => vec![None; pos as usize + 1],
+        None => Vec::new(),
+    };
+    for (pos, token) in tokens {
+        positions[pos as usize] = Some(token.text.into_owned());
+    }
+    positions
+}
+
+/// Whether the terms occur within a window of the tokens which is at most `slop` positions
+/// longer than the number of terms. Each term needs its own position in the window.
+fn near_match(tokens: &[Option<String>], terms: &[String], slop: u64, in_order: bool) -> bool {
+    near_windows(tokens, terms, slop, in_order).next().is_some()
+}
+
+/// The positions of the terms within the windows that `near_match` finds.
+fn near_positions(
+    tokens: &[Option<String>],
+    terms: &[String],
+    slop: u64,
+    in_order: bool,
+) -> Vec<Range<usize>> {
+    near_windows(tokens, terms, slop, in_order)
+        .flatten()
+        .filter(|&pos| tokens[pos].as_ref().is_some_and(|t| terms.contains(t)))
+        .map(|pos| pos..pos + 1)
+        .collect()
+}
+
+/// The windows of the tokens in which the terms occur near each other, by start position.
+fn near_windows<'a>(
+    tokens: &'a [Option<String>],
+    terms: &'a [String],
+    slop: u64,
+    in_order: bool,
+) -> impl Iterator<Item = Range<usize>> + 'a {
+    let width = terms.len() + slop as usize;
+    let starts = if terms.is_empty() { 0 } else { tokens.len() };
+    (0..starts)
+        .map(move |start| start..tokens.len().min(start + width))
+        .filter(move |range| {
+            let window = &tokens[range.clone()];
+            if in_order {
+                let mut window_tokens = window.iter();
+                terms
+                    .iter()
+                    .all(|term| window_tokens.any(|token| token.as_ref() == Some(term)))
+            } else {
+                let mut window_tokens: Vec<&String> =
+                    window.iter().filter_map(|t| t.as_ref()).collect();
+                terms.iter().all(|term| {
+                    match window_tokens.iter().position(|token| *token == term) {
+                        Some(i) => {
+                            window_tokens.swap_remove(i);
+                            true
+                        }
+                        None => false,
+                    }
+                })
+            }
+        })
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct MatchAllDocsQuery;
 
@@ -323,6 +697,28 @@ impl Query for AllQuery {
         Ok(Some(Box::new(AllDocIter::new(sub))))
     }
 
+    fn segment_scorer(
+        &self,
+        reader: &SegmentReader,
+        stats: &IndexStats,
+    ) -> Result<Option<Box<dyn DocIter>>, Error> {
+        let mut sub: Vec<Box<dyn DocIter>> = Vec::with_capacity(self.queries.len());
+        for q in &self.queries {
+            match q.segment_scorer(reader, stats)? {
+                Some(sub_iter) => sub.push(sub_iter),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(Box::new(AllDocIter::new(sub))))
+    }
+
+    fn collect_stats(&self, reader: &SegmentReader, stats: &mut IndexStats) -> Result<(), Error> {
+        for q in &self.queries {
+            q.collect_stats(reader, stats)?;
+        }
+        Ok(())
+    }
+
     fn matches(&self, doc: &Doc) -> bool {
         for q in &self.queries {
             if !q.matches(doc) {
@@ -332,66 +728,1301 @@ impl Query for AllQuery {
         true
     }
 
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        self.queries
+            .iter()
+            .flat_map(|q| q.highlight_positions(field, tokens))
+            .collect()
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
 
-pub trait Collector: Sync {
-    fn collect_for(&mut self, reader: &SegmentReader, docs: &mut dyn DocIter) -> Result<(), Error>;
+/// Combines queries into one, like the bool query of Elasticsearch.
+///
+/// Docs must match all `must` and `filter` clauses, at least `minimum_should_match` of the
+/// `should` clauses and none of the `must_not` clauses. Only `must` and `should` clauses
+/// contribute to the score. Unless set, `minimum_should_match` is 1 if there are `should` but no
+/// `must` or `filter` clauses, otherwise 0. A query without `must`, `filter` or `should` clauses
+/// matches all docs not excluded by `must_not`.
+#[derive(Debug, Clone, Default)]
+pub struct BooleanQuery {
+    must: Vec<Box<dyn Query>>,
+    should: Vec<Box<dyn Query>>,
+    must_not: Vec<Box<dyn Query>>,
+    filter: Vec<Box<dyn Query>>,
+    minimum_should_match: Option<usize>,
 }
 
-#[derive(Default)]
-pub struct CountCollector {
-    count: u64,
-}
+impl BooleanQuery {
+    pub fn new() -> BooleanQuery {
+        BooleanQuery::default()
+    }
 
-impl CountCollector {
-    pub fn new() -> CountCollector {
-        CountCollector { count: 0 }
+    pub fn must(mut self, query: Box<dyn Query>) -> BooleanQuery {
+        self.must.push(query);
+        self
     }
 
-    pub fn total_count(&self) -> u64 {
-        self.count
+    pub fn should(mut self, query: Box<dyn Query>) -> BooleanQuery {
+        self.should.push(query);
+        self
+    }
+
+    pub fn must_not(mut self, query: Box<dyn Query>) -> BooleanQuery {
+        self.must_not.push(query);
+        self
+    }
+
+    pub fn filter(mut self, query: Box<dyn Query>) -> BooleanQuery {
+        self.filter.push(query);
+        self
+    }
+
+    pub fn minimum_should_match(mut self, minimum_should_match: usize) -> BooleanQuery {
+        self.minimum_should_match = Some(minimum_should_match);
+        self
+    }
+
+    fn min_should_match(&self) -> usize {
+        match self.minimum_should_match {
+            Some(min) => min,
+            None if self.must.is_empty() && self.filter.is_empty() && !self.should.is_empty() => 1,
+            None => 0,
+        }
+    }
+
+    fn scorer(
+        &self,
+        reader: &SegmentReader,
+        stats: Option<&IndexStats>,
+    ) -> Result<Option<Box<dyn DocIter>>, Error> {
+        let sub_iter = |q: &dyn Query| match stats {
+            Some(stats) => q.segment_scorer(reader, stats),
+            None => q.segment_matches(reader),
+        };
+        let mut required: Vec<Box<dyn DocIter>> = Vec::new();
+        for q in &self.must {
+            match sub_iter(q.as_ref())? {
+                Some(iter) => required.push(iter),
+                None => return Ok(None),
+            }
+        }
+        for q in &self.filter {
+            match q.segment_matches(reader)? {
+                Some(iter) => required.push(Box::new(ConstantScoreDocIter::new(iter, 0.0))),
+                None => return Ok(None),
+            }
+        }
+        let mut optional: Vec<Box<dyn DocIter>> = Vec::new();
+        for q in &self.should {
+            if let Some(iter) = sub_iter(q.as_ref())? {
+                optional.push(iter);
+            }
+        }
+        let min_should_match = self.min_should_match();
+        if optional.len() < min_should_match {
+            return Ok(None);
+        }
+
+        if required.is_empty() && min_should_match == 0 {
+            required.push(Box::new(AllDocsDocIter::new(reader.info().doc_count)));
+        }
+
+        let positive: Box<dyn DocIter> = if required.is_empty() {
+            Box::new(AnyDocIter::new(optional, min_should_match))
+        } else {
+            let required: Box<dyn DocIter> = if required.len() == 1 {
+                required.pop().unwrap()
+            } else {
+                Box::new(AllDocIter::new(required))
+            };
+            if optional.is_empty() {
+                required
+            } else if min_should_match > 0 {
+                let optional = Box::new(AnyDocIter::new(optional, min_should_match));
+                Box::new(AllDocIter::new(vec![required, optional]))
+            } else {
+                let optional = Box::new(AnyDocIter::new(optional, 1));
+                Box::new(ReqOptDocIter::new(required, optional))
+            }
+        };
+
+        let mut excluded: Vec<Box<dyn DocIter>> = Vec::new();
+        for q in &self.must_not {
+            if let Some(iter) = q.segment_matches(reader)? {
+                excluded.push(iter);
+            }
+        }
+        if excluded.is_empty() {
+            Ok(Some(positive))
+        } else {
+            let excluded = Box::new(AnyDocIter::new(excluded, 1));
+            Ok(Some(Box::new(ExcludeDocIter::new(positive, excluded))))
+        }
     }
 }
 
-impl Collector for CountCollector {
-    fn collect_for(&mut self, _reader: &SegmentReader, docs: &mut dyn DocIter) -> Result<(), Error> {
-        let mut i = 0u64;
-        while let Some(_doc_id) = docs.next_doc()? {
-            i += 1;
+impl Query for BooleanQuery {
+    fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        self.scorer(reader, None)
+    }
+
+    fn segment_scorer(
+        &self,
+        reader: &SegmentReader,
+        stats: &IndexStats,
+    ) -> Result<Option<Box<dyn DocIter>>, Error> {
+        self.scorer(reader, Some(stats))
+    }
+
+    fn collect_stats(&self, reader: &SegmentReader, stats: &mut IndexStats) -> Result<(), Error> {
+        for q in self.must.iter().chain(self.should.iter()) {
+            q.collect_stats(reader, stats)?;
         }
-        self.count += i;
         Ok(())
     }
+
+    fn matches(&self, doc: &Doc) -> bool {
+        if !self
+            .must
+            .iter()
+            .chain(self.filter.iter())
+            .all(|q| q.matches(doc))
+        {
+            return false;
+        }
+        if self.must_not.iter().any(|q| q.matches(doc)) {
+            return false;
+        }
+        self.should.iter().filter(|q| q.matches(doc)).count() >= self.min_should_match()
+    }
+
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        self.must
+            .iter()
+            .chain(self.should.iter())
+            .chain(self.filter.iter())
+            .flat_map(|q| q.highlight_positions(field, tokens))
+            .collect()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
 
-#[derive(Default)]
-pub struct AllDocsCollector {
-    docs: Vec<Doc>,
+/// Matches docs with a term starting with the prefix. All matching docs get the same score.
+#[derive(Debug, Clone)]
+pub struct PrefixQuery {
+    field: String,
+    prefix: String,
+    analyzer: Box<dyn Analyzer>,
 }
 
-impl AllDocsCollector {
-    pub fn new() -> AllDocsCollector {
-        AllDocsCollector { docs: Vec::new() }
+impl PrefixQuery {
+    pub fn new<N, V>(field: N, prefix: V, analyzer: Box<dyn Analyzer>) -> PrefixQuery
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        PrefixQuery {
+            field: field.into(),
+            prefix: prefix.into(),
+            analyzer,
+        }
     }
+}
 
-    pub fn docs(&self) -> &[Doc] {
-        &self.docs
+impl Query for PrefixQuery {
+    fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        let automaton = Str::new(&self.prefix).starts_with();
+        multi_term_matches(reader, &self.field, &*self.analyzer, &automaton)
+    }
+
+    fn matches(&self, doc: &Doc) -> bool {
+        let automaton = Str::new(&self.prefix).starts_with();
+        multi_term_doc_matches(doc, &self.field, &*self.analyzer, &automaton)
+    }
+
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        if field != self.field {
+            return Vec::new();
+        }
+        let automaton = Str::new(&self.prefix).starts_with();
+        accepted_positions(tokens, |token| automaton_accepts(&automaton, token))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
     }
 }
 
-impl Collector for AllDocsCollector {
-    fn collect_for(&mut self, reader: &SegmentReader, docs: &mut dyn DocIter) -> Result<(), Error> {
-        if let Some(mut doc_cursor) = reader.full_doc().unwrap().cursor()? {
-            while let Some(doc_id) = docs.next_doc().unwrap() {
-                if !reader.deleted_docs().get(doc_id as usize).unwrap_or(false) {
-                    let doc = doc_cursor.read_doc(doc_id).unwrap();
-                    self.docs.push(doc);
+/// Matches docs with a term matching a wildcard pattern, where `*` matches any number of
+/// characters and `?` matches a single character. All matching docs get the same score.
+#[derive(Debug, Clone)]
+pub struct WildcardQuery {
+    field: String,
+    pattern: String,
+    analyzer: Box<dyn Analyzer>,
+    dfa: DenseDFA<Vec<usize>, usize>,
+}
+
+impl WildcardQuery {
+    pub fn new<N, V>(
+        field: N,
+        pattern: V,
+        analyzer: Box<dyn Analyzer>,
+    ) -> Result<WildcardQuery, Error>
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        let pattern = pattern.into();
+        let mut regex = String::with_capacity(pattern.len());
+        for c in pattern.chars() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                _ => {
+                    if regex_syntax_char(c) {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
                 }
             }
         }
+        Ok(WildcardQuery {
+            field: field.into(),
+            dfa: term_dfa(&regex)?,
+            pattern,
+            analyzer,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl Query for WildcardQuery {
+    fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        multi_term_matches(reader, &self.field, &*self.analyzer, &self.dfa)
+    }
+
+    fn matches(&self, doc: &Doc) -> bool {
+        multi_term_doc_matches(doc, &self.field, &*self.analyzer, &self.dfa)
+    }
+
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        if field != self.field {
+            return Vec::new();
+        }
+        accepted_positions(tokens, |token| automaton_accepts(&self.dfa, token))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Matches docs with a term which the regular expression matches in full. Anchors and other
+/// zero-width assertions are not supported. All matching docs get the same score.
+#[derive(Debug, Clone)]
+pub struct RegexQuery {
+    field: String,
+    pattern: String,
+    analyzer: Box<dyn Analyzer>,
+    dfa: DenseDFA<Vec<usize>, usize>,
+}
+
+impl RegexQuery {
+    pub fn new<N, V>(field: N, pattern: V, analyzer: Box<dyn Analyzer>) -> Result<RegexQuery, Error>
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        let pattern = pattern.into();
+        Ok(RegexQuery {
+            field: field.into(),
+            dfa: term_dfa(&pattern)?,
+            pattern,
+            analyzer,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl Query for RegexQuery {
+    fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        multi_term_matches(reader, &self.field, &*self.analyzer, &self.dfa)
+    }
+
+    fn matches(&self, doc: &Doc) -> bool {
+        multi_term_doc_matches(doc, &self.field, &*self.analyzer, &self.dfa)
+    }
+
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        if field != self.field {
+            return Vec::new();
+        }
+        accepted_positions(tokens, |token| automaton_accepts(&self.dfa, token))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Matches docs with a term within `max_edits` insertions, deletions or substitutions of the
+/// given term. The first `prefix_length` characters must match exactly, which also makes the
+/// query a lot cheaper to run. All matching docs get the same score.
+#[derive(Debug, Clone)]
+pub struct FuzzyQuery {
+    field: String,
+    term: String,
+    max_edits: u32,
+    prefix_length: usize,
+    analyzer: Box<dyn Analyzer>,
+}
+
+impl FuzzyQuery {
+    pub fn new<N, V>(field: N, term: V, max_edits: u32, analyzer: Box<dyn Analyzer>) -> FuzzyQuery
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        FuzzyQuery {
+            field: field.into(),
+            term: term.into(),
+            max_edits,
+            prefix_length: 0,
+            analyzer,
+        }
+    }
+
+    pub fn with_prefix_length(mut self, prefix_length: usize) -> FuzzyQuery {
+        self.prefix_length = prefix_length;
+        self
+    }
+
+    fn split_term(&self) -> (&str, &str) {
+        let split = self
+            .term
+            .char_indices()
+            .nth(self.prefix_length)
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| self.term.len());
+        self.term.split_at(split)
+    }
+
+    fn within_edits(&self, term: &str) -> bool {
+        let (prefix, suffix) = self.split_term();
+        term.starts_with(prefix)
+            && edit_distance(&term[prefix.len()..], suffix) <= self.max_edits as usize
+    }
+}
+
+impl Query for FuzzyQuery {
+    fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        let (prefix, suffix) = self.split_term();
+        let automaton = PrefixedAutomaton {
+            prefix: prefix.as_bytes(),
+            inner: Levenshtein::new(suffix, self.max_edits)?,
+        };
+        multi_term_matches(reader, &self.field, &*self.analyzer, &automaton)
+    }
+
+    fn matches(&self, doc: &Doc) -> bool {
+        field_values(doc, &self.field).iter().any(|val| {
+            self.analyzer
+                .analyze_value(val)
+                .any(|t| self.within_edits(&t))
+        })
+    }
+
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        if field != self.field {
+            return Vec::new();
+        }
+        accepted_positions(tokens, |token| self.within_edits(token))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Accepts terms starting with an exact prefix, followed by a suffix accepted by the inner
+/// automaton.
+struct PrefixedAutomaton<'a, A> {
+    prefix: &'a [u8],
+    inner: A,
+}
+
+#[derive(Clone)]
+enum PrefixedState<S> {
+    Prefix(usize),
+    Inner(S),
+    Dead,
+}
+
+impl<'a, A: Automaton> Automaton for PrefixedAutomaton<'a, A> {
+    type State = PrefixedState<A::State>;
+
+    fn start(&self) -> Self::State {
+        if self.prefix.is_empty() {
+            PrefixedState::Inner(self.inner.start())
+        } else {
+            PrefixedState::Prefix(0)
+        }
+    }
+
+    fn is_match(&self, state: &Self::State) -> bool {
+        match *state {
+            PrefixedState::Inner(ref inner) => self.inner.is_match(inner),
+            _ => false,
+        }
+    }
+
+    fn can_match(&self, state: &Self::State) -> bool {
+        match *state {
+            PrefixedState::Prefix(_) => true,
+            PrefixedState::Inner(ref inner) => self.inner.can_match(inner),
+            PrefixedState::Dead => false,
+        }
+    }
+
+    fn accept(&self, state: &Self::State, byte: u8) -> Self::State {
+        match *state {
+            PrefixedState::Prefix(i) if self.prefix[i] == byte => {
+                if i + 1 == self.prefix.len() {
+                    PrefixedState::Inner(self.inner.start())
+                } else {
+                    PrefixedState::Prefix(i + 1)
+                }
+            }
+            PrefixedState::Inner(ref inner) => PrefixedState::Inner(self.inner.accept(inner, byte)),
+            _ => PrefixedState::Dead,
+        }
+    }
+}
+
+/// The Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut distances: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut previous = distances[0];
+        distances[0] = i + 1;
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution = previous + if a_char == b_char { 0 } else { 1 };
+            previous = distances[j + 1];
+            distances[j + 1] = substitution.min(distances[j] + 1).min(previous + 1);
+        }
+    }
+    distances[b.len()]
+}
+
+fn term_dfa(pattern: &str) -> Result<DenseDFA<Vec<usize>, usize>, Error> {
+    //Anchored and longest match, so that a term is accepted only if the whole term matches
+    Ok(dense::Builder::new()
+        .anchored(true)
+        .longest_match(true)
+        .build(pattern)?)
+}
+
+fn regex_syntax_char(c: char) -> bool {
+    "\\.+*?()|[]{}^$#&-~".contains(c)
+}
+
+/// Matches the docs containing any of the terms that the automaton accepts.
+fn multi_term_matches<A: Automaton>(
+    reader: &SegmentReader,
+    field: &str,
+    analyzer: &dyn Analyzer,
+    automaton: A,
+) -> Result<Option<Box<dyn DocIter>>, Error> {
+    terms_union(
+        reader,
+        field,
+        analyzer,
+        |index| index.matching_terms(&automaton),
+        |index| index.matching_terms(&automaton),
+    )
+}
+
+/// Matches the docs containing any of the selected terms, by collecting their postings into a
+/// bit set. That way only one postings file is open at a time, no matter how many terms match.
+fn terms_union<F, G>(
+    reader: &SegmentReader,
+    field: &str,
+    analyzer: &dyn Analyzer,
+    index_terms: F,
+    pos_index_terms: G,
+) -> Result<Option<Box<dyn DocIter>>, Error>
+where
+    F: Fn(&StringIndexReader) -> Vec<String>,
+    G: Fn(&StringPosIndexReader) -> Vec<String>,
+{
+    let mut docs = BitVec::from_elem(reader.info().doc_count as usize, false);
+    if let Some(index) = reader.string_index(field, analyzer) {
+        for term in index_terms(index) {
+            if let Some(mut iter) = index.doc_iter(&term)? {
+                while let Some(doc_id) = iter.next_doc()? {
+                    docs.set(doc_id as usize, true);
+                }
+            }
+        }
+    } else if let Some(index) = reader.string_pos_index(field, analyzer) {
+        for term in pos_index_terms(index) {
+            if let Some(mut iter) = index.doc_spans_iter(&term)? {
+                while let Some(doc_id) = iter.next_doc()? {
+                    docs.set(doc_id as usize, true);
+                }
+            }
+        }
+    } else {
+        return Ok(None);
+    }
+    let doc_ids: Vec<DocId> = docs
+        .iter()
+        .enumerate()
+        .filter(|(_, matches)| *matches)
+        .map(|(doc_id, _)| doc_id as DocId)
+        .collect();
+    if doc_ids.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(Box::new(VecDocIter::new(doc_ids))))
+    }
+}
+
+fn multi_term_doc_matches<A: Automaton>(
+    doc: &Doc,
+    field: &str,
+    analyzer: &dyn Analyzer,
+    automaton: &A,
+) -> bool {
+    field_values(doc, field).iter().any(|val| {
+        analyzer
+            .analyze_value(val)
+            .any(|t| automaton_accepts(automaton, &t))
+    })
+}
+
+/// The positions of the tokens which are accepted, one range each.
+fn accepted_positions<F>(tokens: &[Option<String>], accepts: F) -> Vec<Range<usize>>
+where
+    F: Fn(&str) -> bool,
+{
+    tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| token.as_ref().is_some_and(|token| accepts(token)))
+        .map(|(pos, _)| pos..pos + 1)
+        .collect()
+}
+
+fn automaton_accepts<A: Automaton>(automaton: &A, term: &str) -> bool {
+    let mut state = automaton.start();
+    for &b in term.as_bytes() {
+        if !automaton.can_match(&state) {
+            return false;
+        }
+        state = automaton.accept(&state, b);
+    }
+    automaton.is_match(&state)
+}
+
+fn term_scorer<I, T>(
+    iter: I,
+    reader: &SegmentReader,
+    field: &str,
+    analyzer: &dyn Analyzer,
+    terms: &[T],
+    stats: &IndexStats,
+) -> Box<dyn DocIter>
+where
+    I: TermFreqDocIter + 'static,
+    T: AsRef<str>,
+{
+    let norms = reader.field_norms(field, analyzer).cloned();
+    Box::new(TermScorer::new(iter, stats.weight(field, terms)).with_norms(norms))
+}
+
+fn collect_field_stats(
+    reader: &SegmentReader,
+    field: &str,
+    analyzer: &dyn Analyzer,
+    stats: &mut IndexStats,
+) {
+    if let Some(norms) = reader.field_norms(field, analyzer) {
+        stats.set_field_lengths(field, norms);
+    }
+}
+
+/// Matches docs with a term between the bounds, in byte order. The bounds are not analyzed. All
+/// matching docs get the same score.
+#[derive(Debug, Clone)]
+pub struct TermRangeQuery {
+    field: String,
+    lower: Bound<String>,
+    upper: Bound<String>,
+    analyzer: Box<dyn Analyzer>,
+}
+
+impl TermRangeQuery {
+    pub fn new<N>(
+        field: N,
+        lower: Bound<String>,
+        upper: Bound<String>,
+        analyzer: Box<dyn Analyzer>,
+    ) -> TermRangeQuery
+    where
+        N: Into<String>,
+    {
+        TermRangeQuery {
+            field: field.into(),
+            lower,
+            upper,
+            analyzer,
+        }
+    }
+
+    fn in_range(&self, term: &str) -> bool {
+        let above_lower = match self.lower {
+            Bound::Included(ref lower) => term >= lower.as_str(),
+            Bound::Excluded(ref lower) => term > lower.as_str(),
+            Bound::Unbounded => true,
+        };
+        let below_upper = match self.upper {
+            Bound::Included(ref upper) => term <= upper.as_str(),
+            Bound::Excluded(ref upper) => term < upper.as_str(),
+            Bound::Unbounded => true,
+        };
+        above_lower && below_upper
+    }
+}
+
+fn bound_str(bound: &Bound<String>) -> Bound<&str> {
+    match *bound {
+        Bound::Included(ref term) => Bound::Included(term),
+        Bound::Excluded(ref term) => Bound::Excluded(term),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+impl Query for TermRangeQuery {
+    fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        let (lower, upper) = (bound_str(&self.lower), bound_str(&self.upper));
+        terms_union(
+            reader,
+            &self.field,
+            &*self.analyzer,
+            |index| index.terms_in_range(lower, upper),
+            |index| index.terms_in_range(lower, upper),
+        )
+    }
+
+    fn matches(&self, doc: &Doc) -> bool {
+        field_values(doc, &self.field)
+            .iter()
+            .any(|val| self.analyzer.analyze_value(val).any(|t| self.in_range(&t)))
+    }
+
+    fn highlight_positions(&self, field: &str, tokens: &[Option<String>]) -> Vec<Range<usize>> {
+        if field != self.field {
+            return Vec::new();
+        }
+        accepted_positions(tokens, |token| self.in_range(token))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Matches docs with a numeric value within a range, using a numeric points feature of the
+/// field with the same numeric type. All matching docs get the same score.
+#[derive(Debug, Clone)]
+pub struct RangeQuery {
+    field: String,
+    numeric_type: NumericType,
+    lower: Bound<i64>,
+    upper: Bound<i64>,
+}
+
+impl RangeQuery {
+    /// Matches integer values within the bounds.
+    pub fn int<F: Into<String>>(field: F, lower: Bound<i64>, upper: Bound<i64>) -> RangeQuery {
+        RangeQuery {
+            field: field.into(),
+            numeric_type: NumericType::Int,
+            lower,
+            upper,
+        }
+    }
+
+    /// Matches both integer and float values within the bounds, which can not be NaN.
+    pub fn float<F: Into<String>>(
+        field: F,
+        lower: Bound<f64>,
+        upper: Bound<f64>,
+    ) -> Result<RangeQuery, Error> {
+        let encode = |bound: Bound<f64>| match bound {
+            Bound::Included(value) | Bound::Excluded(value) if value.is_nan() => Err(
+                Error::InvalidConfig("NaN can not be the bound of a range".to_string()),
+            ),
+            Bound::Included(value) => Ok(Bound::Included(float_stored(value))),
+            Bound::Excluded(value) => Ok(Bound::Excluded(float_stored(value))),
+            Bound::Unbounded => Ok(Bound::Unbounded),
+        };
+        Ok(RangeQuery {
+            field: field.into(),
+            numeric_type: NumericType::Float,
+            lower: encode(lower)?,
+            upper: encode(upper)?,
+        })
+    }
+
+    /// The smallest and largest matching stored values, if any value can match.
+    fn stored_range(&self) -> Option<(i64, i64)> {
+        let min = match self.lower {
+            Bound::Included(value) => value,
+            Bound::Excluded(value) => value.checked_add(1)?,
+            Bound::Unbounded => i64::MIN,
+        };
+        let max = match self.upper {
+            Bound::Included(value) => value,
+            Bound::Excluded(value) => value.checked_sub(1)?,
+            Bound::Unbounded => i64::MAX,
+        };
+        if min <= max {
+            Some((min, max))
+        } else {
+            None
+        }
+    }
+}
+
+fn float_stored(value: f64) -> i64 {
+    NumericType::Float
+        .encode(&FieldValue::Float(value))
+        .unwrap()
+}
+
+impl Query for RangeQuery {
+    fn segment_matches(&self, reader: &SegmentReader) -> Result<Option<Box<dyn DocIter>>, Error> {
+        let (min, max) = match self.stored_range() {
+            Some(range) => range,
+            None => return Ok(None),
+        };
+        match reader.numeric_points(&self.field, self.numeric_type) {
+            Some(points) => {
+                let doc_ids = points.doc_ids(min, max);
+                if doc_ids.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(Box::new(VecDocIter::new(doc_ids))))
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn matches(&self, doc: &Doc) -> bool {
+        let (min, max) = match self.stored_range() {
+            Some(range) => range,
+            None => return false,
+        };
+        field_values(doc, &self.field).iter().any(|val| {
+            val.values()
+                .filter_map(|v| self.numeric_type.encode(v))
+                .any(|v| min <= v && v <= max)
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub trait Collector: Sync {
+    fn collect_for(&mut self, reader: &SegmentReader, docs: &mut dyn DocIter) -> Result<(), Error>;
+}
+
+#[derive(Default)]
+pub struct CountCollector {
+    count: u64,
+}
+
+impl CountCollector {
+    pub fn new() -> CountCollector {
+        CountCollector { count: 0 }
+    }
+
+    pub fn total_count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl Collector for CountCollector {
+    fn collect_for(&mut self, _reader: &SegmentReader, docs: &mut dyn DocIter) -> Result<(), Error> {
+        let mut i = 0u64;
+        while let Some(_doc_id) = docs.next_doc()? {
+            i += 1;
+        }
+        self.count += i;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct AllDocsCollector {
+    docs: Vec<Doc>,
+}
+
+impl AllDocsCollector {
+    pub fn new() -> AllDocsCollector {
+        AllDocsCollector { docs: Vec::new() }
+    }
+
+    pub fn docs(&self) -> &[Doc] {
+        &self.docs
+    }
+}
+
+impl Collector for AllDocsCollector {
+    fn collect_for(&mut self, reader: &SegmentReader, docs: &mut dyn DocIter) -> Result<(), Error> {
+        if let Some(mut doc_cursor) = reader.full_doc().unwrap().cursor()? {
+            while let Some(doc_id) = docs.next_doc().unwrap() {
+                if !reader.deleted_docs().get(doc_id as usize).unwrap_or(false) {
+                    let doc = doc_cursor.read_doc(doc_id).unwrap();
+                    self.docs.push(doc);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A matching doc, identified by its segment and id within that segment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub segment: SegmentAddress,
+    pub doc_id: DocId,
+    pub score: f32,
+}
+
+type SortKey = dyn Fn(&SegmentReader, DocId, f32) -> f32 + Sync + Send;
+
+/// Keeps the best hits, by score or by a provided key, without loading any documents.
+pub struct TopDocsCollector {
+    size: usize,
+    key: Option<Box<SortKey>>,
+    heap: BinaryHeap<TopDocsEntry>,
+    total_hits: u64,
+}
+
+impl TopDocsCollector {
+    pub fn new(size: usize) -> TopDocsCollector {
+        TopDocsCollector {
+            size,
+            key: None,
+            heap: BinaryHeap::with_capacity(size + 1),
+            total_hits: 0,
+        }
+    }
+
+    /// Keeps the hits with the highest keys, as computed from the segment, doc id and score.
+    pub fn with_key<F>(size: usize, key: F) -> TopDocsCollector
+    where
+        F: Fn(&SegmentReader, DocId, f32) -> f32 + Sync + Send + 'static,
+    {
+        TopDocsCollector {
+            size,
+            key: Some(Box::new(key)),
+            heap: BinaryHeap::with_capacity(size + 1),
+            total_hits: 0,
+        }
+    }
+
+    pub fn total_hits(&self) -> u64 {
+        self.total_hits
+    }
+
+    /// The best hits, best first.
+    pub fn hits(&self) -> Vec<Hit> {
+        self.heap
+            .clone()
+            .into_sorted_vec()
+            .into_iter()
+            .map(|entry| entry.hit)
+            .collect()
+    }
+
+    /// Loads the stored documents of the best hits, best first.
+    pub fn docs(&self, index_reader: &ManagedIndexReader) -> Result<Vec<Doc>, Error> {
+        load_docs(index_reader, &self.hits())
+    }
+}
+
+/// Loads the stored documents of hits, in the order of the hits.
+fn load_docs(index_reader: &ManagedIndexReader, hits: &[Hit]) -> Result<Vec<Doc>, Error> {
+    let mut by_segment: HashMap<&SegmentAddress, Vec<(usize, DocId)>> = HashMap::new();
+    for (i, hit) in hits.iter().enumerate() {
+        by_segment
+            .entry(&hit.segment)
+            .or_default()
+            .push((i, hit.doc_id));
+    }
+    let mut docs: Vec<Option<Doc>> = vec![None; hits.len()];
+    for reader in index_reader.segment_readers() {
+        if let Some(segment_hits) = by_segment.get_mut(&reader.info().address) {
+            //Cursors can only read forward within a block
+            segment_hits.sort_by_key(|&(_, doc_id)| doc_id);
+            let full_doc = reader.full_doc().ok_or_else(|| {
+                Error::InvalidConfig(format!(
+                    "No full_doc feature to load docs from in segment {}",
+                    reader.info().address.name
+                ))
+            })?;
+            if let Some(mut cursor) = full_doc.cursor()? {
+                for &(i, doc_id) in segment_hits.iter() {
+                    docs[i] = Some(cursor.read_doc(doc_id)?);
+                }
+            }
+        }
+    }
+    Ok(docs.into_iter().flatten().collect())
+}
+
+impl Collector for TopDocsCollector {
+    fn collect_for(&mut self, reader: &SegmentReader, docs: &mut dyn DocIter) -> Result<(), Error> {
+        if self.size == 0 {
+            return Ok(());
+        }
+        while let Some(doc_id) = docs.next_doc()? {
+            if reader.deleted_docs().get(doc_id as usize).unwrap_or(false) {
+                continue;
+            }
+            self.total_hits += 1;
+            let score = docs.score()?;
+            let key = match self.key {
+                Some(ref key) => key(reader, doc_id, score),
+                None => score,
+            };
+            if self.heap.len() >= self.size {
+                //Ties are won by the earlier hit
+                if key <= self.heap.peek().unwrap().key {
+                    continue;
+                }
+                self.heap.pop();
+            }
+            self.heap.push(TopDocsEntry {
+                key,
+                order: self.total_hits,
+                hit: Hit {
+                    segment: reader.info().address.clone(),
+                    doc_id,
+                    score,
+                },
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Orders worse hits before better ones, so that the worst hit is at the top of the heap.
+#[derive(Clone)]
+struct TopDocsEntry {
+    key: f32,
+    order: u64,
+    hit: Hit,
+}
+
+impl Ord for TopDocsEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .key
+            .partial_cmp(&self.key)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| self.order.cmp(&other.order))
+    }
+}
+
+impl PartialOrd for TopDocsEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for TopDocsEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TopDocsEntry {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// Where docs without a value for a sort field are placed, regardless of the sort order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissingOrder {
+    First,
+    Last,
+}
+
+/// A field to sort hits by, read from a numeric or sorted doc values feature of the field. Docs
+/// with many values are sorted by their smallest value when ascending, and by their largest
+/// value when descending.
+#[derive(Clone, Debug)]
+pub struct SortField {
+    field: String,
+    order: SortOrder,
+    missing: MissingOrder,
+}
+
+impl SortField {
+    pub fn asc<F: Into<String>>(field: F) -> SortField {
+        SortField {
+            field: field.into(),
+            order: SortOrder::Ascending,
+            missing: MissingOrder::Last,
+        }
+    }
+
+    pub fn desc<F: Into<String>>(field: F) -> SortField {
+        SortField {
+            field: field.into(),
+            order: SortOrder::Descending,
+            missing: MissingOrder::Last,
+        }
+    }
+
+    pub fn missing_first(mut self) -> SortField {
+        self.missing = MissingOrder::First;
+        self
+    }
+
+    pub fn missing_last(mut self) -> SortField {
+        self.missing = MissingOrder::Last;
+        self
+    }
+
+    fn compare<T: Ord>(&self, a: &Option<T>, b: &Option<T>) -> Ordering {
+        let missing = match self.missing {
+            MissingOrder::First => Ordering::Less,
+            MissingOrder::Last => Ordering::Greater,
+        };
+        match (a, b) {
+            (Some(a), Some(b)) => match self.order {
+                SortOrder::Ascending => a.cmp(b),
+                SortOrder::Descending => b.cmp(a),
+            },
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => missing,
+            (Some(_), None) => missing.reverse(),
+        }
+    }
+
+    fn segment_values<'a>(&self, reader: &'a SegmentReader) -> SegmentSortValues<'a> {
+        if let Some(values) = reader.numeric_doc_values(&self.field) {
+            SegmentSortValues::Numeric(values)
+        } else if let Some(values) = reader.sorted_doc_values(&self.field) {
+            SegmentSortValues::Sorted(values)
+        } else {
+            SegmentSortValues::Missing
+        }
+    }
+}
+
+/// The value that a hit was sorted by. Numeric values are in their stored form.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortValue {
+    Numeric(i64),
+    Term(Vec<u8>),
+}
+
+/// Sort values of a segment, where the values of sorted doc values are their ordinals. These
+/// only compare correctly within the segment.
+enum SegmentSortValues<'a> {
+    Numeric(&'a NumericDocValuesReader),
+    Sorted(&'a SortedDocValuesReader),
+    Missing,
+}
+
+impl<'a> SegmentSortValues<'a> {
+    fn value(&self, doc_id: DocId, order: SortOrder) -> Option<i64> {
+        let values = match *self {
+            SegmentSortValues::Numeric(values) => values.values(doc_id),
+            SegmentSortValues::Sorted(values) => {
+                values.ords(doc_id).into_iter().map(|o| o as i64).collect()
+            }
+            SegmentSortValues::Missing => return None,
+        };
+        match order {
+            SortOrder::Ascending => values.into_iter().min(),
+            SortOrder::Descending => values.into_iter().max(),
+        }
+    }
+
+    fn sort_value(&self, value: i64) -> SortValue {
+        match *self {
+            SegmentSortValues::Sorted(values) => {
+                SortValue::Term(values.term_bytes(value as u64).to_vec())
+            }
+            _ => SortValue::Numeric(value),
+        }
+    }
+}
+
+/// A hit together with the values it was sorted by, one for each sort field.
+#[derive(Clone, Debug)]
+pub struct SortedHit {
+    pub hit: Hit,
+    pub values: Vec<Option<SortValue>>,
+    order: u64,
+}
+
+/// Keeps the first hits when sorted by field values, with ties broken by score and then by the
+/// order in which hits were found. Hits are sorted per segment first, so that only the best
+/// hits of each segment need their values looked up.
+pub struct SortingCollector {
+    size: usize,
+    sort: Vec<SortField>,
+    hits: Vec<SortedHit>,
+    total_hits: u64,
+}
+
+impl SortingCollector {
+    pub fn new(size: usize, sort: Vec<SortField>) -> SortingCollector {
+        SortingCollector {
+            size,
+            sort,
+            hits: Vec::new(),
+            total_hits: 0,
+        }
+    }
+
+    pub fn total_hits(&self) -> u64 {
+        self.total_hits
+    }
+
+    /// The first hits, in sorted order.
+    pub fn hits(&self) -> Vec<Hit> {
+        self.hits.iter().map(|sorted| sorted.hit.clone()).collect()
+    }
+
+    /// The first hits together with their sort values, in sorted order.
+    pub fn sorted_hits(&self) -> &[SortedHit] {
+        &self.hits
+    }
+
+    /// Loads the stored documents of the first hits, in sorted order.
+    pub fn docs(&self, index_reader: &ManagedIndexReader) -> Result<Vec<Doc>, Error> {
+        load_docs(index_reader, &self.hits())
+    }
+
+    fn compare<T: Ord>(
+        &self,
+        a: (&[Option<T>], f32, u64),
+        b: (&[Option<T>], f32, u64),
+    ) -> Ordering {
+        for (i, field) in self.sort.iter().enumerate() {
+            let ordering = field.compare(&a.0[i], &b.0[i]);
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.2.cmp(&b.2))
+    }
+}
+
+struct SegmentHit {
+    values: Vec<Option<i64>>,
+    score: f32,
+    order: u64,
+    doc_id: DocId,
+}
+
+impl Collector for SortingCollector {
+    fn collect_for(&mut self, reader: &SegmentReader, docs: &mut dyn DocIter) -> Result<(), Error> {
+        let sources: Vec<SegmentSortValues> = self
+            .sort
+            .iter()
+            .map(|field| field.segment_values(reader))
+            .collect();
+        let mut segment_hits = Vec::new();
+        while let Some(doc_id) = docs.next_doc()? {
+            if reader.deleted_docs().get(doc_id as usize).unwrap_or(false) {
+                continue;
+            }
+            self.total_hits += 1;
+            if self.size == 0 {
+                continue;
+            }
+            segment_hits.push(SegmentHit {
+                values: sources
+                    .iter()
+                    .zip(self.sort.iter())
+                    .map(|(source, field)| source.value(doc_id, field.order))
+                    .collect(),
+                score: docs.score()?,
+                order: self.total_hits,
+                doc_id,
+            });
+            //Sorting once the hits are twice the size keeps the amortized cost low
+            if segment_hits.len() >= 2 * self.size {
+                segment_hits.sort_by(|a, b| {
+                    self.compare((&a.values, a.score, a.order), (&b.values, b.score, b.order))
+                });
+                segment_hits.truncate(self.size);
+            }
+        }
+        segment_hits.sort_by(|a, b| {
+            self.compare((&a.values, a.score, a.order), (&b.values, b.score, b.order))
+        });
+        segment_hits.truncate(self.size);
+
+        for segment_hit in segment_hits {
+            self.hits.push(SortedHit {
+                values: segment_hit
+                    .values
+                    .iter()
+                    .zip(sources.iter())
+                    .map(|(value, source)| value.map(|v| source.sort_value(v)))
+                    .collect(),
+                order: segment_hit.order,
+                hit: Hit {
+                    segment: reader.info().address.clone(),
+                    doc_id: segment_hit.doc_id,
+                    score: segment_hit.score,
+                },
+            });
+        }
+        let mut hits = ::std::mem::take(&mut self.hits);
+        hits.sort_by(|a, b| {
+            self.compare(
+                (&a.values, a.hit.score, a.order),
+                (&b.values, b.hit.score, b.order),
+            )
+        });
+        hits.truncate(self.size);
+        self.hits = hits;
         Ok(())
     }
 }
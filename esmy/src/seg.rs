@@ -6,22 +6,33 @@ use std::fs::OpenOptions;
 use std::io;
 use std::io::Read;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use bit_vec::BitVec;
 use rayon::prelude::*;
 use rmps;
 
 use analyzis::Analyzer;
+use analyzis::AnalyzerRegistry;
 use doc::Doc;
 use error::Error;
+use field_norms::FieldNorms;
+use field_norms::FieldNormsReader;
 use full_doc::FullDoc;
 use full_doc::FullDocReader;
+use numeric_doc_values::NumericDocValues;
+use numeric_doc_values::NumericDocValuesReader;
+use numeric_doc_values::NumericType;
+use numeric_points::NumericPoints;
+use numeric_points::NumericPointsReader;
+use sorted_doc_values::SortedDocValues;
+use sorted_doc_values::SortedDocValuesReader;
 use string_index::StringIndex;
 use string_index::StringIndexReader;
 use string_pos_index::StringPosIndex;
 use string_pos_index::StringPosIndexReader;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(untagged)]
 pub enum FeatureConfig {
     None,
@@ -29,9 +40,17 @@ pub enum FeatureConfig {
     Int(i64),
     String(String),
     Map(HashMap<String, FeatureConfig>),
+    List(Vec<FeatureConfig>),
 }
 
 impl FeatureConfig {
+    pub fn at(&self, path: &str) -> Option<&FeatureConfig> {
+        if let FeatureConfig::Map(map) = self {
+            return map.get(path);
+        }
+        None
+    }
+
     pub fn str_at(&self, path: &str) -> Option<&str> {
         if let FeatureConfig::Map(map) = self {
             if let Some(field) = map.get(path) {
@@ -43,6 +62,13 @@ impl FeatureConfig {
         None
     }
 
+    /// Like `str_at`, but a missing string is an error, for configs read back from a segment.
+    pub fn required_str_at(&self, path: &str) -> Result<&str, Error> {
+        self.str_at(path).ok_or_else(|| {
+            Error::InvalidConfig(format!("Config without string at {}: {:?}", path, self))
+        })
+    }
+
     pub fn int_at(&self, path: &str) -> Option<i64> {
         if let FeatureConfig::Map(map) = self {
             if let Some(field) = map.get(path) {
@@ -64,8 +90,7 @@ impl FeatureConfig {
 
 pub trait Feature: FeatureClone + Sync + Send {
     fn feature_type(&self) -> &'static str;
-    //TODO add error for faulty configs
-    fn from_config(_: FeatureConfig) -> Self
+    fn from_config(_: FeatureConfig, analyzers: &AnalyzerRegistry) -> Result<Self, Error>
     where
         Self: Sized;
     fn to_config(&self) -> FeatureConfig;
@@ -102,6 +127,70 @@ impl Clone for Box<dyn Feature> {
     }
 }
 
+type FeatureFactory =
+    dyn Fn(FeatureConfig, &AnalyzerRegistry) -> Result<Box<dyn Feature>, Error> + Send + Sync;
+
+/// Creates features by their `feature_type`, so that the schema of a segment can be read back
+/// when it is opened or merged. Custom features must be registered before an index using them
+/// is opened.
+#[derive(Clone)]
+pub struct FeatureRegistry {
+    factories: HashMap<String, Arc<FeatureFactory>>,
+}
+
+impl Default for FeatureRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FeatureRegistry {
+    /// A registry with the features of this crate.
+    pub fn new() -> FeatureRegistry {
+        FeatureRegistry {
+            factories: HashMap::new(),
+        }
+        .register::<FullDoc>(FullDoc::FEATURE_TYPE)
+        .register::<StringIndex>(StringIndex::FEATURE_TYPE)
+        .register::<StringPosIndex>(StringPosIndex::FEATURE_TYPE)
+        .register::<FieldNorms>(FieldNorms::FEATURE_TYPE)
+        .register::<NumericDocValues>(NumericDocValues::FEATURE_TYPE)
+        .register::<NumericPoints>(NumericPoints::FEATURE_TYPE)
+        .register::<SortedDocValues>(SortedDocValues::FEATURE_TYPE)
+    }
+
+    /// Registers a type of feature under a name, replacing any feature with the same name. The
+    /// name must be the one the features of the type return from `feature_type`, as that is the
+    /// name their segments are stored with.
+    pub fn register<F>(mut self, name: &str) -> FeatureRegistry
+    where
+        F: Feature + 'static,
+    {
+        self.factories.insert(
+            name.to_string(),
+            Arc::new(|config, analyzers| -> Result<Box<dyn Feature>, Error> {
+                Ok(Box::new(F::from_config(config, analyzers)?))
+            }),
+        );
+        self
+    }
+
+    pub fn create(
+        &self,
+        feature_type: &str,
+        config: FeatureConfig,
+        analyzers: &AnalyzerRegistry,
+    ) -> Result<Box<dyn Feature>, Error> {
+        match self.factories.get(feature_type) {
+            Some(factory) => factory(config, analyzers),
+            None => Err(Error::InvalidConfig(format!(
+                "No such feature: {}",
+                feature_type
+            ))),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FeatureMeta {
     #[serde(rename = "type")]
@@ -166,6 +255,57 @@ impl SegmentSchemaBuilder {
         self
     }
 
+    pub fn add_field_norms<N, F>(mut self, name: N, field: F, analyzer: Box<dyn Analyzer>) -> Self
+    where
+        N: Into<String>,
+        F: Into<String>,
+    {
+        self.features.insert(
+            name.into(),
+            Box::new(FieldNorms::new(field.into(), analyzer)),
+        );
+        self
+    }
+
+    pub fn add_numeric_doc_values<N, F>(
+        mut self,
+        name: N,
+        field: F,
+        numeric_type: NumericType,
+    ) -> Self
+    where
+        N: Into<String>,
+        F: Into<String>,
+    {
+        self.features.insert(
+            name.into(),
+            Box::new(NumericDocValues::new(field.into(), numeric_type)),
+        );
+        self
+    }
+
+    pub fn add_numeric_points<N, F>(mut self, name: N, field: F, numeric_type: NumericType) -> Self
+    where
+        N: Into<String>,
+        F: Into<String>,
+    {
+        self.features.insert(
+            name.into(),
+            Box::new(NumericPoints::new(field.into(), numeric_type)),
+        );
+        self
+    }
+
+    pub fn add_sorted_doc_values<N, F>(mut self, name: N, field: F) -> Self
+    where
+        N: Into<String>,
+        F: Into<String>,
+    {
+        self.features
+            .insert(name.into(), Box::new(SortedDocValues::new(field.into())));
+        self
+    }
+
     pub fn add_full_doc<N>(mut self, name: N) -> Self
     where
         N: Into<String>,
@@ -229,19 +369,19 @@ impl SegmentInfo {
     }
 }
 
-pub fn schema_from_metas(feature_metas: HashMap<String, FeatureMeta>) -> SegmentSchema {
-    let mut features = HashMap::new();
+pub fn schema_from_metas(
+    feature_metas: HashMap<String, FeatureMeta>,
+    features: &FeatureRegistry,
+    analyzers: &AnalyzerRegistry,
+) -> Result<SegmentSchema, Error> {
+    let mut schema_features = HashMap::new();
     for (name, feature_meta) in feature_metas {
-        let feature: Box<dyn Feature> = match feature_meta.ftype.as_ref() {
-            "full_doc" => Box::new(FullDoc::from_config(feature_meta.config)),
-            "string_index" => Box::new(StringIndex::from_config(feature_meta.config)),
-            "string_pos_index" => Box::new(StringPosIndex::from_config(feature_meta.config)),
-            //TODO error handling
-            _ => panic!("No such feature"),
-        };
-        features.insert(name, feature);
+        let feature = features.create(&feature_meta.ftype, feature_meta.config, analyzers)?;
+        schema_features.insert(name, feature);
     }
-    SegmentSchema { features }
+    Ok(SegmentSchema {
+        features: schema_features,
+    })
 }
 
 pub fn schema_to_feature_metas(schema: &SegmentSchema) -> HashMap<String, FeatureMeta> {
@@ -259,12 +399,16 @@ pub fn schema_to_feature_metas(schema: &SegmentSchema) -> HashMap<String, Featur
 }
 
 impl SegmentAddress {
-    pub fn read_info(&self) -> Result<SegmentInfo, Error> {
+    pub fn read_info(
+        &self,
+        features: &FeatureRegistry,
+        analyzers: &AnalyzerRegistry,
+    ) -> Result<SegmentInfo, Error> {
         let seg_file = self.open_file("seg")?;
         let segment_meta: SegmentMeta = rmps::from_read(seg_file)?;
 
         let feature_metas = segment_meta.feature_metas;
-        let schema = schema_from_metas(feature_metas);
+        let schema = schema_from_metas(feature_metas, features, analyzers)?;
 
         Ok(SegmentInfo {
             address: self.clone(),
@@ -370,29 +514,12 @@ pub fn merge(
     schema: &SegmentSchema,
     new_address: &SegmentAddress,
     addresses: &[SegmentAddress],
+    features: &FeatureRegistry,
+    analyzers: &AnalyzerRegistry,
 ) -> Result<(), Error> {
     let mut infos: Vec<SegmentInfo> = Vec::with_capacity(addresses.len());
     for address in addresses {
-        let seg_file = address.open_file("seg")?;
-        let segment_meta: SegmentMeta = rmps::from_read(seg_file)?;
-
-        let mut features = HashMap::new();
-        for (name, feature_meta) in segment_meta.feature_metas {
-            let feature: Box<dyn Feature> = match feature_meta.ftype.as_ref() {
-                "full_doc" => Box::new(FullDoc::from_config(feature_meta.config)),
-                "string_index" => Box::new(StringIndex::from_config(feature_meta.config)),
-                "string_pos_index" => Box::new(StringPosIndex::from_config(feature_meta.config)),
-                //TODO error handling
-                _ => panic!("No such feature"),
-            };
-            features.insert(name, feature);
-        }
-
-        infos.push(SegmentInfo {
-            address: (*address).clone(),
-            schema: SegmentSchema { features },
-            doc_count: segment_meta.doc_count,
-        });
+        infos.push(address.read_info(features, analyzers)?);
     }
     schema
         .features
@@ -476,6 +603,12 @@ impl SegmentReader {
         &self.deleted_docs
     }
 
+    /// The reader of the feature with the given name in the schema, for features which have no
+    /// lookup of their own.
+    pub fn feature_reader(&self, name: &str) -> Option<&dyn FeatureReader> {
+        self.readers.get(name).map(|reader| &**reader)
+    }
+
     pub fn string_index(
         &self,
         field_name: &str,
@@ -484,7 +617,7 @@ impl SegmentReader {
         for reader in self.readers.values() {
             if let Some(reader) = reader.as_any().downcast_ref::<StringIndexReader>() {
                 if reader.feature.field_name == field_name
-                    && analyzer.analyzer_type() == reader.feature.analyzer.analyzer_type()
+                    && analyzer.same_as(&*reader.feature.analyzer)
                 {
                     return Some(reader);
                 }
@@ -501,7 +634,24 @@ impl SegmentReader {
         for reader in self.readers.values() {
             if let Some(reader) = reader.as_any().downcast_ref::<StringPosIndexReader>() {
                 if reader.feature.field_name == field_name
-                    && analyzer.analyzer_type() == reader.feature.analyzer.analyzer_type()
+                    && analyzer.same_as(&*reader.feature.analyzer)
+                {
+                    return Some(reader);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn field_norms(
+        &self,
+        field_name: &str,
+        analyzer: &dyn Analyzer,
+    ) -> Option<&FieldNormsReader> {
+        for reader in self.readers.values() {
+            if let Some(reader) = reader.as_any().downcast_ref::<FieldNormsReader>() {
+                if reader.feature.field_name == field_name
+                    && analyzer.same_as(&*reader.feature.analyzer)
                 {
                     return Some(reader);
                 }
@@ -510,6 +660,45 @@ impl SegmentReader {
         None
     }
 
+    pub fn numeric_doc_values(&self, field_name: &str) -> Option<&NumericDocValuesReader> {
+        for reader in self.readers.values() {
+            if let Some(reader) = reader.as_any().downcast_ref::<NumericDocValuesReader>() {
+                if reader.feature.field_name == field_name {
+                    return Some(reader);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn numeric_points(
+        &self,
+        field_name: &str,
+        numeric_type: NumericType,
+    ) -> Option<&NumericPointsReader> {
+        for reader in self.readers.values() {
+            if let Some(reader) = reader.as_any().downcast_ref::<NumericPointsReader>() {
+                if reader.feature.field_name == field_name
+                    && reader.feature.numeric_type == numeric_type
+                {
+                    return Some(reader);
+                }
+            }
+        }
+        None
+    }
+
+    pub fn sorted_doc_values(&self, field_name: &str) -> Option<&SortedDocValuesReader> {
+        for reader in self.readers.values() {
+            if let Some(reader) = reader.as_any().downcast_ref::<SortedDocValuesReader>() {
+                if reader.feature.field_name == field_name {
+                    return Some(reader);
+                }
+            }
+        }
+        None
+    }
+
     pub fn full_doc(&self) -> Option<&FullDocReader> {
         for reader in self.readers.values() {
             if let Some(reader) = reader.as_any().downcast_ref::<FullDocReader>() {
@@ -519,3 +708,252 @@ impl SegmentReader {
         None
     }
 }
+
+/// Fixtures shared by the tests of the features.
+#[cfg(test)]
+pub mod test_fixtures {
+
+    use super::FeatureAddress;
+    use super::SegmentAddress;
+    use super::SegmentInfo;
+    use super::SegmentSchemaBuilder;
+    use bit_vec::BitVec;
+    use doc::DocDecorator;
+    use doc::FieldValue;
+    use tempfile::TempDir;
+    use Doc;
+
+    pub fn feature_address(dir: &TempDir, segment: &str) -> FeatureAddress {
+        FeatureAddress {
+            segment: SegmentAddress {
+                path: dir.path().to_path_buf(),
+                name: segment.to_string(),
+            },
+            name: "feature".to_string(),
+        }
+    }
+
+    /// A segment to merge, with the given docs deleted.
+    pub fn old_segment(
+        address: &FeatureAddress,
+        doc_count: usize,
+        deleted_docs: &[usize],
+    ) -> (FeatureAddress, SegmentInfo, BitVec) {
+        let info = SegmentInfo {
+            address: address.segment.clone(),
+            schema: SegmentSchemaBuilder::new().build(),
+            doc_count: doc_count as u64,
+        };
+        let mut deleted = BitVec::from_elem(doc_count, false);
+        for &doc_id in deleted_docs {
+            deleted.set(doc_id, true);
+        }
+        (address.clone(), info, deleted)
+    }
+
+    /// A doc with the values in the int field "n", which is left out if there are none.
+    pub fn int_doc(values: &[i64]) -> Doc {
+        match values.len() {
+            0 => Doc::new(),
+            1 => Doc::new().int_field("n", values[0]),
+            _ => Doc::new().array_field("n", values.iter().map(|v| FieldValue::Int(*v)).collect()),
+        }
+    }
+
+    /// Docs with one, no, two and one int values, for the merge tests of the numeric features.
+    pub fn int_docs() -> Vec<Doc> {
+        vec![
+            int_doc(&[3]),
+            int_doc(&[]),
+            int_doc(&[-7, 12]),
+            int_doc(&[5]),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Feature;
+    use super::FeatureAddress;
+    use super::FeatureConfig;
+    use super::FeatureReader;
+    use super::FeatureRegistry;
+    use super::SegmentInfo;
+    use super::SegmentSchemaBuilder;
+    use analyzis::AnalyzerRegistry;
+    use analyzis::UAX29Analyzer;
+    use bit_vec::BitVec;
+    use doc::DocDecorator;
+    use error::Error;
+    use index::IndexBuilder;
+    use numeric_doc_values::NumericType;
+    use std::any::Any;
+    use std::fs;
+    use tempfile::TempDir;
+    use Doc;
+
+    #[derive(Clone)]
+    struct DocCount;
+
+    struct DocCountReader {
+        count: u64,
+    }
+
+    impl FeatureReader for DocCountReader {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    impl Feature for DocCount {
+        fn feature_type(&self) -> &'static str {
+            "doc_count"
+        }
+
+        fn from_config(_: FeatureConfig, _: &AnalyzerRegistry) -> Result<Self, Error> {
+            Ok(DocCount)
+        }
+
+        fn to_config(&self) -> FeatureConfig {
+            FeatureConfig::None
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn write_segment(&self, address: &FeatureAddress, docs: &[Doc]) -> Result<(), Error> {
+            fs::write(address.with_ending("cnt"), docs.len().to_string())?;
+            Ok(())
+        }
+
+        fn reader(&self, address: &FeatureAddress) -> Result<Box<dyn FeatureReader>, Error> {
+            let count = fs::read_to_string(address.with_ending("cnt"))?;
+            Ok(Box::new(DocCountReader {
+                count: count.parse().unwrap(),
+            }))
+        }
+
+        fn merge_segments(
+            &self,
+            old_segments: &[(FeatureAddress, SegmentInfo, BitVec)],
+            new_segment: &FeatureAddress,
+        ) -> Result<(), Error> {
+            let count = old_segments
+                .iter()
+                .map(|(_, _, deleted_docs)| deleted_docs.iter().filter(|deleted| !deleted).count())
+                .sum::<usize>();
+            fs::write(new_segment.with_ending("cnt"), count.to_string())?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn opens_and_merges_registered_features() {
+        let dir = TempDir::new().unwrap();
+        let features = FeatureRegistry::new().register::<DocCount>("doc_count");
+        let schema = SegmentSchemaBuilder::new()
+            .add_feature("count", Box::new(DocCount))
+            .build();
+        let index = IndexBuilder::new()
+            .auto_merge(false)
+            .features(features.clone())
+            .create(dir.path(), schema)
+            .unwrap();
+        for docs in &[3, 2] {
+            for i in 0..*docs {
+                index.add_doc(Doc::new().int_field("n", i)).unwrap();
+            }
+            index.commit().unwrap();
+        }
+        drop(index);
+
+        assert!(IndexBuilder::new().open(dir.path()).is_err());
+
+        let index = IndexBuilder::new()
+            .auto_merge(false)
+            .features(features)
+            .open(dir.path())
+            .unwrap();
+        index.force_merge().unwrap();
+        let reader = index.open_reader().unwrap();
+        let counts: Vec<u64> = reader
+            .segment_readers()
+            .iter()
+            .map(|segment| {
+                let reader = segment.feature_reader("count").unwrap();
+                reader
+                    .as_any()
+                    .downcast_ref::<DocCountReader>()
+                    .unwrap()
+                    .count
+            })
+            .collect();
+        assert_eq!(vec![5], counts);
+    }
+
+    #[test]
+    fn registers_features_under_their_types() {
+        let features = FeatureRegistry::new();
+        let analyzers = AnalyzerRegistry::new();
+        let schema = SegmentSchemaBuilder::new()
+            .add_full_doc("full_doc")
+            .add_string_index("string_index", "f", Box::new(UAX29Analyzer))
+            .add_string_pos_index("string_pos_index", "f", Box::new(UAX29Analyzer))
+            .add_field_norms("field_norms", "f", Box::new(UAX29Analyzer))
+            .add_numeric_doc_values("numeric_doc_values", "n", NumericType::Int)
+            .add_numeric_points("numeric_points", "n", NumericType::Float)
+            .add_sorted_doc_values("sorted_doc_values", "f")
+            .build();
+        for feature in schema.features.values() {
+            assert!(features
+                .create(feature.feature_type(), feature.to_config(), &analyzers)
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn refuses_faulty_feature_configs() {
+        let features = FeatureRegistry::new();
+        let analyzers = AnalyzerRegistry::new();
+        let config = |entries: &[(&str, &str)]| {
+            FeatureConfig::Map(
+                entries
+                    .iter()
+                    .map(|&(k, v)| (k.to_string(), FeatureConfig::String(v.to_string())))
+                    .collect(),
+            )
+        };
+        assert!(features
+            .create(
+                "numeric_doc_values",
+                config(&[("field", "n"), ("type", "int")]),
+                &analyzers
+            )
+            .is_ok());
+        for &feature_type in &["numeric_doc_values", "numeric_points"] {
+            let faulty = vec![
+                config(&[("field", "n"), ("type", "decimal")]),
+                config(&[("field", "n")]),
+                config(&[("type", "int")]),
+            ];
+            for config in faulty {
+                match features.create(feature_type, config, &analyzers) {
+                    Err(Error::InvalidConfig(_)) => (),
+                    _ => panic!("Expected an invalid config for {}", feature_type),
+                }
+            }
+        }
+        for &feature_type in &[
+            "string_index",
+            "string_pos_index",
+            "field_norms",
+            "sorted_doc_values",
+        ] {
+            assert!(features
+                .create(feature_type, FeatureConfig::None, &analyzers)
+                .is_err());
+        }
+    }
+}
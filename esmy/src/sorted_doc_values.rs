@@ -0,0 +1,488 @@
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Cursor;
+use std::io::Write;
+use std::sync::Arc;
+
+use bit_vec::BitVec;
+use byteorder::BigEndian;
+use byteorder::ReadBytesExt;
+use byteorder::WriteBytesExt;
+use fst::map::OpBuilder;
+use fst::{Map, MapBuilder, Streamer};
+use memmap::Mmap;
+
+use analyzis::AnalyzerRegistry;
+use column::write_column;
+use column::Column;
+use doc::field_values;
+use error::Error;
+use seg::Feature;
+use seg::FeatureAddress;
+use seg::FeatureConfig;
+use seg::FeatureReader;
+use seg::SegmentInfo;
+use util::bits_required;
+use util::read_packed;
+use util::write_packed;
+use Doc;
+use DocId;
+
+const TERM_ORD_LISTING: &str = "sdd";
+const ORD_TERM_LISTING: &str = "sdt";
+const DOC_ORDS: &str = "sdo";
+const TERMS_HEADER_LENGTH: usize = 8 + 1 + 8;
+
+/// Stores the string values of a field in a sorted dictionary per segment, and the ordinals of
+/// the values that each doc has in a column. Ordinals sort like the values they stand for, so
+/// they can be compared within a segment without looking up the values. Values are stored as
+/// they are, without analysis.
+#[derive(Clone)]
+pub struct SortedDocValues {
+    pub field_name: String,
+}
+
+impl SortedDocValues {
+    /// The name this feature is stored and registered under.
+    pub const FEATURE_TYPE: &'static str = "sorted_doc_values";
+
+    pub fn new(field_name: String) -> SortedDocValues {
+        SortedDocValues { field_name }
+    }
+
+    fn doc_terms(&self, doc: &Doc) -> Vec<String> {
+        field_values(doc, &self.field_name)
+            .iter()
+            .flat_map(|value| value.values())
+            .map(|value| value.to_text().into_owned())
+            .collect()
+    }
+}
+
+impl Feature for SortedDocValues {
+    fn feature_type(&self) -> &'static str {
+        Self::FEATURE_TYPE
+    }
+
+    fn from_config(config: FeatureConfig, _analyzers: &AnalyzerRegistry) -> Result<Self, Error> {
+        let field_name = config.required_str_at("field")?.to_string();
+        Ok(SortedDocValues { field_name })
+    }
+
+    fn to_config(&self) -> FeatureConfig {
+        let mut map = HashMap::new();
+        map.insert(
+            "field".to_string(),
+            FeatureConfig::String(self.field_name.to_string()),
+        );
+        FeatureConfig::Map(map)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn write_segment(&self, address: &FeatureAddress, docs: &[Doc]) -> Result<(), Error> {
+        let doc_terms: Vec<Vec<String>> = docs.iter().map(|doc| self.doc_terms(doc)).collect();
+        let mut ords = BTreeMap::new();
+        for term in doc_terms.iter().flat_map(|terms| terms.iter()) {
+            ords.insert(term.as_str(), 0);
+        }
+        if ords.is_empty() {
+            return Ok(());
+        }
+        for (ord, value) in ords.values_mut().enumerate() {
+            *value = ord as i64;
+        }
+        write_dictionary(address, ords.keys().map(|term| term.as_bytes()))?;
+        write_column(
+            &address.with_ending(DOC_ORDS),
+            doc_terms.iter().map(|terms| {
+                let mut doc_ords: Vec<i64> = terms.iter().map(|t| ords[t.as_str()]).collect();
+                doc_ords.sort();
+                doc_ords.dedup();
+                doc_ords
+            }),
+        )
+    }
+
+    fn reader(&self, address: &FeatureAddress) -> Result<Box<dyn FeatureReader>, Error> {
+        Ok(Box::new(SortedDocValuesReader::open(
+            self.clone(),
+            address,
+        )?))
+    }
+
+    fn merge_segments(
+        &self,
+        old_segments: &[(FeatureAddress, SegmentInfo, BitVec)],
+        new_segment: &FeatureAddress,
+    ) -> Result<(), Error> {
+        let mut readers = Vec::new();
+        for (old_address, _, _) in old_segments {
+            readers.push(SortedDocValuesReader::open(self.clone(), old_address)?);
+        }
+
+        //Values which only deleted docs have are dropped
+        let mut live_ords = Vec::new();
+        for (reader, (_, old_info, deleted_docs)) in readers.iter().zip(old_segments) {
+            let mut live = BitVec::from_elem(reader.term_count() as usize, false);
+            for doc_id in 0..old_info.doc_count {
+                if !deleted_docs.get(doc_id as usize).unwrap_or(false) {
+                    for ord in reader.ords(doc_id) {
+                        live.set(ord as usize, true);
+                    }
+                }
+            }
+            live_ords.push(live);
+        }
+
+        let mut op_builder = OpBuilder::new();
+        let mut dictionary_segments = Vec::new();
+        for (segment, reader) in readers.iter().enumerate() {
+            if let Some(ref dictionary) = reader.dictionary {
+                op_builder.push(dictionary.map.stream());
+                dictionary_segments.push(segment);
+            }
+        }
+        let mut new_ords: Vec<HashMap<u64, i64>> = vec![HashMap::new(); readers.len()];
+        let mut terms = Vec::new();
+        let mut union = op_builder.union();
+        while let Some((term, term_ords)) = union.next() {
+            let new_ord = terms.len() as i64;
+            let mut is_live = false;
+            for term_ord in term_ords {
+                let segment = dictionary_segments[term_ord.index];
+                if live_ords[segment]
+                    .get(term_ord.value as usize)
+                    .unwrap_or(false)
+                {
+                    new_ords[segment].insert(term_ord.value, new_ord);
+                    is_live = true;
+                }
+            }
+            if is_live {
+                terms.push(term.to_vec());
+            }
+        }
+        if terms.is_empty() {
+            return Ok(());
+        }
+        write_dictionary(new_segment, terms.iter().map(|term| term.as_slice()))?;
+
+        let mut doc_ords = Vec::new();
+        for (segment, (reader, (_, old_info, deleted_docs))) in
+            readers.iter().zip(old_segments).enumerate()
+        {
+            for doc_id in 0..old_info.doc_count {
+                if !deleted_docs.get(doc_id as usize).unwrap_or(false) {
+                    doc_ords.push(
+                        reader
+                            .ords(doc_id)
+                            .iter()
+                            .map(|ord| new_ords[segment][ord])
+                            .collect(),
+                    );
+                }
+            }
+        }
+        write_column(&new_segment.with_ending(DOC_ORDS), doc_ords.into_iter())
+    }
+}
+
+/// Writes an fst from the values to their ordinals, and the values in order so that they can be
+/// found by ordinal.
+fn write_dictionary<'a, I>(address: &FeatureAddress, terms: I) -> Result<(), Error>
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    let mut term_map = MapBuilder::new(BufWriter::new(File::create(
+        address.with_ending(TERM_ORD_LISTING),
+    )?))?;
+    let mut offsets = vec![0u64];
+    let mut term_data = Vec::new();
+    for (ord, term) in terms.enumerate() {
+        term_map.insert(term, ord as u64)?;
+        term_data.extend_from_slice(term);
+        offsets.push(term_data.len() as u64);
+    }
+    term_map.finish()?;
+
+    let offset_bits = bits_required(term_data.len() as u64);
+    let mut offsets_data = Vec::new();
+    let offsets_length = write_packed(&mut offsets_data, &offsets, offset_bits)?;
+    let mut target = BufWriter::new(File::create(address.with_ending(ORD_TERM_LISTING))?);
+    target.write_u64::<BigEndian>(offsets.len() as u64 - 1)?;
+    target.write_u8(offset_bits)?;
+    target.write_u64::<BigEndian>(offsets_length)?;
+    target.write_all(&offsets_data)?;
+    target.write_all(&term_data)?;
+    target.flush()?;
+    Ok(())
+}
+
+/// Reads the values of docs in a segment through their ordinals. Cloning is cheap.
+#[derive(Clone)]
+pub struct SortedDocValuesReader {
+    pub feature: SortedDocValues,
+    dictionary: Option<Arc<Dictionary>>,
+    ords: Option<Arc<Column>>,
+}
+
+struct Dictionary {
+    map: Map<Mmap>,
+    terms: Mmap,
+    term_count: u64,
+    offset_bits: u8,
+    terms_start: usize,
+}
+
+impl SortedDocValuesReader {
+    fn open(
+        feature: SortedDocValues,
+        address: &FeatureAddress,
+    ) -> Result<SortedDocValuesReader, Error> {
+        let map_path = address.with_ending(TERM_ORD_LISTING);
+        let dictionary = if map_path.exists() {
+            let map = Map::new(unsafe { Mmap::map(&File::open(map_path)?)? })?;
+            let terms = unsafe { Mmap::map(&File::open(address.with_ending(ORD_TERM_LISTING))?)? };
+            let mut header = Cursor::new(&terms[..TERMS_HEADER_LENGTH]);
+            let term_count = header.read_u64::<BigEndian>()?;
+            let offset_bits = header.read_u8()?;
+            let offsets_length = header.read_u64::<BigEndian>()?;
+            Some(Arc::new(Dictionary {
+                map,
+                terms,
+                term_count,
+                offset_bits,
+                terms_start: TERMS_HEADER_LENGTH + offsets_length as usize,
+            }))
+        } else {
+            None
+        };
+        let ords = Column::open(&address.with_ending(DOC_ORDS))?.map(Arc::new);
+        Ok(SortedDocValuesReader {
+            feature,
+            dictionary,
+            ords,
+        })
+    }
+
+    /// The number of distinct values in the segment.
+    pub fn term_count(&self) -> u64 {
+        match self.dictionary {
+            Some(ref dictionary) => dictionary.term_count,
+            None => 0,
+        }
+    }
+
+    /// The ordinals of the values of the doc, in ascending order.
+    pub fn ords(&self, doc_id: DocId) -> Vec<u64> {
+        match self.ords {
+            Some(ref ords) => ords.values(doc_id).into_iter().map(|o| o as u64).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The smallest ordinal of the values of the doc, if it has any.
+    pub fn ord(&self, doc_id: DocId) -> Option<u64> {
+        match self.ords {
+            Some(ref ords) => ords.first_value(doc_id).map(|o| o as u64),
+            None => None,
+        }
+    }
+
+    /// The value with the ordinal.
+    pub fn term_bytes(&self, ord: u64) -> &[u8] {
+        match self.dictionary {
+            Some(ref dictionary) => dictionary.term(ord),
+            None => &[],
+        }
+    }
+
+    /// The value with the ordinal, as a string.
+    pub fn term(&self, ord: u64) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.term_bytes(ord))
+    }
+
+    /// The ordinal of a value, if any doc in the segment has it.
+    pub fn lookup_term(&self, term: &str) -> Option<u64> {
+        match self.dictionary {
+            Some(ref dictionary) => dictionary.map.get(term),
+            None => None,
+        }
+    }
+}
+
+impl Dictionary {
+    fn term(&self, ord: u64) -> &[u8] {
+        if ord >= self.term_count {
+            return &[];
+        }
+        let offsets = &self.terms[TERMS_HEADER_LENGTH..self.terms_start];
+        let start = read_packed(offsets, self.offset_bits, ord) as usize;
+        let end = read_packed(offsets, self.offset_bits, ord + 1) as usize;
+        &self.terms[self.terms_start + start..self.terms_start + end]
+    }
+}
+
+impl FeatureReader for SortedDocValuesReader {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::SortedDocValues;
+    use super::SortedDocValuesReader;
+    use doc::DocDecorator;
+    use doc::FieldValue;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use seg::test_fixtures::feature_address;
+    use seg::test_fixtures::old_segment;
+    use seg::Feature;
+    use seg::FeatureAddress;
+    use tempfile::TempDir;
+    use Doc;
+
+    fn string_doc(values: &[&str]) -> Doc {
+        match values.len() {
+            0 => Doc::new(),
+            1 => Doc::new().string_field("s", values[0]),
+            _ => Doc::new().array_field(
+                "s",
+                values
+                    .iter()
+                    .map(|v| FieldValue::String(v.to_string()))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn open(feature: &SortedDocValues, address: &FeatureAddress) -> SortedDocValuesReader {
+        let reader = feature.reader(address).unwrap();
+        reader
+            .as_any()
+            .downcast_ref::<SortedDocValuesReader>()
+            .unwrap()
+            .clone()
+    }
+
+    fn doc_terms(reader: &SortedDocValuesReader, doc_count: usize) -> Vec<Vec<String>> {
+        (0..doc_count as u64)
+            .map(|doc_id| {
+                reader
+                    .ords(doc_id)
+                    .into_iter()
+                    .map(|ord| reader.term(ord).into_owned())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn expected_terms(values: &[Vec<String>]) -> Vec<Vec<String>> {
+        values
+            .iter()
+            .map(|v| {
+                let mut terms = v.clone();
+                terms.sort();
+                terms.dedup();
+                terms
+            })
+            .collect()
+    }
+
+    #[test]
+    fn maps_docs_to_sorted_ords() {
+        let dir = TempDir::new().unwrap();
+        let feature = SortedDocValues::new("s".to_string());
+        let docs = vec![
+            string_doc(&["pear"]),
+            string_doc(&[]),
+            string_doc(&["fig", "apple", "fig"]),
+        ];
+        let address = feature_address(&dir, "seg");
+        feature.write_segment(&address, &docs).unwrap();
+        let reader = open(&feature, &address);
+        assert_eq!(3, reader.term_count());
+        assert_eq!(Some(2), reader.ord(0));
+        assert_eq!(None, reader.ord(1));
+        assert_eq!(vec![0, 1], reader.ords(2));
+        assert_eq!("apple", reader.term(0));
+        assert_eq!(Some(1), reader.lookup_term("fig"));
+        assert_eq!(None, reader.lookup_term("plum"));
+    }
+
+    #[test]
+    fn drops_deleted_values_on_merge() {
+        let dir = TempDir::new().unwrap();
+        let feature = SortedDocValues::new("s".to_string());
+        let first_docs = vec![string_doc(&["b"]), string_doc(&["a"])];
+        let second_docs = vec![string_doc(&["c", "b"]), string_doc(&[])];
+        let first = feature_address(&dir, "first");
+        let second = feature_address(&dir, "second");
+        feature.write_segment(&first, &first_docs).unwrap();
+        feature.write_segment(&second, &second_docs).unwrap();
+
+        let merged = feature_address(&dir, "merged");
+        feature
+            .merge_segments(
+                &[old_segment(&first, 2, &[1]), old_segment(&second, 2, &[])],
+                &merged,
+            )
+            .unwrap();
+        let reader = open(&feature, &merged);
+        assert_eq!(2, reader.term_count());
+        assert_eq!(
+            vec![vec!["b"], vec!["b", "c"], vec![]],
+            doc_terms(&reader, 3)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn merges_segments(
+            ref first in vec(vec("[a-e]{0,2}", 0..3), 0..20),
+            ref second in vec(vec("[a-e]{0,2}", 0..3), 0..20),
+            ref deleted in vec(any::<bool>(), 40)
+        ) {
+            let dir = TempDir::new().unwrap();
+            let feature = SortedDocValues::new("s".to_string());
+            let mut segments = Vec::new();
+            let mut expected = Vec::new();
+            for (name, values, deleted) in &[("first", first, &deleted[..20]), ("second", second, &deleted[20..])] {
+                let address = feature_address(&dir, name);
+                let docs: Vec<Doc> = values
+                    .iter()
+                    .map(|v| string_doc(&v.iter().map(|s| s.as_str()).collect::<Vec<&str>>()))
+                    .collect();
+                feature.write_segment(&address, &docs).unwrap();
+                assert_eq!(expected_terms(values), doc_terms(&open(&feature, &address), docs.len()));
+                let mut deleted_docs = Vec::new();
+                for (doc_id, values) in values.iter().enumerate() {
+                    if deleted[doc_id] {
+                        deleted_docs.push(doc_id);
+                    } else {
+                        expected.push(values.clone());
+                    }
+                }
+                segments.push(old_segment(&address, docs.len(), &deleted_docs));
+            }
+            let merged = feature_address(&dir, "merged");
+            feature.merge_segments(&segments, &merged).unwrap();
+            let reader = open(&feature, &merged);
+            assert_eq!(expected_terms(&expected), doc_terms(&reader, expected.len()));
+            let mut all_terms: Vec<String> = expected.into_iter().flatten().collect();
+            all_terms.sort();
+            all_terms.dedup();
+            assert_eq!(all_terms.len() as u64, reader.term_count());
+        }
+    }
+}
@@ -0,0 +1,563 @@
+use std::borrow::Cow;
+
+/// The languages there are stemmers for. The stemmers are the Snowball stemmers of the
+/// languages, and expect lowercase words.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Language {
+    /// The Porter2 stemmer.
+    English,
+    German,
+    Swedish,
+}
+
+impl Language {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Language::English => "english",
+            Language::German => "german",
+            Language::Swedish => "swedish",
+        }
+    }
+
+    pub fn for_name(name: &str) -> Option<Language> {
+        match name {
+            "english" => Some(Language::English),
+            "german" => Some(Language::German),
+            "swedish" => Some(Language::Swedish),
+            _ => None,
+        }
+    }
+
+    /// The stem of a word, which is borrowed if nothing is removed from the word.
+    pub fn stem<'a>(&self, word: &'a str) -> Cow<'a, str> {
+        let mut chars: Vec<char> = word.chars().collect();
+        match *self {
+            Language::English => stem_english(&mut chars),
+            Language::German => stem_german(&mut chars),
+            Language::Swedish => stem_swedish(&mut chars),
+        }
+        if is(&chars, word) {
+            Cow::Borrowed(word)
+        } else {
+            Cow::Owned(chars.into_iter().collect())
+        }
+    }
+}
+
+fn is(word: &[char], other: &str) -> bool {
+    word.iter().cloned().eq(other.chars())
+}
+
+fn starts_with(word: &[char], prefix: &str) -> bool {
+    let mut chars = word.iter();
+    prefix.chars().all(|c| chars.next() == Some(&c))
+}
+
+fn ends_with(word: &[char], suffix: &str) -> bool {
+    let mut chars = word.iter().rev();
+    suffix.chars().rev().all(|c| chars.next() == Some(&c))
+}
+
+/// Where the suffix starts in a word ending with it.
+fn suffix_start(word: &[char], suffix: &str) -> usize {
+    word.len() - suffix.chars().count()
+}
+
+/// The longest of the suffixes that the word ends with, which starts at `min_start` or later.
+fn longest_suffix(
+    word: &[char],
+    suffixes: &[&'static str],
+    min_start: usize,
+) -> Option<&'static str> {
+    suffixes
+        .iter()
+        .filter(|suffix| ends_with(word, suffix) && suffix_start(word, suffix) >= min_start)
+        .max_by_key(|suffix| suffix.chars().count())
+        .cloned()
+}
+
+fn replace_suffix(word: &mut Vec<char>, suffix: &str, replacement: &str) {
+    let start = suffix_start(word, suffix);
+    word.truncate(start);
+    word.extend(replacement.chars());
+}
+
+/// Where the region after the first non-vowel following a vowel starts, looking from `start`.
+/// This is the end of the word if there is no such region.
+fn region_after(word: &[char], start: usize, is_vowel: fn(char) -> bool) -> usize {
+    let mut i = start;
+    while i < word.len() && !is_vowel(word[i]) {
+        i += 1;
+    }
+    while i < word.len() && is_vowel(word[i]) {
+        i += 1;
+    }
+    (i + 1).min(word.len())
+}
+
+fn is_english_vowel(c: char) -> bool {
+    "aeiouy".contains(c)
+}
+
+const ENGLISH_EXCEPTIONS: &[(&str, &str)] = &[
+    ("skis", "ski"),
+    ("skies", "sky"),
+    ("dying", "die"),
+    ("lying", "lie"),
+    ("tying", "tie"),
+    ("idly", "idl"),
+    ("gently", "gentl"),
+    ("ugly", "ugli"),
+    ("early", "earli"),
+    ("only", "onli"),
+    ("singly", "singl"),
+    ("sky", "sky"),
+    ("news", "news"),
+    ("howe", "howe"),
+    ("atlas", "atlas"),
+    ("cosmos", "cosmos"),
+    ("bias", "bias"),
+    ("andes", "andes"),
+];
+
+/// Words which are left as they are after their plural ending is removed.
+const ENGLISH_INVARIANTS: &[&str] = &[
+    "inning", "outing", "canning", "herring", "earring", "proceed", "exceed", "succeed",
+];
+
+/// Whether the word ends in a short syllable, which is a vowel followed by a non-vowel other
+/// than w, x and Y and preceded by a non-vowel, or a vowel followed by a non-vowel at the start
+/// of the word.
+fn ends_in_short_syllable(word: &[char]) -> bool {
+    let n = word.len();
+    if n == 2 {
+        is_english_vowel(word[0]) && !is_english_vowel(word[1])
+    } else if n > 2 {
+        !is_english_vowel(word[n - 3])
+            && is_english_vowel(word[n - 2])
+            && !is_english_vowel(word[n - 1])
+            && !['w', 'x', 'Y'].contains(&word[n - 1])
+    } else {
+        false
+    }
+}
+
+fn stem_english(word: &mut Vec<char>) {
+    if let Some(&(_, stem)) = ENGLISH_EXCEPTIONS.iter().find(|&&(w, _)| is(word, w)) {
+        *word = stem.chars().collect();
+        return;
+    }
+    if word.len() < 3 {
+        return;
+    }
+    if word[0] == '\'' {
+        word.remove(0);
+    }
+    // A y which is a consonant is marked as Y while stemming.
+    for i in 0..word.len() {
+        if word[i] == 'y' && (i == 0 || is_english_vowel(word[i - 1])) {
+            word[i] = 'Y';
+        }
+    }
+    let p1 = ["gener", "commun", "arsen"]
+        .iter()
+        .find(|prefix| starts_with(word, prefix))
+        .map(|prefix| prefix.len())
+        .unwrap_or_else(|| region_after(word, 0, is_english_vowel));
+    let p2 = region_after(word, p1, is_english_vowel);
+
+    if let Some(suffix) = longest_suffix(word, &["'", "'s", "'s'"], 0) {
+        replace_suffix(word, suffix, "");
+    }
+
+    match longest_suffix(word, &["sses", "ied", "ies", "s", "us", "ss"], 0) {
+        Some("sses") => replace_suffix(word, "sses", "ss"),
+        Some(suffix @ "ied") | Some(suffix @ "ies") => {
+            let replacement = if word.len() > 4 { "i" } else { "ie" };
+            replace_suffix(word, suffix, replacement);
+        }
+        Some("s")
+            if word[..word.len().saturating_sub(2)]
+                .iter()
+                .any(|&c| is_english_vowel(c)) =>
+        {
+            word.pop();
+        }
+        _ => {}
+    }
+
+    if !ENGLISH_INVARIANTS.iter().any(|w| is(word, w)) {
+        english_step_1b(word, p1);
+        english_step_1c(word);
+        english_step_2(word, p1);
+        english_step_3(word, p1, p2);
+        english_step_4(word, p2);
+        english_step_5(word, p1, p2);
+    }
+
+    for c in word.iter_mut() {
+        if *c == 'Y' {
+            *c = 'y';
+        }
+    }
+}
+
+fn english_step_1b(word: &mut Vec<char>, p1: usize) {
+    let suffixes = &["eed", "eedly", "ed", "edly", "ing", "ingly"];
+    match longest_suffix(word, suffixes, 0) {
+        Some(suffix @ "eed") | Some(suffix @ "eedly") if suffix_start(word, suffix) >= p1 => {
+            replace_suffix(word, suffix, "ee");
+        }
+        Some("eed") | Some("eedly") => {}
+        Some(suffix) => {
+            let start = suffix_start(word, suffix);
+            if word[..start].iter().any(|&c| is_english_vowel(c)) {
+                word.truncate(start);
+                let doubles = &["bb", "dd", "ff", "gg", "mm", "nn", "pp", "rr", "tt"];
+                if ["at", "bl", "iz"]
+                    .iter()
+                    .any(|ending| ends_with(word, ending))
+                {
+                    word.push('e');
+                } else if doubles.iter().any(|double| ends_with(word, double)) {
+                    word.pop();
+                } else if p1 >= word.len() && ends_in_short_syllable(word) {
+                    word.push('e');
+                }
+            }
+        }
+        None => {}
+    }
+}
+
+fn english_step_1c(word: &mut [char]) {
+    let n = word.len();
+    if n > 2 && (word[n - 1] == 'y' || word[n - 1] == 'Y') && !is_english_vowel(word[n - 2]) {
+        word[n - 1] = 'i';
+    }
+}
+
+fn english_step_2(word: &mut Vec<char>, p1: usize) {
+    let suffixes = &[
+        "tional", "enci", "anci", "abli", "entli", "izer", "ization", "ational", "ation", "ator",
+        "alism", "aliti", "alli", "fulness", "ousli", "ousness", "iveness", "iviti", "biliti",
+        "bli", "ogi", "fulli", "lessli", "li",
+    ];
+    let suffix = match longest_suffix(word, suffixes, 0) {
+        Some(suffix) if suffix_start(word, suffix) >= p1 => suffix,
+        _ => return,
+    };
+    let preceding = word[suffix_start(word, suffix) - 1];
+    let replacement = match suffix {
+        "tional" => "tion",
+        "enci" => "ence",
+        "anci" => "ance",
+        "abli" => "able",
+        "entli" => "ent",
+        "izer" | "ization" => "ize",
+        "ational" | "ation" | "ator" => "ate",
+        "alism" | "aliti" | "alli" => "al",
+        "fulness" | "fulli" => "ful",
+        "ousli" | "ousness" => "ous",
+        "iveness" | "iviti" => "ive",
+        "biliti" | "bli" => "ble",
+        "ogi" if preceding == 'l' => "og",
+        "lessli" => "less",
+        "li" if "cdeghkmnrt".contains(preceding) => "",
+        _ => return,
+    };
+    replace_suffix(word, suffix, replacement);
+}
+
+fn english_step_3(word: &mut Vec<char>, p1: usize, p2: usize) {
+    let suffixes = &[
+        "tional", "ational", "alize", "icate", "iciti", "ical", "ful", "ness", "ative",
+    ];
+    let suffix = match longest_suffix(word, suffixes, 0) {
+        Some(suffix) if suffix_start(word, suffix) >= p1 => suffix,
+        _ => return,
+    };
+    let replacement = match suffix {
+        "tional" => "tion",
+        "ational" => "ate",
+        "alize" => "al",
+        "icate" | "iciti" | "ical" => "ic",
+        "ative" if suffix_start(word, suffix) < p2 => return,
+        _ => "",
+    };
+    replace_suffix(word, suffix, replacement);
+}
+
+fn english_step_4(word: &mut Vec<char>, p2: usize) {
+    let suffixes = &[
+        "al", "ance", "ence", "er", "ic", "able", "ible", "ant", "ement", "ment", "ent", "ism",
+        "ate", "iti", "ous", "ive", "ize", "ion",
+    ];
+    let suffix = match longest_suffix(word, suffixes, 0) {
+        Some(suffix) if suffix_start(word, suffix) >= p2 => suffix,
+        _ => return,
+    };
+    let start = suffix_start(word, suffix);
+    if suffix != "ion" || word[start - 1] == 's' || word[start - 1] == 't' {
+        word.truncate(start);
+    }
+}
+
+fn english_step_5(word: &mut Vec<char>, p1: usize, p2: usize) {
+    let start = match word.len() {
+        0 => return,
+        n => n - 1,
+    };
+    let remove = match word[start] {
+        'e' => start >= p2 || (start >= p1 && !ends_in_short_syllable(&word[..start])),
+        'l' => start >= p2 && word[start - 1] == 'l',
+        _ => false,
+    };
+    if remove {
+        word.truncate(start);
+    }
+}
+
+fn is_german_vowel(c: char) -> bool {
+    "aeiouyäöü".contains(c)
+}
+
+fn stem_german(word: &mut Vec<char>) {
+    let mut i = 0;
+    while i < word.len() {
+        if word[i] == 'ß' {
+            word[i] = 's';
+            word.insert(i, 's');
+        }
+        i += 1;
+    }
+    // A u or y between vowels is marked as U or Y while stemming, so that it is not a vowel.
+    for i in 1..word.len().saturating_sub(1) {
+        if is_german_vowel(word[i - 1]) && is_german_vowel(word[i + 1]) {
+            match word[i] {
+                'u' => word[i] = 'U',
+                'y' => word[i] = 'Y',
+                _ => {}
+            }
+        }
+    }
+    let (p1, p2) = if word.len() < 3 {
+        (word.len(), word.len())
+    } else {
+        let p1 = region_after(word, 0, is_german_vowel);
+        (p1.max(3), region_after(word, p1, is_german_vowel))
+    };
+
+    german_step_1(word, p1);
+    german_step_2(word, p1);
+    german_step_3(word, p1, p2);
+
+    for c in word.iter_mut() {
+        *c = match *c {
+            'U' | 'ü' => 'u',
+            'Y' => 'y',
+            'ä' => 'a',
+            'ö' => 'o',
+            c => c,
+        };
+    }
+}
+
+fn german_step_1(word: &mut Vec<char>, p1: usize) {
+    let suffix = match longest_suffix(word, &["em", "ern", "er", "e", "en", "es", "s"], 0) {
+        Some(suffix) if suffix_start(word, suffix) >= p1 => suffix,
+        _ => return,
+    };
+    let start = suffix_start(word, suffix);
+    match suffix {
+        "s" => {
+            if "bdfghklmnrt".contains(word[start - 1]) {
+                word.truncate(start);
+            }
+        }
+        "e" | "en" | "es" => {
+            word.truncate(start);
+            if ends_with(word, "niss") {
+                word.pop();
+            }
+        }
+        _ => word.truncate(start),
+    }
+}
+
+fn german_step_2(word: &mut Vec<char>, p1: usize) {
+    let suffix = match longest_suffix(word, &["en", "er", "est", "st"], 0) {
+        Some(suffix) if suffix_start(word, suffix) >= p1 => suffix,
+        _ => return,
+    };
+    let start = suffix_start(word, suffix);
+    if suffix != "st" || (start > 3 && "bdfghklmnt".contains(word[start - 1])) {
+        word.truncate(start);
+    }
+}
+
+fn german_step_3(word: &mut Vec<char>, p1: usize, p2: usize) {
+    let suffixes = &["end", "ung", "ig", "ik", "isch", "lich", "heit", "keit"];
+    let suffix = match longest_suffix(word, suffixes, 0) {
+        Some(suffix) if suffix_start(word, suffix) >= p2 => suffix,
+        _ => return,
+    };
+    let start = suffix_start(word, suffix);
+    match suffix {
+        "end" | "ung" => {
+            word.truncate(start);
+            if ends_with(word, "ig") {
+                let start = suffix_start(word, "ig");
+                if start >= p2 && word[start - 1] != 'e' {
+                    word.truncate(start);
+                }
+            }
+        }
+        "ig" | "ik" | "isch" => {
+            if word[start - 1] != 'e' {
+                word.truncate(start);
+            }
+        }
+        "lich" | "heit" => {
+            word.truncate(start);
+            if let Some(ending) = longest_suffix(word, &["er", "en"], p1) {
+                replace_suffix(word, ending, "");
+            }
+        }
+        _ => {
+            word.truncate(start);
+            if let Some(ending) = longest_suffix(word, &["lich", "ig"], 0) {
+                if suffix_start(word, ending) >= p2 {
+                    replace_suffix(word, ending, "");
+                }
+            }
+        }
+    }
+}
+
+fn is_swedish_vowel(c: char) -> bool {
+    "aeiouyäåö".contains(c)
+}
+
+/// The suffixes only count if they are entirely in the region, and the longest of those is
+/// removed.
+fn stem_swedish(word: &mut Vec<char>) {
+    if word.len() < 3 {
+        return;
+    }
+    let p1 = region_after(word, 0, is_swedish_vowel).max(3);
+
+    let suffixes = &[
+        "a", "arna", "erna", "heterna", "orna", "ad", "e", "ade", "ande", "arne", "are", "aste",
+        "en", "anden", "aren", "heten", "ern", "ar", "er", "heter", "or", "as", "arnas", "ernas",
+        "ornas", "es", "ades", "andes", "ens", "arens", "hetens", "erns", "at", "andet", "het",
+        "ast", "s",
+    ];
+    match longest_suffix(word, suffixes, p1) {
+        Some("s") if "bcdfghjklmnoprtvy".contains(word[word.len() - 2]) => {
+            word.pop();
+        }
+        Some("s") => {}
+        Some(suffix) => replace_suffix(word, suffix, ""),
+        None => {}
+    }
+
+    let pairs = &["dd", "gd", "nn", "dt", "gt", "kt", "tt"];
+    if longest_suffix(word, pairs, p1).is_some() {
+        word.pop();
+    }
+
+    match longest_suffix(word, &["lig", "ig", "els", "löst", "fullt"], p1) {
+        Some("löst") | Some("fullt") => {
+            word.pop();
+        }
+        Some(suffix) => replace_suffix(word, suffix, ""),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Language;
+    use proptest::prelude::*;
+
+    fn assert_stems(language: Language, pairs: &[(&str, &str)]) {
+        for &(word, stem) in pairs {
+            assert_eq!(stem, language.stem(word), "stem of {}", word);
+        }
+    }
+
+    #[test]
+    fn stems_english() {
+        assert_stems(
+            Language::English,
+            &[
+                ("running", "run"),
+                ("runs", "run"),
+                ("run", "run"),
+                ("caresses", "caress"),
+                ("ponies", "poni"),
+                ("ties", "tie"),
+                ("cats", "cat"),
+                ("hopping", "hop"),
+                ("hoping", "hope"),
+                ("agreed", "agre"),
+                ("generate", "generat"),
+                ("generously", "generous"),
+                ("consistently", "consist"),
+                ("national", "nation"),
+                ("relational", "relat"),
+                ("happily", "happili"),
+                ("dying", "die"),
+                ("news", "news"),
+                ("succeeding", "succeed"),
+                ("by", "by"),
+                ("abandoned", "abandon"),
+                ("abilities", "abil"),
+                ("absolutely", "absolut"),
+                ("accordingly", "accord"),
+                ("beautiful", "beauti"),
+                ("cries", "cri"),
+                ("'tis", "tis"),
+            ],
+        );
+    }
+
+    #[test]
+    fn stems_german() {
+        assert_stems(
+            Language::German,
+            &[
+                ("häuser", "haus"),
+                ("laufen", "lauf"),
+                ("kinder", "kind"),
+                ("straße", "strass"),
+            ],
+        );
+    }
+
+    #[test]
+    fn stems_swedish() {
+        assert_stems(
+            Language::Swedish,
+            &[
+                ("flickorna", "flick"),
+                ("klokaste", "klok"),
+                ("pojkarna", "pojk"),
+                ("och", "och"),
+            ],
+        );
+    }
+    proptest! {
+        #[test]
+        fn stems_any_word(ref word in "[a-zäåöüß']{0,12}") {
+            let length = word.chars().count();
+            //Only the German stemmer makes words longer, by replacing "ß" with "ss"
+            let sharp_s = word.chars().filter(|&c| c == 'ß').count();
+            assert!(Language::English.stem(word).chars().count() <= length);
+            assert!(Language::Swedish.stem(word).chars().count() <= length);
+            assert!(Language::German.stem(word).chars().count() <= length + sharp_s);
+        }
+    }
+}
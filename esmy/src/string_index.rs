@@ -3,26 +3,28 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::BufWriter;
+use std::io::ErrorKind;
+use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
+use std::ops::Bound;
 
 use bit_vec::BitVec;
 use fasthash::RandomState;
 use fasthash::sea::Hash64;
-use fst::{self, Map, MapBuilder, Streamer};
+use fst::{self, Automaton, IntoStreamer, Map, MapBuilder, Streamer};
 use fst::map::OpBuilder;
 use indexmap::IndexMap;
 use indexmap::map;
 use memmap::Mmap;
 
 use analyzis::Analyzer;
-use analyzis::NoopAnalyzer;
-use analyzis::UAX29Analyzer;
-use analyzis::WhiteSpaceAnalyzer;
+use analyzis::AnalyzerRegistry;
 use Doc;
-use doc::FieldValue;
+use doc::field_values;
 use doc_iter::DocIter;
+use doc_iter::TermFreqDocIter;
 use DocId;
 use error::Error;
 use seg::Feature;
@@ -35,6 +37,9 @@ use util::write_vint;
 
 const TERM_ID_LISTING: &str = "tid";
 const ID_DOC_LISTING: &str = "iddoc";
+/// Starts the postings file, with the version of its format last. Version 2 stores the frequency
+/// of the term after each doc id delta, which version 1, without a header, did not.
+const ID_DOC_HEADER: &[u8] = b"ESID\x02";
 
 #[derive(Clone)]
 pub struct StringIndex {
@@ -43,6 +48,9 @@ pub struct StringIndex {
 }
 
 impl StringIndex {
+    /// The name this feature is stored and registered under.
+    pub const FEATURE_TYPE: &'static str = "string_index";
+
     pub fn new(field_name: String, analyzer: Box<dyn Analyzer>) -> StringIndex {
         StringIndex {
             field_name,
@@ -59,24 +67,23 @@ impl StringIndex {
         let mut map = IndexMap::with_hasher(s);
 
         for (doc_id, doc) in docs.iter().enumerate() {
-            for (_name, val) in doc.iter().filter(|e| e.0 == field_name) {
-                match *val {
-                    FieldValue::String(ref value) => {
-                        for token in analyzer.analyze(value) {
-                            match map.entry(token) {
-                                map::Entry::Vacant(vacant) => {
-                                    vacant.insert(vec![doc_id as u64]);
-                                }
-                                map::Entry::Occupied(mut occupied) => {
-                                    let term_docs = occupied.get_mut();
-                                    if *term_docs.last().unwrap() != doc_id as u64 {
-                                        term_docs.push(doc_id as u64)
-                                    }
-                                }
+            for val in field_values(doc, field_name) {
+                for token in analyzer.analyze_value(val) {
+                    match map.entry(token) {
+                        map::Entry::Vacant(vacant) => {
+                            vacant.insert(vec![(doc_id as u64, 1u64)]);
+                        }
+                        map::Entry::Occupied(mut occupied) => {
+                            let term_docs = occupied.get_mut();
+                            let last = term_docs.last_mut().unwrap();
+                            if last.0 == doc_id as u64 {
+                                last.1 += 1;
+                            } else {
+                                term_docs.push((doc_id as u64, 1))
                             }
                         }
                     }
-                };
+                }
             }
         }
         if map.is_empty() {
@@ -87,13 +94,18 @@ impl StringIndex {
         let mut target_terms = MapBuilder::new(fst_writer)?;
         let mut target_postings =
             BufWriter::new(File::create(address.with_ending(ID_DOC_LISTING))?);
-        let mut offset = 0u64;
-        for (term, doc_ids) in map.iter() {
+        target_postings.write_all(ID_DOC_HEADER)?;
+        let mut offset = ID_DOC_HEADER.len() as u64;
+        for (term, doc_ids_and_freqs) in map.iter() {
             target_terms.insert(term.as_bytes(), offset)?;
-            offset += u64::from(write_vint(&mut target_postings, doc_ids.len() as u64)?);
+            offset += u64::from(write_vint(
+                &mut target_postings,
+                doc_ids_and_freqs.len() as u64,
+            )?);
             let mut prev = 0u64;
-            for doc_id in doc_ids {
+            for (doc_id, freq) in doc_ids_and_freqs {
                 offset += u64::from(write_vint(&mut target_postings, (*doc_id - prev) as u64)?);
+                offset += u64::from(write_vint(&mut target_postings, *freq)?);
                 prev = *doc_id;
             }
         }
@@ -105,22 +117,17 @@ impl StringIndex {
 
 impl Feature for StringIndex {
     fn feature_type(&self) -> &'static str {
-        "string_index"
+        Self::FEATURE_TYPE
     }
 
-    fn from_config(config: FeatureConfig) -> Self {
-        let field_name = config.str_at("field").unwrap().to_string();
-        let analyzer_name = config.str_at("analyzer").unwrap();
-        let analyzer: Box<dyn Analyzer> = match analyzer_name {
-            "uax29" => Box::new(UAX29Analyzer),
-            "whitespace" => Box::new(WhiteSpaceAnalyzer),
-            "noop" => Box::new(NoopAnalyzer),
-            _ => panic!("No such analyzer"),
-        };
-        StringIndex {
+    fn from_config(config: FeatureConfig, analyzers: &AnalyzerRegistry) -> Result<Self, Error> {
+        let field_name = config.required_str_at("field")?.to_string();
+        let analyzer =
+            analyzers.from_config(config.at("analyzer").unwrap_or(&FeatureConfig::None))?;
+        Ok(StringIndex {
             field_name,
             analyzer,
-        }
+        })
     }
 
     fn to_config(&self) -> FeatureConfig {
@@ -131,7 +138,7 @@ impl Feature for StringIndex {
         );
         map.insert(
             "analyzer".to_string(),
-            FeatureConfig::String(self.analyzer.analyzer_type().to_string()),
+            self.analyzer.to_config(),
         );
         FeatureConfig::Map(map)
     }
@@ -147,6 +154,7 @@ impl Feature for StringIndex {
     fn reader(&self, address: &FeatureAddress) -> Result<Box<dyn FeatureReader>, Error> {
         let path = address.with_ending(TERM_ID_LISTING);
         if path.exists() {
+            check_header(&mut File::open(address.with_ending(ID_DOC_LISTING))?)?;
             let mmap = unsafe { Mmap::map(&File::open(path)?)? };
             Ok(Box::new({
                 StringIndexReader {
@@ -178,6 +186,7 @@ impl Feature for StringIndex {
         let target_postings_path = new_segment.with_ending(&ID_DOC_LISTING);
         let target_postings_file = File::create(&target_postings_path)?;
         let mut target_postings = BufWriter::new(target_postings_file);
+        target_postings.write_all(ID_DOC_HEADER)?;
 
         let (
             ref mut source_terms,
@@ -197,9 +206,10 @@ impl Feature for StringIndex {
                 if old_terms_path.exists() {
                     let mmap = unsafe { Mmap::map(&File::open(old_terms_path)?)? };
                     source_terms.push(Map::new(mmap)?);
-                    source_postings.push(BufReader::new(File::open(
-                        old_address.with_ending(ID_DOC_LISTING),
-                    )?));
+                    let mut postings =
+                        BufReader::new(File::open(old_address.with_ending(ID_DOC_LISTING))?);
+                    check_header(&mut postings)?;
+                    source_postings.push(postings);
                     source_doc_offsets.push(source_offset);
                     source_offset +=
                         old_info.doc_count - deleted_docs.iter().filter(|b| *b).count() as u64;
@@ -221,7 +231,7 @@ impl Feature for StringIndex {
             op_builder.push(map.stream());
         }
         let mut union = op_builder.union();
-        let mut postings_offset = 0u64;
+        let mut postings_offset = ID_DOC_HEADER.len() as u64;
         let mut has_written = false;
         while let Some((term, term_offsets)) = union.next() {
             let mut sorted_offsets = term_offsets.to_vec();
@@ -237,14 +247,16 @@ impl Feature for StringIndex {
                     let diff = read_vint(&mut source_posting)?;
                     let read_doc_id = last_read_doc_id + diff;
                     last_read_doc_id = read_doc_id;
+                    let freq = read_vint(&mut source_posting)?;
                     if !deletions[term_offset.index]
                         .get(read_doc_id as usize)
                         .unwrap_or(false)
                         {
-                            docs_to_write.push(
+                            docs_to_write.push((
                                 source_doc_offsets[term_offset.index]
                                     + deleted_remap[term_offset.index][read_doc_id as usize],
-                            );
+                                freq,
+                            ));
                         }
                 }
             }
@@ -255,9 +267,10 @@ impl Feature for StringIndex {
                 let mut last_written_doc_id = 0u64;
                 postings_offset +=
                     write_vint(&mut target_postings, docs_to_write.len() as u64)? as u64;
-                for doc in docs_to_write {
+                for (doc, freq) in docs_to_write {
                     postings_offset +=
                         write_vint(&mut target_postings, doc - last_written_doc_id)? as u64;
+                    postings_offset += write_vint(&mut target_postings, freq)? as u64;
                     last_written_doc_id = doc;
                 }
             }
@@ -297,6 +310,7 @@ impl StringIndexReader {
                 Ok(Some(TermDocIter {
                     file: iddoc,
                     current_doc_id: 0,
+                    current_freq: 0,
                     finished: false,
                     left: num,
                 }))
@@ -304,6 +318,53 @@ impl StringIndexReader {
         }
     }
 
+    /// The number of docs in this segment which contain the term.
+    pub fn doc_freq(&self, term: &str) -> Result<u64, Error> {
+        match self.term_offset(term)? {
+            None => Ok(0),
+            Some(offset) => {
+                let mut iddoc =
+                    BufReader::new(File::open(self.address.with_ending(ID_DOC_LISTING))?);
+                iddoc.seek(SeekFrom::Start(offset))?;
+                Ok(read_vint(&mut iddoc)?)
+            }
+        }
+    }
+
+    /// The terms in this segment which the automaton accepts, in sorted order.
+    pub fn matching_terms<A: Automaton>(&self, automaton: A) -> Vec<String> {
+        let mut terms = Vec::new();
+        if let Some(ref map) = self.map {
+            let mut stream = map.search(automaton).into_stream();
+            while let Some((term, _)) = stream.next() {
+                terms.push(String::from_utf8_lossy(term).into_owned());
+            }
+        }
+        terms
+    }
+
+    /// The terms in this segment within the bounds, in sorted order.
+    pub fn terms_in_range(&self, lower: Bound<&str>, upper: Bound<&str>) -> Vec<String> {
+        let mut terms = Vec::new();
+        if let Some(ref map) = self.map {
+            let range = match lower {
+                Bound::Included(term) => map.range().ge(term),
+                Bound::Excluded(term) => map.range().gt(term),
+                Bound::Unbounded => map.range(),
+            };
+            let range = match upper {
+                Bound::Included(term) => range.le(term),
+                Bound::Excluded(term) => range.lt(term),
+                Bound::Unbounded => range,
+            };
+            let mut stream = range.into_stream();
+            while let Some((term, _)) = stream.next() {
+                terms.push(String::from_utf8_lossy(term).into_owned());
+            }
+        }
+        terms
+    }
+
     fn term_offset(&self, term: &str) -> Result<Option<u64>, Error> {
         Ok(match self.map {
             Some(ref m) => m.get(term),
@@ -315,6 +376,7 @@ impl StringIndexReader {
 pub struct TermDocIter {
     file: BufReader<File>,
     current_doc_id: DocId,
+    current_freq: u64,
     finished: bool,
     left: u64,
 }
@@ -326,6 +388,7 @@ impl DocIter for TermDocIter {
             match read_vint(&mut self.file) {
                 Ok(diff) => {
                     self.current_doc_id += diff;
+                    self.current_freq = read_vint(&mut self.file)?;
                     Ok(Some(self.current_doc_id))
                 }
                 Err(e) => Err(Error::from(e)),
@@ -345,12 +408,38 @@ impl DocIter for TermDocIter {
     }
 }
 
+impl TermFreqDocIter for TermDocIter {
+    /// The number of times the term occurs in the current doc.
+    fn freq(&mut self) -> Result<u64, Error> {
+        Ok(self.current_freq)
+    }
+}
+
 impl From<fst::Error> for Error {
     fn from(e: fst::Error) -> Self {
         Error::Other(Box::new(e))
     }
 }
 
+/// Fails for postings files in another format than the one this version writes, such as those
+/// written before term frequencies were stored.
+fn check_header<R: Read>(postings: &mut R) -> Result<(), Error> {
+    let mut header = [0u8; ID_DOC_HEADER.len()];
+    match postings.read_exact(&mut header) {
+        Ok(()) if header == ID_DOC_HEADER => Ok(()),
+        Ok(()) => Err(unsupported_format()),
+        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => Err(unsupported_format()),
+        Err(e) => Err(Error::from(e)),
+    }
+}
+
+fn unsupported_format() -> Error {
+    Error::from(::std::io::Error::new(
+        ErrorKind::InvalidData,
+        "Unsupported string index postings format, the index needs to be rebuilt",
+    ))
+}
+
 fn remap_deleted(deleted_docs: &BitVec) -> Vec<u64> {
     let mut new_doc = 0u64;
     let mut ids = Vec::with_capacity(deleted_docs.len());
@@ -362,3 +451,67 @@ fn remap_deleted(deleted_docs: &BitVec) -> Vec<u64> {
     }
     ids
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::StringIndex;
+    use super::StringIndexReader;
+    use super::ID_DOC_LISTING;
+    use analyzis::UAX29Analyzer;
+    use doc::DocDecorator;
+    use doc_iter::DocIter;
+    use doc_iter::TermFreqDocIter;
+    use seg::test_fixtures::feature_address;
+    use seg::test_fixtures::old_segment;
+    use seg::Feature;
+    use seg::FeatureAddress;
+    use std::fs;
+    use tempfile::TempDir;
+    use Doc;
+
+    fn freqs(index: &StringIndex, address: &FeatureAddress, term: &str) -> Vec<(u64, u64)> {
+        let reader = index.reader(address).unwrap();
+        let reader = reader.as_any().downcast_ref::<StringIndexReader>().unwrap();
+        let mut iter = reader.doc_iter(term).unwrap().unwrap();
+        let mut res = Vec::new();
+        while let Some(doc_id) = iter.next_doc().unwrap() {
+            res.push((doc_id, iter.freq().unwrap()));
+        }
+        res
+    }
+
+    #[test]
+    fn keeps_term_freqs_through_merge() {
+        let dir = TempDir::new().unwrap();
+        let index = StringIndex::new("text".to_string(), Box::new(UAX29Analyzer));
+        let docs = vec![
+            Doc::new().string_field("text", "fox fox dog"),
+            Doc::new().string_field("text", "dog"),
+            Doc::new().string_field("text", "fox fox fox"),
+        ];
+        let first = feature_address(&dir, "first");
+        index.write_segment(&first, &docs).unwrap();
+        assert_eq!(vec![(0, 2), (2, 3)], freqs(&index, &first, "fox"));
+        assert_eq!(vec![(0, 1), (1, 1)], freqs(&index, &first, "dog"));
+
+        let merged = feature_address(&dir, "merged");
+        index
+            .merge_segments(&[old_segment(&first, docs.len(), &[0])], &merged)
+            .unwrap();
+        assert_eq!(vec![(1, 3)], freqs(&index, &merged, "fox"));
+        assert_eq!(vec![(0, 1)], freqs(&index, &merged, "dog"));
+    }
+
+    #[test]
+    fn refuses_postings_without_header() {
+        let dir = TempDir::new().unwrap();
+        let index = StringIndex::new("text".to_string(), Box::new(UAX29Analyzer));
+        let docs = vec![Doc::new().string_field("text", "fox")];
+        let address = feature_address(&dir, "old");
+        index.write_segment(&address, &docs).unwrap();
+        // The postings of "fox" as written before the header: one doc, with id 0.
+        fs::write(address.with_ending(ID_DOC_LISTING), &[1u8, 0u8]).unwrap();
+        assert!(index.reader(&address).is_err());
+    }
+}
@@ -6,25 +6,27 @@ use std::io::BufWriter;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
+use std::ops::Bound;
 
 use bit_vec::BitVec;
 use fasthash::RandomState;
 use fasthash::sea::Hash64;
 use fst::map::OpBuilder;
-use fst::{Map, MapBuilder, Streamer};
+use fst::{Automaton, IntoStreamer, Map, MapBuilder, Streamer};
 use indexmap::map;
 use indexmap::IndexMap;
 use memmap::Mmap;
 use smallvec::SmallVec;
 
 use analyzis::Analyzer;
-use analyzis::NoopAnalyzer;
-use analyzis::UAX29Analyzer;
-use analyzis::WhiteSpaceAnalyzer;
+use analyzis::AnalyzerRegistry;
+use analyzis::Token;
+use doc::field_values;
 use doc::FieldValue;
 use doc_iter::DocIter;
 use doc_iter::DocSpansIter;
 use doc_iter::Position;
+use doc_iter::TermFreqDocIter;
 use error::Error;
 use seg::Feature;
 use seg::FeatureAddress;
@@ -39,6 +41,33 @@ use DocId;
 const TERM_ID_LISTING: &str = "tid";
 const ID_DOC_LISTING: &str = "iddoc";
 
+/// Positions left between the values of an array, so that phrases do not match across values.
+pub const POSITION_GAP: Position = 100;
+
+/// The tokens of the values of a field together with their positions. The first token of a value
+/// with a position increment of 1 gets the first position of the value.
+pub fn positioned_tokens<'a>(
+    analyzer: &dyn Analyzer,
+    values: &[&'a FieldValue],
+) -> Vec<(Position, Token<'a>)> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    for single_value in values.iter().flat_map(|value| value.values()) {
+        let mut last_pos = None;
+        for token in analyzer.value_tokens(single_value) {
+            let increment = Position::from(token.position_increment);
+            let pos = match last_pos {
+                Some(last_pos) => last_pos + increment,
+                None => start + increment.saturating_sub(1),
+            };
+            tokens.push((pos, token));
+            last_pos = Some(pos);
+        }
+        start = last_pos.map_or(start, |pos| pos + 1) + POSITION_GAP;
+    }
+    tokens
+}
+
 #[derive(Clone)]
 pub struct StringPosIndex {
     pub field_name: String,
@@ -46,6 +75,9 @@ pub struct StringPosIndex {
 }
 
 impl StringPosIndex {
+    /// The name this feature is stored and registered under.
+    pub const FEATURE_TYPE: &'static str = "string_pos_index";
+
     pub fn new(field_name: String, analyzer: Box<dyn Analyzer>) -> StringPosIndex {
         StringPosIndex {
             field_name,
@@ -61,33 +93,26 @@ impl StringPosIndex {
         let s = RandomState::<Hash64>::new();
         let mut map = IndexMap::with_hasher(s);
         for (doc_id, doc) in docs.iter().enumerate() {
-            for (_name, val) in doc.iter().filter(|e| e.0 == field_name) {
-                match *val {
-                    FieldValue::String(ref value) => {
-                        for (pos, token) in analyzer.analyze(value).enumerate() {
-                            match map.entry(token) {
-                                map::Entry::Vacant(vacant) => {
-                                    let mut pos_vec = SmallVec::<[u64; 1]>::new();
-                                    pos_vec.push(pos as u64);
-                                    let mut doc_pos_vec =
-                                        SmallVec::<[(u64, SmallVec<[u64; 1]>); 1]>::new();
-                                    doc_pos_vec.push((doc_id as u64, pos_vec));
-                                    vacant.insert(doc_pos_vec);
-                                }
-                                map::Entry::Occupied(mut occupied) => {
-                                    let term_docs = occupied.get_mut();
-                                    if term_docs.last().unwrap().0 == doc_id as u64 {
-                                        term_docs.last_mut().unwrap().1.push(pos as u64);
-                                    } else {
-                                        let mut pos_vec = SmallVec::<[u64; 1]>::new();
-                                        pos_vec.push(pos as u64);
-                                        term_docs.push((doc_id as u64, pos_vec))
-                                    }
-                                }
-                            }
+            for (pos, token) in positioned_tokens(&**analyzer, &field_values(doc, field_name)) {
+                match map.entry(token.text) {
+                    map::Entry::Vacant(vacant) => {
+                        let mut pos_vec = SmallVec::<[u64; 1]>::new();
+                        pos_vec.push(pos);
+                        let mut doc_pos_vec = SmallVec::<[(u64, SmallVec<[u64; 1]>); 1]>::new();
+                        doc_pos_vec.push((doc_id as u64, pos_vec));
+                        vacant.insert(doc_pos_vec);
+                    }
+                    map::Entry::Occupied(mut occupied) => {
+                        let term_docs = occupied.get_mut();
+                        if term_docs.last().unwrap().0 == doc_id as u64 {
+                            term_docs.last_mut().unwrap().1.push(pos);
+                        } else {
+                            let mut pos_vec = SmallVec::<[u64; 1]>::new();
+                            pos_vec.push(pos);
+                            term_docs.push((doc_id as u64, pos_vec))
                         }
                     }
-                };
+                }
             }
         }
         map.sort_keys();
@@ -112,10 +137,7 @@ impl StringPosIndex {
             let mut prev_doc_id = 0u64;
             let mut prev_pos_offset = 0u64;
             for (doc_id, positions) in doc_ids_and_pos {
-                id_offset += u64::from(write_vint(
-                    &mut target_postings,
-                    doc_id - prev_doc_id,
-                )?);
+                id_offset += u64::from(write_vint(&mut target_postings, doc_id - prev_doc_id)?);
                 id_offset += u64::from(write_vint(
                     &mut target_postings,
                     pos_offset - prev_pos_offset,
@@ -124,8 +146,7 @@ impl StringPosIndex {
                 pos_offset += u64::from(write_vint(&mut target_positions, positions.len() as u64)?);
                 let mut last_pos = 0u64;
                 for pos in positions {
-                    pos_offset +=
-                        u64::from(write_vint(&mut target_positions, pos - last_pos)?);
+                    pos_offset += u64::from(write_vint(&mut target_positions, pos - last_pos)?);
                     last_pos = pos;
                 }
                 prev_doc_id = doc_id;
@@ -140,22 +161,17 @@ impl StringPosIndex {
 
 impl Feature for StringPosIndex {
     fn feature_type(&self) -> &'static str {
-        "string_pos_index"
+        Self::FEATURE_TYPE
     }
 
-    fn from_config(config: FeatureConfig) -> Self {
-        let field_name = config.str_at("field").unwrap().to_string();
-        let analyzer_name = config.str_at("analyzer").unwrap();
-        let analyzer: Box<dyn Analyzer> = match analyzer_name {
-            "uax29" => Box::new(UAX29Analyzer),
-            "whitespace" => Box::new(WhiteSpaceAnalyzer),
-            "noop" => Box::new(NoopAnalyzer),
-            _ => panic!("No such analyzer"),
-        };
-        StringPosIndex {
+    fn from_config(config: FeatureConfig, analyzers: &AnalyzerRegistry) -> Result<Self, Error> {
+        let field_name = config.required_str_at("field")?.to_string();
+        let analyzer =
+            analyzers.from_config(config.at("analyzer").unwrap_or(&FeatureConfig::None))?;
+        Ok(StringPosIndex {
             field_name,
             analyzer,
-        }
+        })
     }
 
     fn to_config(&self) -> FeatureConfig {
@@ -166,7 +182,7 @@ impl Feature for StringPosIndex {
         );
         map.insert(
             "analyzer".to_string(),
-            FeatureConfig::String(self.analyzer.analyzer_type().to_string()),
+            self.analyzer.to_config(),
         );
         FeatureConfig::Map(map)
     }
@@ -379,6 +395,7 @@ impl StringPosIndexReader {
                     current_doc_id: 0,
                     current_pos_offset: 0,
                     current_pos: 0,
+                    pos_count: 0,
                     pos_left: 0,
                     finished: false,
                     new_pos_offset: false,
@@ -388,6 +405,53 @@ impl StringPosIndexReader {
         }
     }
 
+    /// The number of docs in this segment which contain the term.
+    pub fn doc_freq(&self, term: &str) -> Result<u64, Error> {
+        match self.term_offset(term)? {
+            None => Ok(0),
+            Some(offset) => {
+                let mut iddoc =
+                    BufReader::new(File::open(self.address.with_ending(ID_DOC_LISTING))?);
+                iddoc.seek(SeekFrom::Start(offset))?;
+                Ok(read_vint(&mut iddoc)?)
+            }
+        }
+    }
+
+    /// The terms in this segment which the automaton accepts, in sorted order.
+    pub fn matching_terms<A: Automaton>(&self, automaton: A) -> Vec<String> {
+        let mut terms = Vec::new();
+        if let Some(ref map) = self.map {
+            let mut stream = map.search(automaton).into_stream();
+            while let Some((term, _)) = stream.next() {
+                terms.push(String::from_utf8_lossy(term).into_owned());
+            }
+        }
+        terms
+    }
+
+    /// The terms in this segment within the bounds, in sorted order.
+    pub fn terms_in_range(&self, lower: Bound<&str>, upper: Bound<&str>) -> Vec<String> {
+        let mut terms = Vec::new();
+        if let Some(ref map) = self.map {
+            let range = match lower {
+                Bound::Included(term) => map.range().ge(term),
+                Bound::Excluded(term) => map.range().gt(term),
+                Bound::Unbounded => map.range(),
+            };
+            let range = match upper {
+                Bound::Included(term) => range.le(term),
+                Bound::Excluded(term) => range.lt(term),
+                Bound::Unbounded => range,
+            };
+            let mut stream = range.into_stream();
+            while let Some((term, _)) = stream.next() {
+                terms.push(String::from_utf8_lossy(term).into_owned());
+            }
+        }
+        terms
+    }
+
     fn term_offset(&self, term: &str) -> Result<Option<u64>, Error> {
         Ok(match self.map {
             Some(ref m) => m.get(term),
@@ -402,6 +466,7 @@ pub struct TermDocSpansIter {
     current_doc_id: DocId,
     current_pos_offset: u64,
     current_pos: u64,
+    pos_count: u64,
     pos_left: u64,
     finished: bool,
     new_pos_offset: bool,
@@ -433,15 +498,30 @@ impl DocIter for TermDocSpansIter {
     }
 }
 
-impl DocSpansIter for TermDocSpansIter {
-    fn next_start_pos(&mut self) -> Result<Option<Position>, Error> {
+impl TermDocSpansIter {
+    fn load_positions(&mut self) -> Result<(), Error> {
         if self.new_pos_offset {
             self.new_pos_offset = false;
             self.pos_file
                 .seek(SeekFrom::Start(self.current_pos_offset))?;
-            self.pos_left = read_vint(&mut self.pos_file)?;
+            self.pos_count = read_vint(&mut self.pos_file)?;
+            self.pos_left = self.pos_count;
             self.current_pos = 0;
         }
+        Ok(())
+    }
+}
+
+impl TermFreqDocIter for TermDocSpansIter {
+    fn freq(&mut self) -> Result<u64, Error> {
+        self.load_positions()?;
+        Ok(self.pos_count)
+    }
+}
+
+impl DocSpansIter for TermDocSpansIter {
+    fn next_start_pos(&mut self) -> Result<Option<Position>, Error> {
+        self.load_positions()?;
         if self.pos_left == 0 {
             return Ok(None);
         }
@@ -475,3 +555,130 @@ fn remap_deleted(deleted_docs: &BitVec) -> Vec<u64> {
     }
     ids
 }
+
+#[cfg(test)]
+mod tests {
+
+    use std::borrow::Cow;
+
+    use super::positioned_tokens;
+    use super::StringPosIndex;
+    use super::StringPosIndexReader;
+    use super::POSITION_GAP;
+    use analyzis::Analyzer;
+    use analyzis::Token;
+    use analyzis::WhiteSpaceAnalyzer;
+    use doc::DocDecorator;
+    use doc::FieldValue;
+    use doc_iter::DocIter;
+    use doc_iter::DocSpansIter;
+    use doc_iter::OrderedNearDocSpansIter;
+    use seg::Feature;
+    use seg::FeatureAddress;
+    use seg::SegmentAddress;
+    use tempfile::TempDir;
+    use Doc;
+
+    /// Splits on whitespace and drops "of", leaving a gap where it was.
+    #[derive(Clone, Debug)]
+    struct GapAnalyzer;
+
+    impl Analyzer for GapAnalyzer {
+        fn analyzer_type(&self) -> &'static str {
+            "gap"
+        }
+
+        fn tokens<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Token<'a>> + 'a> {
+            let mut removed = 0;
+            Box::new(
+                WhiteSpaceAnalyzer
+                    .tokens(value)
+                    .filter_map(move |mut token| {
+                        if token.text == "of" {
+                            removed += token.position_increment;
+                            None
+                        } else {
+                            token.position_increment += removed;
+                            removed = 0;
+                            Some(token)
+                        }
+                    }),
+            )
+        }
+
+        fn analyze<'a>(&self, value: &'a str) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+            Box::new(self.tokens(value).map(|token| token.text))
+        }
+    }
+
+    #[test]
+    fn positions_honor_increments() {
+        let values = vec![
+            FieldValue::String("of tower of london".to_string()),
+            FieldValue::String("of".to_string()),
+            FieldValue::String("tower".to_string()),
+        ];
+        let value = FieldValue::Array(values);
+        let positions: Vec<(u64, String)> = positioned_tokens(&GapAnalyzer, &[&value])
+            .into_iter()
+            .map(|(pos, token)| (pos, token.text.into_owned()))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, "tower".to_string()),
+                (3, "london".to_string()),
+                (4 + 2 * POSITION_GAP, "tower".to_string()),
+            ],
+            positions
+        );
+    }
+
+    #[test]
+    fn phrases_keep_gaps() {
+        let dir = TempDir::new().unwrap();
+        let address = FeatureAddress {
+            segment: SegmentAddress {
+                path: dir.path().to_path_buf(),
+                name: "seg".to_string(),
+            },
+            name: "text".to_string(),
+        };
+        let feature = StringPosIndex::new("text".to_string(), Box::new(GapAnalyzer));
+        let docs = vec![
+            Doc::new().string_field("text", "tower of london"),
+            Doc::new().string_field("text", "tower london"),
+        ];
+        feature.write_segment(&address, &docs).unwrap();
+        let reader = feature.reader(&address).unwrap();
+        let reader = reader
+            .as_any()
+            .downcast_ref::<StringPosIndexReader>()
+            .unwrap();
+        let phrase = |slop| {
+            let sub_spans: Vec<Box<dyn DocSpansIter>> = ["tower", "london"]
+                .iter()
+                .map(|term| {
+                    Box::new(reader.doc_spans_iter(term).unwrap().unwrap()) as Box<dyn DocSpansIter>
+                })
+                .collect();
+            let mut iter = OrderedNearDocSpansIter::with_slop(sub_spans, slop);
+            let mut doc_ids = Vec::new();
+            while let Some(doc_id) = iter.next_doc().unwrap() {
+                doc_ids.push(doc_id);
+            }
+            doc_ids
+        };
+        assert_eq!(vec![1], phrase(0));
+        assert_eq!(vec![0, 1], phrase(1));
+
+        let sub_spans: Vec<Box<dyn DocSpansIter>> = ["tower", "london"]
+            .iter()
+            .map(|term| {
+                Box::new(reader.doc_spans_iter(term).unwrap().unwrap()) as Box<dyn DocSpansIter>
+            })
+            .collect();
+        let mut iter = OrderedNearDocSpansIter::with_slop(sub_spans, 0).with_offsets(vec![0, 2]);
+        assert_eq!(Some(0), iter.next_doc().unwrap());
+        assert_eq!(None, iter.next_doc().unwrap());
+    }
+}
@@ -28,11 +28,63 @@ pub fn read_vint(read: &mut dyn Read) -> Result<u64, Error> {
     Ok(res as u64)
 }
 
+/// The number of bits needed to store all values from zero up to `max`.
+pub fn bits_required(max: u64) -> u8 {
+    (64 - max.leading_zeros()) as u8
+}
+
+/// Writes the values packed with `bits` bits each, and returns the number of bytes written.
+pub fn write_packed(write: &mut dyn Write, values: &[u64], bits: u8) -> Result<u64, Error> {
+    let mut buffer = 0u128;
+    let mut buffered_bits = 0u32;
+    let mut written = 0u64;
+    for &value in values {
+        buffer |= u128::from(value) << buffered_bits;
+        buffered_bits += u32::from(bits);
+        while buffered_bits >= 8 {
+            write.write_all(&[buffer as u8])?;
+            buffer >>= 8;
+            buffered_bits -= 8;
+            written += 1;
+        }
+    }
+    if buffered_bits > 0 {
+        write.write_all(&[buffer as u8])?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Reads the value at `index` from values written by `write_packed`.
+#[inline]
+pub fn read_packed(data: &[u8], bits: u8, index: u64) -> u64 {
+    if bits == 0 {
+        return 0;
+    }
+    let bit_pos = index * u64::from(bits);
+    let start = (bit_pos / 8) as usize;
+    let end = (bit_pos + u64::from(bits)).div_ceil(8) as usize;
+    let mut buffer = 0u128;
+    for (i, b) in data[start..end].iter().enumerate() {
+        buffer |= u128::from(*b) << (8 * i);
+    }
+    let mask = if bits == 64 {
+        u64::MAX
+    } else {
+        (1u64 << bits) - 1
+    };
+    (buffer >> (bit_pos % 8)) as u64 & mask
+}
+
 #[cfg(test)]
 mod tests {
 
+    use super::bits_required;
+    use super::read_packed;
     use super::read_vint;
+    use super::write_packed;
     use super::write_vint;
+    use proptest::collection::vec;
     use proptest::prelude::*;
     use proptest::test_runner::Config;
     use std::io::Cursor;
@@ -47,4 +99,18 @@ mod tests {
             assert!(num == read_vint(&mut write).unwrap())
         }
     }
+
+    proptest! {
+        #[test]
+        fn read_write_packed_correct(ref values in vec(any::<u64>(), 0..100), shift in 0u32..64) {
+            let values: Vec<u64> = values.iter().map(|v| v >> shift).collect();
+            let bits = bits_required(values.iter().cloned().max().unwrap_or(0));
+            let mut data = Vec::new();
+            let written = write_packed(&mut data, &values, bits).unwrap();
+            assert_eq!(data.len() as u64, written);
+            for (i, value) in values.iter().enumerate() {
+                assert_eq!(*value, read_packed(&data, bits, i as u64));
+            }
+        }
+    }
 }
@@ -82,11 +82,51 @@ fn format_doc(doc: &Doc) -> String {
                 field_name,
                 s.replace("\n", "\\n").replace("\"", "\\\"")
             )),
+            FieldValue::Int(i) => ret.push_str(&format!(".int_field(\"{}\", {})", field_name, i)),
+            FieldValue::Float(f) => {
+                ret.push_str(&format!(".float_field(\"{}\", {:?})", field_name, f))
+            }
+            FieldValue::Bool(b) => ret.push_str(&format!(".bool_field(\"{}\", {})", field_name, b)),
+            FieldValue::Array(values) => ret.push_str(&format!(
+                ".array_field(\"{}\", {})",
+                field_name,
+                format_values(values)
+            )),
+            FieldValue::Object(fields) => ret.push_str(&format!(
+                ".object_field(\"{}\", {})",
+                field_name,
+                format_doc(fields)
+            )),
         }
     }
     ret
 }
 
+fn format_value(value: &FieldValue) -> String {
+    match value {
+        FieldValue::String(s) => format!(
+            "FieldValue::String(\"{}\".to_string())",
+            s.replace("\n", "\\n").replace("\"", "\\\"")
+        ),
+        FieldValue::Int(i) => format!("FieldValue::Int({})", i),
+        FieldValue::Float(f) => format!("FieldValue::Float({:?})", f),
+        FieldValue::Bool(b) => format!("FieldValue::Bool({})", b),
+        FieldValue::Array(values) => format!("FieldValue::Array({})", format_values(values)),
+        FieldValue::Object(fields) => format!("FieldValue::Object({})", format_doc(fields)),
+    }
+}
+
+fn format_values(values: &[FieldValue]) -> String {
+    format!(
+        "vec![{}]",
+        values
+            .iter()
+            .map(format_value)
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+}
+
 fn format_queries<'a>(queries: &'a [Box<dyn Query>]) -> String {
     format!(
         "vec![{}]",
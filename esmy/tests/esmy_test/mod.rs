@@ -5,10 +5,21 @@ use proptest::strategy::Just;
 use proptest::strategy::Strategy;
 use tempfile::TempDir;
 
+use esmy::aggregation::AggregationCollector;
+use esmy::aggregation::AggregationResult;
+use esmy::aggregation::Aggregator;
 use esmy::index::Index;
 use esmy::index::IndexBuilder;
+use esmy::doc_iter::DocIter;
+use esmy::error::Error;
 use esmy::search::AllDocsCollector;
+use esmy::search::Collector;
+use esmy::search::Hit;
 use esmy::search::Query;
+use esmy::search::SortField;
+use esmy::search::SortingCollector;
+use esmy::search::TopDocsCollector;
+use esmy::seg::SegmentReader;
 use esmy::seg::SegmentSchema;
 use esmy::Doc;
 use std::path::PathBuf;
@@ -31,6 +42,57 @@ pub fn index_and_assert_search_matches(
     ops: &[IndexOperation],
     queries: &[Box<dyn Query>],
 ) {
+    with_index_state(schema, ops, |state| state.check_queries_match_same(queries));
+}
+
+pub fn index_and_assert_top_docs(
+    schema: &SegmentSchema,
+    ops: &[IndexOperation],
+    queries: &[Box<dyn Query>],
+    size: usize,
+) {
+    with_index_state(schema, ops, |state| state.check_top_docs(queries, size));
+}
+
+/// Checks that sorted search gives the first matching docs as sorted by the key, which should
+/// order docs the same way as the sort fields do.
+pub fn index_and_assert_sorted<K, T>(
+    schema: &SegmentSchema,
+    ops: &[IndexOperation],
+    queries: &[Box<dyn Query>],
+    size: usize,
+    sort: &[SortField],
+    key: K,
+) where
+    K: Fn(&Doc) -> T,
+    T: Ord + ::std::fmt::Debug,
+{
+    with_index_state(schema, ops, |state| {
+        state.check_sorted(queries, size, sort, &key)
+    });
+}
+
+/// Checks that aggregating the docs matching each query gives what `expected` gives for the
+/// retained docs matching the query.
+pub fn index_and_assert_aggregation<A, E>(
+    schema: &SegmentSchema,
+    ops: &[IndexOperation],
+    queries: &[Box<dyn Query>],
+    aggregator: A,
+    expected: E,
+) where
+    A: Fn() -> Box<dyn Aggregator>,
+    E: Fn(&[&Doc]) -> AggregationResult,
+{
+    with_index_state(schema, ops, |state| {
+        state.check_aggregation(queries, &aggregator, &expected)
+    });
+}
+
+fn with_index_state<F>(schema: &SegmentSchema, ops: &[IndexOperation], check: F)
+where
+    F: Fn(&IndexTestState),
+{
     let index_dir = TempDir::new().unwrap();
     {
         let index = IndexBuilder::new()
@@ -43,10 +105,9 @@ pub fn index_and_assert_search_matches(
             index,
             in_mem_docs: Vec::new(),
             in_mem_seg_docs: Vec::new(),
-            docs_to_delete: Vec::new(),
         };
         index_test_state.apply_ops(ops);
-        index_test_state.check_queries_match_same(queries);
+        check(&index_test_state);
     }
     index_dir.close().unwrap();
 }
@@ -56,7 +117,6 @@ struct IndexTestState {
     index: Index,
     in_mem_docs: Vec<Doc>,
     in_mem_seg_docs: Vec<Doc>,
-    docs_to_delete: Vec<Doc>,
 }
 
 impl IndexTestState {
@@ -82,12 +142,8 @@ impl IndexTestState {
                 }
                 &IndexOperation::Delete(ref query) => {
                     self.index.delete(query).expect("Could not delete docs.");
-                    self.docs_to_delete.extend(
-                        self.in_mem_docs
-                            .iter()
-                            .filter(|d| query.matches(d))
-                            .cloned(),
-                    );
+                    //Only committed docs are deleted, so uncommitted equal docs must be kept
+                    self.in_mem_docs.retain(|d| !query.matches(d));
                 }
                 &IndexOperation::ReOpen => {
                     self.index.commit().expect("Could not commit segment.");
@@ -104,14 +160,109 @@ impl IndexTestState {
         }
     }
 
+    fn retained_docs(&self) -> Vec<Doc> {
+        self.in_mem_docs.clone()
+    }
+
+    fn check_top_docs(&self, queries: &[Box<dyn Query>], size: usize) {
+        let reader = self.index.open_reader().unwrap();
+        let retained_docs = self.retained_docs();
+        queries.iter().for_each(|query| {
+            let expected_count = retained_docs.iter().filter(|doc| query.matches(doc)).count();
+            let mut collector = TopDocsCollector::new(size);
+            reader.search(query, &mut collector).unwrap();
+            assert_eq!(expected_count as u64, collector.total_hits());
+            let hits = collector.hits();
+            assert_eq!(expected_count.min(size), hits.len());
+
+            let mut all_hits = AllHitsCollector { hits: Vec::new() };
+            reader.search(query, &mut all_hits).unwrap();
+            assert_eq!(expected_count, all_hits.hits.len());
+            //A stable sort keeps the earlier hit first on ties, as the top docs do
+            let mut expected_hits = all_hits.hits;
+            expected_hits.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+            expected_hits.truncate(size);
+            assert_eq!(
+                expected_hits, hits,
+                "Top hits were not the best scored of all hits for query {:?}",
+                query
+            );
+            for doc in collector.docs(&reader).unwrap() {
+                assert!(
+                    query.matches(&doc),
+                    "Top doc {:?} did not match query {:?}",
+                    doc,
+                    query
+                );
+            }
+        });
+    }
+
+    fn check_sorted<K, T>(
+        &self,
+        queries: &[Box<dyn Query>],
+        size: usize,
+        sort: &[SortField],
+        key: &K,
+    ) where
+        K: Fn(&Doc) -> T,
+        T: Ord + ::std::fmt::Debug,
+    {
+        let reader = self.index.open_reader().unwrap();
+        let retained_docs = self.retained_docs();
+        queries.iter().for_each(|query| {
+            let mut expected_keys: Vec<T> = retained_docs
+                .iter()
+                .filter(|doc| query.matches(doc))
+                .map(|doc| key(doc))
+                .collect();
+            expected_keys.sort();
+            let expected_count = expected_keys.len();
+            expected_keys.truncate(size);
+            let mut collector = SortingCollector::new(size, sort.to_vec());
+            reader.search(query, &mut collector).unwrap();
+            assert_eq!(expected_count as u64, collector.total_hits());
+            let actual_keys: Vec<T> = collector
+                .docs(&reader)
+                .unwrap()
+                .iter()
+                .map(|doc| key(doc))
+                .collect();
+            assert_eq!(
+                expected_keys, actual_keys,
+                "Docs not sorted as expected for query {:?}",
+                query
+            );
+        });
+    }
+
+    fn check_aggregation<A, E>(&self, queries: &[Box<dyn Query>], aggregator: &A, expected: &E)
+    where
+        A: Fn() -> Box<dyn Aggregator>,
+        E: Fn(&[&Doc]) -> AggregationResult,
+    {
+        let reader = self.index.open_reader().unwrap();
+        let retained_docs = self.retained_docs();
+        queries.iter().for_each(|query| {
+            let matching: Vec<&Doc> = retained_docs
+                .iter()
+                .filter(|doc| query.matches(doc))
+                .collect();
+            let mut collector = AggregationCollector::new().add("agg", aggregator());
+            reader.search(query, &mut collector).unwrap();
+            assert_eq!(matching.len() as u64, collector.total_hits());
+            assert_eq!(
+                expected(&matching),
+                collector.result("agg").unwrap(),
+                "Unexpected aggregation for query {:?}",
+                query
+            );
+        });
+    }
+
     fn check_queries_match_same(&self, queries: &[Box<dyn Query>]) {
         let reader = self.index.open_reader().unwrap();
-        let retained_docs: Vec<Doc> = self
-            .in_mem_docs
-            .iter()
-            .filter(|d| !self.docs_to_delete.contains(d))
-            .cloned()
-            .collect();
+        let retained_docs = self.retained_docs();
         queries.iter().for_each(|query| {
             let expected_matches: Vec<Doc> = retained_docs
                 .iter()
@@ -125,6 +276,26 @@ impl IndexTestState {
     }
 }
 
+/// Keeps every scored hit, in the order they are found.
+struct AllHitsCollector {
+    hits: Vec<Hit>,
+}
+
+impl Collector for AllHitsCollector {
+    fn collect_for(&mut self, reader: &SegmentReader, docs: &mut dyn DocIter) -> Result<(), Error> {
+        while let Some(doc_id) = docs.next_doc()? {
+            if !reader.deleted_docs().get(doc_id as usize).unwrap_or(false) {
+                self.hits.push(Hit {
+                    segment: reader.info().address.clone(),
+                    doc_id,
+                    score: docs.score()?,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
 fn assert_same_docs(query: &dyn Query, expected: &[Doc], actual: &[Doc]) {
     if expected != actual {
         for doc in expected {
@@ -1,18 +1,29 @@
 use std::collections::HashSet;
+use std::ops::Bound;
 
 use proptest::collection::vec;
+use proptest::option;
 use proptest::prelude::BoxedStrategy;
 use proptest::strategy::Just;
 use proptest::strategy::Strategy;
 
 use esmy::analyzis::Analyzer;
+use esmy::doc::field_values;
 use esmy::doc::FieldValue;
 use esmy::search::AllQuery;
+use esmy::search::BooleanQuery;
+use esmy::search::FuzzyQuery;
 use esmy::search::MatchAllDocsQuery;
+use esmy::search::PrefixQuery;
 use esmy::search::Query;
+use esmy::search::RangeQuery;
+use esmy::search::RegexQuery;
+use esmy::search::SpanNearQuery;
 use esmy::search::TermQuery;
+use esmy::search::TermRangeQuery;
 use esmy::search::TextQuery;
 use esmy::search::ValueQuery;
+use esmy::search::WildcardQuery;
 use esmy::Doc;
 
 pub fn match_all_docs() -> BoxedStrategy<Box<dyn Query>> {
@@ -98,6 +109,129 @@ pub fn text_query(
     }
 }
 
+pub fn sloppy_text_query(
+    docs: &[&Doc],
+    field_name: &'static str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    (arb_near_terms(docs, field_name, analyzer.clone()), 0u64..3)
+        .prop_map(move |(terms, slop)| {
+            Box::new(TextQuery::new(field_name, terms.join(" "), analyzer.clone()).with_slop(slop))
+                as Box<dyn Query>
+        })
+        .boxed()
+}
+
+/// Like `sloppy_text_query`, but picks from the words of a doc value rather than from its
+/// tokens, so that words which the analyzer removes can leave gaps in the phrase.
+pub fn sloppy_phrase_query(
+    docs: &[&Doc],
+    field_name: &'static str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    let word_lists: Vec<Vec<String>> = extract_doc_values(docs, field_name)
+        .iter()
+        .map(|v| v.split_whitespace().map(|w| w.to_string()).collect())
+        .collect();
+    (arb_near_words(word_lists), 0u64..3)
+        .prop_map(move |(words, slop)| {
+            Box::new(TextQuery::new(field_name, words.join(" "), analyzer.clone()).with_slop(slop))
+                as Box<dyn Query>
+        })
+        .boxed()
+}
+
+pub fn span_near_query(
+    docs: &[&Doc],
+    field_name: &'static str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    (
+        arb_near_terms(docs, field_name, analyzer.clone()),
+        0u64..3,
+        proptest::bool::ANY,
+        proptest::bool::ANY,
+    )
+        .prop_map(move |(mut terms, slop, in_order, reverse)| {
+            if reverse {
+                terms.reverse();
+            }
+            Box::new(
+                SpanNearQuery::new(field_name, terms, slop, analyzer.clone()).in_order(in_order),
+            ) as Box<dyn Query>
+        })
+        .boxed()
+}
+
+/// Phrases made of the last token of one array value and the first token of the next.
+pub fn text_query_across_values(
+    docs: &[&Doc],
+    field_name: &'static str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    let mut phrases = Vec::new();
+    for doc in docs {
+        if let Some(FieldValue::Array(values)) = doc.get(field_name) {
+            for pair in values.windows(2) {
+                let last = analyzer.analyze_value(&pair[0]).last();
+                let first = analyzer.analyze_value(&pair[1]).next();
+                if let (Some(last), Some(first)) = (last, first) {
+                    phrases.push(format!("{} {}", last, first));
+                }
+            }
+        }
+    }
+    if phrases.is_empty() {
+        phrases.push("cat fish".to_string());
+    }
+    (0..phrases.len(), 0u64..3)
+        .prop_map(move |(i, slop)| {
+            Box::new(
+                TextQuery::new(field_name, phrases[i].clone(), analyzer.clone()).with_slop(slop),
+            ) as Box<dyn Query>
+        })
+        .boxed()
+}
+
+/// Picks some of the tokens of a doc value, in order, possibly with gaps between them.
+fn arb_near_terms(
+    docs: &[&Doc],
+    field_name: &str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Vec<String>> {
+    let token_lists: Vec<Vec<String>> = extract_doc_values(docs, field_name)
+        .iter()
+        .map(|v| analyzer.analyze(v).map(|t| t.into_owned()).collect())
+        .collect();
+    arb_near_words(token_lists)
+}
+
+/// Picks some of the words of one of the lists, in order, possibly with gaps between them.
+fn arb_near_words(word_lists: Vec<Vec<String>>) -> BoxedStrategy<Vec<String>> {
+    let token_lists: Vec<Vec<String>> = word_lists
+        .into_iter()
+        .filter(|tokens| !tokens.is_empty())
+        .collect();
+    if token_lists.is_empty() {
+        return Just(vec!["cat".to_string(), "fish".to_string()]).boxed();
+    }
+    (0..token_lists.len(), vec(proptest::bool::ANY, 8))
+        .prop_map(move |(i, keep)| {
+            let tokens = &token_lists[i];
+            let mut terms: Vec<String> = tokens
+                .iter()
+                .zip(keep.iter())
+                .filter(|(_, keep)| **keep)
+                .map(|(token, _)| token.clone())
+                .collect();
+            if terms.is_empty() {
+                terms.push(tokens[0].clone());
+            }
+            terms
+        })
+        .boxed()
+}
+
 pub fn all_queries(
     docs: &[&Doc],
     field_name: &'static str,
@@ -108,6 +242,224 @@ pub fn all_queries(
         .boxed()
 }
 
+pub fn boolean_queries(
+    docs: &[&Doc],
+    field_name: &'static str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    let clauses = || vec(term_query(docs, field_name, analyzer.clone()), 0..3);
+    (
+        clauses(),
+        clauses(),
+        clauses(),
+        clauses(),
+        option::of(0usize..3),
+    )
+        .prop_map(|(must, should, must_not, filter, minimum_should_match)| {
+            let mut query = BooleanQuery::new();
+            for q in must {
+                query = query.must(q);
+            }
+            for q in should {
+                query = query.should(q);
+            }
+            for q in must_not {
+                query = query.must_not(q);
+            }
+            for q in filter {
+                query = query.filter(q);
+            }
+            if let Some(minimum_should_match) = minimum_should_match {
+                query = query.minimum_should_match(minimum_should_match);
+            }
+            Box::new(query) as Box<dyn Query>
+        })
+        .boxed()
+}
+
+pub fn prefix_query(
+    docs: &[&Doc],
+    field_name: &'static str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    (arb_term(docs, field_name, analyzer.clone()), 0usize..4)
+        .prop_map(move |(term, len)| {
+            let prefix: String = term.chars().take(len).collect();
+            Box::new(PrefixQuery::new(field_name, prefix, analyzer.clone())) as Box<dyn Query>
+        })
+        .boxed()
+}
+
+pub fn wildcard_query(
+    docs: &[&Doc],
+    field_name: &'static str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    (
+        arb_term(docs, field_name, analyzer.clone()),
+        0usize..6,
+        0usize..6,
+    )
+        .prop_map(move |(term, any_char_at, any_chars_at)| {
+            let mut pattern = String::new();
+            for (i, c) in term.chars().enumerate() {
+                if i == any_chars_at {
+                    pattern.push('*');
+                    break;
+                } else if i == any_char_at {
+                    pattern.push('?');
+                } else {
+                    pattern.push(c);
+                }
+            }
+            Box::new(WildcardQuery::new(field_name, pattern, analyzer.clone()).unwrap())
+                as Box<dyn Query>
+        })
+        .boxed()
+}
+
+pub fn regex_query(
+    docs: &[&Doc],
+    field_name: &'static str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    let terms = || arb_term(docs, field_name, analyzer.clone());
+    let prefix_patterns = (terms(), 0usize..4).prop_map(|(term, len)| {
+        let prefix: String = term.chars().take(len).collect();
+        format!("{}.*", escape_regex(&prefix))
+    });
+    let alternations = (terms(), terms()).prop_map(|(first, second)| {
+        format!("({}|{})", escape_regex(&first), escape_regex(&second))
+    });
+    prop_oneof![prefix_patterns, alternations]
+        .prop_map(move |pattern| {
+            Box::new(RegexQuery::new(field_name, pattern, analyzer.clone()).unwrap())
+                as Box<dyn Query>
+        })
+        .boxed()
+}
+
+pub fn fuzzy_query(
+    docs: &[&Doc],
+    field_name: &'static str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    (
+        arb_term(docs, field_name, analyzer.clone()),
+        vec((0usize..10, 0usize..3), 0..3),
+        0u32..3,
+        0usize..3,
+    )
+        .prop_map(move |(term, edits, max_edits, prefix_length)| {
+            let mut chars: Vec<char> = term.chars().collect();
+            for (at, kind) in edits {
+                let at = at.min(chars.len());
+                match kind {
+                    0 => chars.insert(at, 'x'),
+                    1 if at < chars.len() => {
+                        chars.remove(at);
+                    }
+                    _ if at < chars.len() => chars[at] = 'y',
+                    _ => (),
+                }
+            }
+            let fuzzy_term: String = chars.into_iter().collect();
+            Box::new(
+                FuzzyQuery::new(field_name, fuzzy_term, max_edits, analyzer.clone())
+                    .with_prefix_length(prefix_length),
+            ) as Box<dyn Query>
+        })
+        .boxed()
+}
+
+pub fn term_range_query(
+    docs: &[&Doc],
+    field_name: &'static str,
+    analyzer: Box<dyn Analyzer>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    //Bounds are terms, or prefixes of terms, so that they are sometimes between terms
+    let bound = (
+        arb_term(docs, field_name, analyzer.clone()),
+        0usize..6,
+        0u8..3,
+    )
+        .prop_map(|(term, len, kind)| {
+            let term: String = term.chars().take(len).collect();
+            match kind {
+                0 => Bound::Included(term),
+                1 => Bound::Excluded(term),
+                _ => Bound::Unbounded,
+            }
+        });
+    (bound.clone(), bound)
+        .prop_map(move |(lower, upper)| {
+            Box::new(TermRangeQuery::new(
+                field_name,
+                lower,
+                upper,
+                analyzer.clone(),
+            )) as Box<dyn Query>
+        })
+        .boxed()
+}
+
+/// Range queries with bounds around `values`, in steps of a half so that both integer and float
+/// bounds can be equal to values of both types.
+pub fn range_query(
+    field_name: &'static str,
+    values: ::std::ops::Range<i64>,
+) -> BoxedStrategy<Box<dyn Query>> {
+    let bound = (0u8..3, (values.start - 1) * 2..(values.end + 1) * 2).prop_map(
+        |(kind, halves)| match kind {
+            0 => Bound::Included(halves),
+            1 => Bound::Excluded(halves),
+            _ => Bound::Unbounded,
+        },
+    );
+    (bound.clone(), bound, proptest::bool::ANY)
+        .prop_map(move |(lower, upper, float)| {
+            let query = if float {
+                let to_float = |bound| match bound {
+                    Bound::Included(halves) => Bound::Included(halves as f64 / 2.0),
+                    Bound::Excluded(halves) => Bound::Excluded(halves as f64 / 2.0),
+                    Bound::Unbounded => Bound::Unbounded,
+                };
+                RangeQuery::float(field_name, to_float(lower), to_float(upper)).unwrap()
+            } else {
+                let to_int = |bound| match bound {
+                    Bound::Included(halves) => Bound::Included(halves / 2),
+                    Bound::Excluded(halves) => Bound::Excluded(halves / 2),
+                    Bound::Unbounded => Bound::Unbounded,
+                };
+                RangeQuery::int(field_name, to_int(lower), to_int(upper))
+            };
+            Box::new(query) as Box<dyn Query>
+        })
+        .boxed()
+}
+
+fn arb_term(docs: &[&Doc], field_name: &str, analyzer: Box<dyn Analyzer>) -> BoxedStrategy<String> {
+    let terms = extract_doc_terms(docs, field_name, analyzer);
+    if terms.is_empty() {
+        prop_oneof!("foobar", "cat", "Anne")
+            .prop_map(|term| term.to_string())
+            .boxed()
+    } else {
+        (0..terms.len()).prop_map(move |i| terms[i].clone()).boxed()
+    }
+}
+
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
 fn extract_token_ngrams(
     docs: &[&Doc],
     field_name: &str,
@@ -142,13 +494,10 @@ fn extract_doc_terms(docs: &[&Doc], field_name: &str, analyzer: Box<dyn Analyzer
 fn extract_doc_values(docs: &[&Doc], field_name: &str) -> Vec<String> {
     let mut values: Vec<String> = Vec::new();
     for doc in docs {
-        match doc.get(field_name) {
-            Some(val) => match val {
-                FieldValue::String(str_val) => {
-                    values.push(str_val.clone());
-                }
-            },
-            None => (),
+        for val in field_values(doc, field_name) {
+            for v in val.values() {
+                values.push(v.to_text().into_owned());
+            }
         }
     }
     values
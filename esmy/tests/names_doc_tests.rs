@@ -9,16 +9,45 @@ extern crate serde;
 extern crate serde_json;
 extern crate tempfile;
 
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ops::Bound;
+
 use proptest::strategy::BoxedStrategy;
 use proptest::strategy::Strategy;
 use proptest::test_runner::Config;
 use proptest::test_runner::TestError;
 use proptest::test_runner::TestRunner;
 
+use esmy::aggregation::AggregationResult;
+use esmy::aggregation::HistogramAggregator;
+use esmy::aggregation::HistogramBucket;
+use esmy::aggregation::HistogramResult;
+use esmy::aggregation::RangeAggregator;
+use esmy::aggregation::RangeBucket;
+use esmy::aggregation::RangeResult;
+use esmy::aggregation::StatsAggregator;
+use esmy::aggregation::StatsResult;
+use esmy::aggregation::TermsAggregator;
+use esmy::aggregation::TermsBucket;
+use esmy::aggregation::TermsResult;
+use esmy::analyzis::CustomAnalyzer;
 use esmy::analyzis::NoopAnalyzer;
 use esmy::analyzis::UAX29Analyzer;
+use esmy::doc::field_values;
 use esmy::doc::Doc;
+use esmy::doc::DocDecorator;
+use esmy::doc::FieldValue;
+use esmy::numeric_doc_values::NumericType;
+use esmy::search::Query;
+use esmy::search::RangeQuery;
+use esmy::search::SortField;
+use esmy::search::TextQuery;
 use esmy::seg::SegmentSchemaBuilder;
+use esmy::stemming::Language;
 
 pub mod esmy_test;
 
@@ -104,6 +133,344 @@ fn all_query_name_matching() {
         .unwrap();
 }
 
+#[test]
+fn boolean_query_name_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_name_doc(), 0..20, {
+        |docs| esmy_test::query_gen::boolean_queries(docs, "name", Box::from(UAX29Analyzer::new()))
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn prefix_query_name_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_name_doc(), 0..20, {
+        |docs| esmy_test::query_gen::prefix_query(docs, "name", Box::from(UAX29Analyzer::new()))
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn wildcard_query_name_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_name_doc(), 0..20, {
+        |docs| esmy_test::query_gen::wildcard_query(docs, "name", Box::from(UAX29Analyzer::new()))
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_pos_index("string_pos_index", "name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn regex_query_name_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_name_doc(), 0..20, {
+        |docs| esmy_test::query_gen::regex_query(docs, "name", Box::from(UAX29Analyzer::new()))
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn sloppy_text_query_name_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_name_doc(), 0..20, {
+        |docs| esmy_test::query_gen::sloppy_text_query(docs, "name", Box::from(UAX29Analyzer::new()))
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_pos_index("string_pos_index", "name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn sloppy_text_query_stop_words_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let analyzer = || CustomAnalyzer::without_stop_words(Language::English).boxed();
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_stop_word_doc(), 0..20, {
+        move |docs| esmy_test::query_gen::sloppy_phrase_query(docs, "name", analyzer())
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_pos_index("string_pos_index", "name", analyzer())
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn sloppy_text_query_keeps_stop_word_gaps() {
+    let analyzer = || CustomAnalyzer::without_stop_words(Language::English).boxed();
+    let docs = vec![
+        Doc::new().string_field("name", "tower london"),
+        Doc::new().string_field("name", "tower of london"),
+        Doc::new().string_field("name", "tower of old london"),
+    ];
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_pos_index("string_pos_index", "name", analyzer())
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    let ops = vec![
+        esmy_test::IndexOperation::Index(docs.clone()),
+        esmy_test::IndexOperation::Commit,
+    ];
+    for slop in 0..3 {
+        let query = TextQuery::new("name", "tower of london", analyzer()).with_slop(slop);
+        let matching: Vec<bool> = docs.iter().map(|doc| query.matches(doc)).collect();
+        //The removed "of" must be in between, so "tower london" never matches
+        assert_eq!(vec![false, true, slop > 0], matching, "slop {}", slop);
+        esmy_test::index_and_assert_search_matches(&schema, &ops, &[Box::new(query)]);
+    }
+}
+
+#[test]
+fn span_near_query_name_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_name_doc(), 0..20, {
+        |docs| esmy_test::query_gen::span_near_query(docs, "name", Box::from(UAX29Analyzer::new()))
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_pos_index("string_pos_index", "name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn term_range_query_name_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_name_doc(), 0..20, {
+        |docs| esmy_test::query_gen::term_range_query(docs, "name", Box::from(UAX29Analyzer::new()))
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn term_range_query_name_matching_pos_index() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_name_doc(), 0..20, {
+        |docs| esmy_test::query_gen::term_range_query(docs, "name", Box::from(UAX29Analyzer::new()))
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_pos_index("string_pos_index", "name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn fuzzy_query_name_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_name_doc(), 0..20, {
+        |docs| esmy_test::query_gen::fuzzy_query(docs, "name", Box::from(UAX29Analyzer::new()))
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn numeric_field_value_query_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..20, arb_name_age_doc(), 0..10, {
+        |docs| esmy_test::query_gen::value_query(docs, "age")
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "age", Box::from(NoopAnalyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn range_query_age_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..20, arb_name_age_doc(), 0..10, {
+        |_docs| esmy_test::query_gen::range_query("age", 0..10)
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_numeric_points("age_int_points", "age", NumericType::Int)
+        .add_numeric_points("age_float_points", "age", NumericType::Float)
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn range_query_refuses_nan_bounds() {
+    assert!(RangeQuery::float("age", Bound::Included(f64::NAN), Bound::Unbounded).is_err());
+    assert!(RangeQuery::float("age", Bound::Unbounded, Bound::Excluded(f64::NAN)).is_err());
+    assert!(RangeQuery::float("age", Bound::Included(-0.0), Bound::Included(0.0)).is_ok());
+}
+
+#[test]
+fn array_field_text_query_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..20, arb_tags_doc(), 0..10, {
+        |docs| {
+            prop_oneof![
+                esmy_test::query_gen::sloppy_text_query(docs, "tags", Box::from(UAX29Analyzer::new())),
+                esmy_test::query_gen::text_query_across_values(
+                    docs,
+                    "tags",
+                    Box::from(UAX29Analyzer::new())
+                ),
+            ]
+            .boxed()
+        }
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_pos_index("string_pos_index", "tags", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn array_field_value_query_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..20, arb_tags_doc(), 0..10, {
+        |docs| esmy_test::query_gen::value_query(docs, "tags")
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "tags", Box::from(NoopAnalyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn nested_field_text_query_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..20, arb_authors_doc(), 0..10, {
+        |docs| {
+            esmy_test::query_gen::sloppy_text_query(
+                docs,
+                "authors.name",
+                Box::from(UAX29Analyzer::new()),
+            )
+        }
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_pos_index("string_pos_index", "authors.name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn nested_field_value_query_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..20, arb_authors_doc(), 0..10, {
+        |docs| esmy_test::query_gen::value_query(docs, "editor.name")
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "editor.name", Box::from(NoopAnalyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_search_matches(&schema, &ops, &queries);
+            Ok(())
+        })
+        .unwrap();
+}
+
 #[test]
 fn all_docs_many_docs_matching() {
     let mut runner = TestRunner::new(Config::with_cases(10));
@@ -139,12 +506,312 @@ fn text_query_name_matching_pos_index() {
         .unwrap();
 }
 
+#[test]
+fn top_docs_text_query_name_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_name_doc(), 0..10, {
+        |docs| esmy_test::query_gen::text_query(docs, "name", Box::from(UAX29Analyzer::new()), 1)
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "name", Box::from(UAX29Analyzer {}))
+        .add_field_norms("field_norms", "name", Box::from(UAX29Analyzer {}))
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_top_docs(&schema, &ops, &queries, 5);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn sorted_text_query_name_matching() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..100, arb_optional_age_doc(), 0..10, {
+        |docs| esmy_test::query_gen::text_query(docs, "name", Box::from(UAX29Analyzer::new()), 1)
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "name", Box::from(UAX29Analyzer {}))
+        .add_numeric_doc_values("age_values", "age", NumericType::Float)
+        .add_sorted_doc_values("name_values", "name")
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    let sort = vec![SortField::desc("age"), SortField::asc("name")];
+    //Ages sort descending with missing ages last, then names ascending
+    let key = |doc: &Doc| {
+        let age = doc.get("age").and_then(|age| NumericType::Float.encode(age));
+        let name = doc.get("name").map(|name| name.to_text().into_owned());
+        (age.is_none(), Reverse(age), name.is_none(), name)
+    };
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_sorted(&schema, &ops, &queries, 5, &sort, key);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn terms_aggregation_tags() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..20, arb_tags_doc(), 0..10, {
+        |docs| {
+            prop_oneof![
+                esmy_test::query_gen::match_all_docs(),
+                esmy_test::query_gen::text_query(docs, "tags", Box::from(UAX29Analyzer::new()), 1),
+            ]
+            .boxed()
+        }
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "tags", Box::from(UAX29Analyzer {}))
+        .add_sorted_doc_values("tags_values", "tags")
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_aggregation(
+                &schema,
+                &ops,
+                &queries,
+                || Box::new(TermsAggregator::new("tags", 3)),
+                |docs| expected_terms(docs, "tags", 3),
+            );
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[test]
+fn numeric_aggregations_age() {
+    let mut runner = TestRunner::new(Config::with_cases(1000));
+    let ops_and_queries = esmy_test::do_gen(10, 0..20, arb_optional_age_doc(), 0..10, {
+        |docs| {
+            prop_oneof![
+                esmy_test::query_gen::match_all_docs(),
+                esmy_test::query_gen::text_query(docs, "name", Box::from(UAX29Analyzer::new()), 1),
+            ]
+            .boxed()
+        }
+    });
+    let schema = SegmentSchemaBuilder::new()
+        .add_string_index("string_index", "name", Box::from(UAX29Analyzer {}))
+        .add_numeric_doc_values("age_values", "age", NumericType::Float)
+        .add_sorted_doc_values("name_values", "name")
+        .add_full_doc_with_compression("full_doc", 0)
+        .build();
+    let ranges = [(None, Some(2.0)), (Some(1.5), Some(6.0)), (Some(6.0), None)];
+    runner
+        .run(&ops_and_queries, |(ops, queries)| {
+            esmy_test::index_and_assert_aggregation(
+                &schema,
+                &ops,
+                &queries,
+                || {
+                    Box::new(
+                        HistogramAggregator::new("age", 2.0)
+                            .with_sub(Box::new(StatsAggregator::new("age"))),
+                    )
+                },
+                |docs| expected_histogram(docs, "age", 2.0),
+            );
+            esmy_test::index_and_assert_aggregation(
+                &schema,
+                &ops,
+                &queries,
+                || {
+                    let mut aggregator = RangeAggregator::new("age");
+                    for &(from, to) in ranges.iter() {
+                        aggregator = aggregator.add_range(from, to);
+                    }
+                    Box::new(aggregator.with_sub(Box::new(TermsAggregator::new("name", 2))))
+                },
+                |docs| expected_ranges(docs, "age", &ranges),
+            );
+            Ok(())
+        })
+        .unwrap();
+}
+
+fn numeric_values(doc: &Doc, field: &str) -> Vec<f64> {
+    field_values(doc, field)
+        .iter()
+        .flat_map(|value| value.values())
+        .filter_map(|value| value.as_f64())
+        .collect()
+}
+
+fn expected_stats(docs: &[&Doc], field: &str) -> AggregationResult {
+    let values: Vec<f64> = docs
+        .iter()
+        .flat_map(|doc| numeric_values(doc, field))
+        .collect();
+    let sum: f64 = values.iter().sum();
+    AggregationResult::Stats(StatsResult {
+        count: values.len() as u64,
+        min: values
+            .iter()
+            .cloned()
+            .fold(None, |min, v| Some(v.min(min.unwrap_or(v)))),
+        max: values
+            .iter()
+            .cloned()
+            .fold(None, |max, v| Some(v.max(max.unwrap_or(v)))),
+        sum,
+        avg: if values.is_empty() {
+            None
+        } else {
+            Some(sum / values.len() as f64)
+        },
+    })
+}
+
+fn expected_histogram(docs: &[&Doc], field: &str, interval: f64) -> AggregationResult {
+    let mut buckets: BTreeMap<i64, Vec<&Doc>> = BTreeMap::new();
+    for doc in docs {
+        let keys: BTreeSet<i64> = numeric_values(doc, field)
+            .iter()
+            .map(|value| (value / interval).floor() as i64)
+            .collect();
+        for key in keys {
+            buckets.entry(key).or_default().push(doc);
+        }
+    }
+    AggregationResult::Histogram(HistogramResult {
+        buckets: buckets
+            .into_iter()
+            .map(|(key, docs)| HistogramBucket {
+                key: key as f64 * interval,
+                count: docs.len() as u64,
+                sub: Some(Box::new(expected_stats(&docs, field))),
+            })
+            .collect(),
+    })
+}
+
+fn expected_ranges(
+    docs: &[&Doc],
+    field: &str,
+    ranges: &[(Option<f64>, Option<f64>)],
+) -> AggregationResult {
+    AggregationResult::Range(RangeResult {
+        buckets: ranges
+            .iter()
+            .map(|&(from, to)| {
+                let in_range: Vec<&Doc> = docs
+                    .iter()
+                    .cloned()
+                    .filter(|doc| {
+                        numeric_values(doc, field).iter().any(|&value| {
+                            from.map_or(true, |from| value >= from)
+                                && to.map_or(true, |to| value < to)
+                        })
+                    })
+                    .collect();
+                RangeBucket {
+                    from,
+                    to,
+                    count: in_range.len() as u64,
+                    sub: Some(Box::new(expected_terms(&in_range, "name", 2))),
+                }
+            })
+            .collect(),
+    })
+}
+
+fn expected_terms(docs: &[&Doc], field: &str, size: usize) -> AggregationResult {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for doc in docs {
+        let values: HashSet<String> = field_values(doc, field)
+            .iter()
+            .flat_map(|value| value.values())
+            .map(|value| value.to_text().into_owned())
+            .collect();
+        for value in values {
+            *counts.entry(value).or_insert(0) += 1;
+        }
+    }
+    let mut buckets: Vec<TermsBucket> = counts
+        .into_iter()
+        .map(|(key, count)| TermsBucket {
+            key,
+            count,
+            sub: None,
+        })
+        .collect();
+    buckets.sort_by_key(|bucket| (Reverse(bucket.count), bucket.key.clone()));
+    let other_count = buckets.iter().skip(size).map(|bucket| bucket.count).sum();
+    buckets.truncate(size);
+    AggregationResult::Terms(TermsResult {
+        buckets,
+        other_count,
+    })
+}
+
 fn arb_name_doc() -> BoxedStrategy<Doc> {
     (0..NAME_DOCS.len())
         .prop_map(|i| NAME_DOCS[i].clone())
         .boxed()
 }
 
+fn arb_name_age_doc() -> BoxedStrategy<Doc> {
+    (arb_name_doc(), 0i64..10, proptest::bool::ANY)
+        .prop_map(|(doc, age, has_float_age)| {
+            if has_float_age {
+                doc.float_field("age", age as f64 + 0.5)
+            } else {
+                doc.int_field("age", age)
+            }
+        })
+        .boxed()
+}
+
+fn arb_optional_age_doc() -> BoxedStrategy<Doc> {
+    (arb_name_age_doc(), proptest::bool::ANY)
+        .prop_map(|(mut doc, has_age)| {
+            if !has_age {
+                doc.remove("age");
+            }
+            doc
+        })
+        .boxed()
+}
+
+/// Names made up of a few words, many of which are English stop words.
+fn arb_stop_word_doc() -> BoxedStrategy<Doc> {
+    const WORDS: &[&str] = &["the", "tower", "of", "london", "and", "a", "bridge", "old"];
+    proptest::collection::vec(0..WORDS.len(), 1..8)
+        .prop_map(|indices| {
+            let words: Vec<&str> = indices.into_iter().map(|i| WORDS[i]).collect();
+            Doc::new().string_field("name", words.join(" "))
+        })
+        .boxed()
+}
+
+fn arb_tags_doc() -> BoxedStrategy<Doc> {
+    proptest::collection::vec(0..NAME_DOCS.len(), 0..4)
+        .prop_map(|indices| {
+            let tags = indices
+                .into_iter()
+                .filter_map(|i| NAME_DOCS[i].get("name").cloned())
+                .collect();
+            Doc::new().array_field("tags", tags)
+        })
+        .boxed()
+}
+
+fn arb_authors_doc() -> BoxedStrategy<Doc> {
+    (arb_name_doc(), proptest::collection::vec(arb_name_doc(), 0..4))
+        .prop_map(|(editor, authors)| {
+            let authors = authors.into_iter().map(FieldValue::Object).collect();
+            Doc::new()
+                .object_field("editor", editor)
+                .array_field("authors", authors)
+        })
+        .boxed()
+}
+
 static COMPRESSED_JSON_NAME_DOCS: &[u8] = include_bytes!("../../data/1k_names.json.gz");
 lazy_static! {
     static ref NAME_DOCS: Vec<Doc> = {
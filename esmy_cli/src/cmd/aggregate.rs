@@ -0,0 +1,156 @@
+use std::io;
+use std::path::PathBuf;
+
+use docopt;
+use docopt::Docopt;
+use serde_json;
+
+use esmy::aggregation::AggregationCollector;
+use esmy::aggregation::Aggregator;
+use esmy::aggregation::HistogramAggregator;
+use esmy::aggregation::RangeAggregator;
+use esmy::aggregation::StatsAggregator;
+use esmy::aggregation::TermsAggregator;
+use esmy::analyzis::Analyzer;
+use esmy::analyzis::AnalyzerRegistry;
+use esmy::index::IndexBuilder;
+use esmy::search;
+use esmy::search::TextQuery;
+use esmy::Error;
+
+static USAGE: &'static str = concat!(
+    "
+Aggregates the docs matching a query in an esmy index, and prints the result as JSON.
+
+Usage:
+    esmy aggregate <query> <aggregation> [options]
+    esmy aggregate --help
+
+Aggregations:
+    terms:<field>:<size>
+    histogram:<field>:<interval>
+    range:<field>:<from>..<to>,...      A bound can be left out for an open range
+    stats:<field>
+",
+    analyzers_usage!(),
+    "
+Options:
+    -p, --path <path>           Path to index to
+    -a, --analyzer <analyzer>   Analyzer to use for query [default: uax29]
+    -s, --sub <aggregation>     Aggregation to run on the docs of each bucket
+    -h, --help                  Show this message
+"
+);
+
+#[derive(Deserialize)]
+struct Args {
+    arg_query: String,
+    arg_aggregation: String,
+    flag_path: String,
+    flag_analyzer: String,
+    flag_sub: Option<String>,
+}
+
+pub fn run(argv: &[&str]) -> Result<(), Error> {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.argv(argv.iter().map(|&x| x)).deserialize())
+        .unwrap_or_else(|e| e.exit());
+    let index_path = PathBuf::from(args.flag_path.clone());
+    let analyzer = AnalyzerRegistry::new().create(&args.flag_analyzer)?;
+    let query = parse_query(&args.arg_query, analyzer).unwrap_or_else(|e| e.exit());
+    let sub = args
+        .flag_sub
+        .map(|sub| parse_aggregation(&sub, None).unwrap_or_else(|e| e.exit()));
+    let aggregator = parse_aggregation(&args.arg_aggregation, sub).unwrap_or_else(|e| e.exit());
+
+    let index_manager = IndexBuilder::new().open(index_path)?;
+    let index_reader = index_manager.open_reader()?;
+    let mut collector = AggregationCollector::new().add("aggregation", aggregator);
+    search::search(&index_reader, &query, &mut collector)?;
+    serde_json::to_writer(io::stdout(), &collector.result("aggregation"))
+        .map_err(|e| Error::Other(Box::new(e)))?;
+    println!();
+    Ok(())
+}
+
+fn parse_query(
+    query_string: &str,
+    analyzer: Box<dyn Analyzer>,
+) -> Result<TextQuery, docopt::Error> {
+    match query_string.find(':') {
+        Some(index) => Ok(TextQuery::new(
+            query_string[..index].to_string(),
+            query_string[index + 1..].to_string(),
+            analyzer,
+        )),
+        None => Err(docopt::Error::Argv(format!(
+            "Invalid query, expected <field>:<value>: {}",
+            query_string
+        ))),
+    }
+}
+
+fn parse_aggregation(
+    spec: &str,
+    sub: Option<Box<dyn Aggregator>>,
+) -> Result<Box<dyn Aggregator>, docopt::Error> {
+    let split: Vec<&str> = spec.split(':').collect();
+    let aggregator: Box<dyn Aggregator> = match (split[0], split.len()) {
+        ("terms", 3) => {
+            let aggregator = TermsAggregator::new(split[1], parse_number(split[2])?);
+            match sub {
+                Some(sub) => Box::new(aggregator.with_sub(sub)),
+                None => Box::new(aggregator),
+            }
+        }
+        ("histogram", 3) => {
+            let interval: f64 = parse_number(split[2])?;
+            if !(interval.is_finite() && interval > 0.0) {
+                return Err(invalid(spec));
+            }
+            let aggregator = HistogramAggregator::new(split[1], interval);
+            match sub {
+                Some(sub) => Box::new(aggregator.with_sub(sub)),
+                None => Box::new(aggregator),
+            }
+        }
+        ("range", 3) => {
+            let mut aggregator = RangeAggregator::new(split[1]);
+            for range in split[2].split(',') {
+                let bounds: Vec<&str> = range.split("..").collect();
+                if bounds.len() != 2 {
+                    return Err(invalid(range));
+                }
+                aggregator = aggregator.add_range(parse_bound(bounds[0])?, parse_bound(bounds[1])?);
+            }
+            match sub {
+                Some(sub) => Box::new(aggregator.with_sub(sub)),
+                None => Box::new(aggregator),
+            }
+        }
+        ("stats", 2) => {
+            if sub.is_some() {
+                return Err(invalid("stats can not have a sub aggregation"));
+            }
+            Box::new(StatsAggregator::new(split[1]))
+        }
+        _ => return Err(invalid(spec)),
+    };
+    Ok(aggregator)
+}
+
+fn parse_bound(bound: &str) -> Result<Option<f64>, docopt::Error> {
+    if bound.is_empty() {
+        Ok(None)
+    } else {
+        parse_number(bound).map(Some)
+    }
+}
+
+fn parse_number<T: ::std::str::FromStr>(number: &str) -> Result<T, docopt::Error> {
+    number.parse().map_err(|_| invalid(number))
+}
+
+fn invalid(spec: &str) -> docopt::Error {
+    docopt::Error::Argv(format!("Invalid aggregation: {}", spec))
+}
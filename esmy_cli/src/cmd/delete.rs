@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use docopt::Docopt;
 
 use esmy::analyzis::Analyzer;
+use esmy::analyzis::AnalyzerRegistry;
 use esmy::index::IndexBuilder;
 use esmy::search::TextQuery;
 use esmy::Error;
@@ -14,7 +15,9 @@ Searches and prints all results from an esmy index.
 Usage:
     esmy delete <query> [options]
     esmy delete --help
-
+",
+    analyzers_usage!(),
+    "
 Options::
     -p, --path <path>           Path to index to
     -a, --analyzer <analyzer>   Analyzer to use for query
@@ -34,7 +37,7 @@ pub fn run(argv: &[&str]) -> Result<(), Error> {
         .and_then(|d| d.argv(argv.iter().map(|&x| x)).deserialize())
         .unwrap_or_else(|e| e.exit());
     let index_path = PathBuf::from(args.flag_path.clone());
-    let analyzer = <dyn Analyzer>::for_name(&args.flag_analyzer.unwrap());
+    let analyzer = AnalyzerRegistry::new().create(&args.flag_analyzer.unwrap())?;
     let query_string = args.arg_query;
     let query = parse_query(&query_string, analyzer);
 
@@ -5,6 +5,7 @@ use docopt::Docopt;
 use serde_json;
 
 use esmy::analyzis::Analyzer;
+use esmy::analyzis::AnalyzerRegistry;
 use esmy::doc_iter::DocIter;
 use esmy::index::IndexBuilder;
 use esmy::search;
@@ -20,10 +21,12 @@ Searches and prints all results from an esmy index.
 Usage:
     esmy list <query> [options]
     esmy list --help
-
+",
+    analyzers_usage!(),
+    "
 Options::
     -p, --path <path>           Path to index to
-    -a, --analyzer <analyzer>   Path to index to
+    -a, --analyzer <analyzer>   Analyzer to use for query
     -h, --help                  Show this message
 "
 );
@@ -40,7 +43,7 @@ pub fn run(argv: &[&str]) -> Result<(), Error> {
         .and_then(|d| d.argv(argv.iter().map(|&x| x)).deserialize())
         .unwrap_or_else(|e| e.exit());
     let index_path = PathBuf::from(args.flag_path.clone());
-    let analyzer = <dyn Analyzer>::for_name(&args.flag_analyzer.unwrap());
+    let analyzer = AnalyzerRegistry::new().create(&args.flag_analyzer.unwrap())?;
     let query_string = args.arg_query;
     let query = parse_query(&query_string, analyzer);
 
@@ -1,3 +1,16 @@
+/// The analyzers that the commands taking a query can analyze it with, for their usage.
+macro_rules! analyzers_usage {
+    () => {
+        "
+Analyzers:
+    uax29, whitespace, noop
+    english, german, swedish                    Lowercases and stems the words
+    stop_english, stop_german, stop_swedish     Lowercases and removes the stop words, without stemming
+"
+    };
+}
+
+pub mod aggregate;
 pub mod delete;
 pub mod force_merge;
 pub mod index;
@@ -23,6 +23,7 @@ Options:
 
 Commands: 
     index               Indexes content
+    aggregate           Aggregates content matching a query
     list                Lists content matching a query
     write-template      Writes template to index
     read-template       Reads template from path 
@@ -51,6 +52,7 @@ fn main() {
 #[serde(rename_all = "kebab-case")]
 enum Command {
     Index,
+    Aggregate,
     List,
     Delete,
     ForceMerge,
@@ -65,6 +67,7 @@ impl Command {
         let argv = &*argv;
         match self {
             Command::Index => cmd::index::run(argv),
+            Command::Aggregate => cmd::aggregate::run(argv),
             Command::List => cmd::list::run(argv),
             Command::Delete => cmd::delete::run(argv),
             Command::ForceMerge => cmd::force_merge::run(argv),